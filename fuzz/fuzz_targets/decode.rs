@@ -0,0 +1,36 @@
+//! Every decode path must reject arbitrary bytes without panicking.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use tritiumcan::{
+    codec::Codec,
+    datagram::{
+        FdFrame, Frame, FrameIter, FrameRef, Header, Packet, FD_FRAME_LEN,
+    },
+};
+use zerocopy::FromBytes;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = Header::parse(data);
+    let _ = Codec::decode_header(data);
+    let _ = Packet::parse_heartbeat(data);
+    let _ = Packet::parse_settings(data);
+
+    if let Ok((Some(frame), _)) = Codec::decode(data) {
+        let _ = (frame.id(), frame.data());
+    }
+
+    if let Some(frame) = FrameRef::from_bytes(data) {
+        let _ = (frame.id(), frame.dlc(), frame.data());
+        let _ = frame.frame().to_frame::<Frame>();
+    }
+
+    for frame in FrameIter::new(data) {
+        let _ = (frame.id(), frame.data(), frame.to_frame::<Frame>());
+    }
+
+    if let Some(fd) = data.get(..FD_FRAME_LEN).and_then(FdFrame::read_from) {
+        let _ = (fd.id(), fd.data());
+    }
+});
@@ -0,0 +1,111 @@
+//! [`embedded_can::nb::Can`] over a TCP connection.
+
+use crate::{error::Error, tcp::Server};
+use smoltcp::iface::SocketSet;
+use tritiumcan::datagram::Frame;
+
+/// A [`Server`] and its [`SocketSet`] seen as a non-blocking CAN
+/// peripheral.
+///
+/// Borrows both, so create one around code written against
+/// [`embedded_can::nb::Can`] and drop it to poll the interface.
+///
+/// ```rust,ignore
+/// let mut can = CanAdapter::new(&mut server, &mut sockets);
+/// nb::block!(can.transmit(&frame))?;
+/// ```
+#[derive(Debug)]
+pub struct CanAdapter<'r, 'a> {
+    server: &'r mut Server,
+    sockets: &'r mut SocketSet<'a>,
+}
+
+impl<'r, 'a> CanAdapter<'r, 'a> {
+    pub fn new(server: &'r mut Server, sockets: &'r mut SocketSet<'a>) -> Self {
+        Self { server, sockets }
+    }
+
+    /// The wrapped server, for example to check [`Server::stats`].
+    pub fn server(&mut self) -> &mut Server {
+        self.server
+    }
+}
+
+impl embedded_can::nb::Can for CanAdapter<'_, '_> {
+    type Frame = Frame;
+    type Error = Error;
+
+    /// Send `frame` with [`Server::send_frame`], blocking while the pending
+    /// queue is full.
+    ///
+    /// Frames are never swapped out, so this never returns a frame.
+    fn transmit(
+        &mut self,
+        frame: &Self::Frame,
+    ) -> nb::Result<Option<Self::Frame>, Self::Error> {
+        match self.server.send_frame(self.sockets, frame) {
+            Ok(()) => Ok(None),
+            Err(Error::QueueFull | Error::WouldBlock) => {
+                Err(nb::Error::WouldBlock)
+            }
+            Err(err) => Err(nb::Error::Other(err)),
+        }
+    }
+
+    /// Receive a frame with [`Server::recv_frame`], blocking until a whole
+    /// frame has arrived.
+    fn receive(&mut self) -> nb::Result<Self::Frame, Self::Error> {
+        match self.server.recv_frame(self.sockets) {
+            Ok(Some(frame)) => Ok(frame),
+            Ok(None) => Err(nb::Error::WouldBlock),
+            Err(err) => Err(nb::Error::Other(err)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harness::Harness;
+    use embedded_can::{nb::Can, Frame as CanFrame, StandardId};
+    use tritiumcan::{
+        codec::HEADER_LEN,
+        datagram::{Header, FRAME_LEN},
+        PROTOCOL_VERSION,
+    };
+    use zerocopy::AsBytes;
+
+    #[test]
+    fn transmit_and_receive() {
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 1024);
+        let client = harness.connect(&mut server);
+
+        let frame = |id| -> Frame {
+            CanFrame::new(StandardId::new(id).unwrap(), &[1, 2, 3]).unwrap()
+        };
+
+        // nothing to receive until the peer sends
+        let mut can = CanAdapter::new(&mut server, &mut harness.sockets);
+        assert!(matches!(can.receive(), Err(nb::Error::WouldBlock)));
+
+        let socket = harness.tcp_socket(client);
+        let header = Header::with(PROTOCOL_VERSION, 13, 0x1234);
+        socket.send_slice(header.as_bytes()).unwrap();
+        socket.send_slice(frame(0x456).as_bytes()).unwrap();
+        harness.run(10, |sockets, now| server.poll(sockets, now));
+
+        let mut can = CanAdapter::new(&mut server, &mut harness.sockets);
+        assert_eq!(can.transmit(&frame(0x123)), Ok(None));
+        assert_eq!(can.receive(), Ok(frame(0x456)));
+        assert!(matches!(can.receive(), Err(nb::Error::WouldBlock)));
+
+        harness.run(10, |sockets, now| server.poll(sockets, now));
+        let mut buf = [0; 64];
+        let len = harness.tcp_socket(client).recv_slice(&mut buf).unwrap();
+        // the header packet then the frame
+        let packet_len = HEADER_LEN + FRAME_LEN;
+        assert_eq!(len, packet_len + FRAME_LEN);
+        assert_eq!(&buf[packet_len..len], frame(0x123).as_bytes());
+    }
+}
@@ -0,0 +1,68 @@
+//! Time sources for driving heartbeat timing.
+
+use core::cell::Cell;
+use smoltcp::time::{Duration, Instant};
+
+/// Source of the current time, see [`Server::poll_clock`].
+///
+/// [`Server::poll_clock`]: crate::tcp::Server::poll_clock
+pub trait Clock {
+    fn now(&self) -> Instant;
+}
+
+/// An [`Instant`] is a clock stopped at that time, so the time given to
+/// `poll` can be passed through unchanged.
+impl Clock for Instant {
+    fn now(&self) -> Instant {
+        *self
+    }
+}
+
+/// Clock that only moves when told to, for deterministic tests.
+#[derive(Debug)]
+pub struct MockClock {
+    now: Cell<Instant>,
+}
+
+impl MockClock {
+    /// Create a clock stopped at `now`.
+    pub fn new(now: Instant) -> Self {
+        Self {
+            now: Cell::new(now),
+        }
+    }
+
+    /// Move the clock to `now`.
+    pub fn set(&self, now: Instant) {
+        self.now.set(now);
+    }
+
+    /// Move the clock forward by `duration`.
+    pub fn advance(&self, duration: Duration) {
+        self.now.set(self.now.get() + duration);
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.now.get()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mock_clock() {
+        let clock = MockClock::new(Instant::from_millis(5));
+        assert_eq!(clock.now(), Instant::from_millis(5));
+
+        clock.advance(Duration::from_millis(10));
+        assert_eq!(clock.now(), Instant::from_millis(15));
+
+        clock.set(Instant::ZERO);
+        assert_eq!(clock.now(), Instant::ZERO);
+        assert_eq!(Instant::from_secs(1).now(), Instant::from_secs(1));
+    }
+}
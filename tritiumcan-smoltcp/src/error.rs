@@ -0,0 +1,192 @@
+//! Errors of the drivers, independent of the smoltcp version.
+
+use smoltcp::socket::{tcp, udp};
+
+/// Error sending or receiving on a TCP connection or UDP socket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub enum Error {
+    /// No peer is connected or the handshake hasn't completed.
+    NotConnected,
+    /// The send buffer still holds part of an earlier write, try again
+    /// after the next poll.
+    WouldBlock,
+    /// The peer's header is invalid, the connection has been aborted.
+    Malformed,
+    /// The frame to send has more than the 8 data bytes the protocol
+    /// carries, such as a CAN FD frame.
+    FrameTooLong,
+    /// The pending frame queue is full.
+    QueueFull,
+    /// The [`ClientId`](crate::tcp::ClientId) doesn't refer to a connected
+    /// client.
+    UnknownClient,
+    /// The connection underneath failed.
+    Transport(TransportError),
+}
+
+/// Failure of the connection underneath the protocol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub enum TransportError {
+    /// The peer closed the connection and everything it sent has been read.
+    Finished,
+}
+
+impl From<tcp::SendError> for Error {
+    fn from(err: tcp::SendError) -> Self {
+        match err {
+            tcp::SendError::InvalidState => Error::NotConnected,
+        }
+    }
+}
+
+impl From<tcp::RecvError> for Error {
+    fn from(err: tcp::RecvError) -> Self {
+        match err {
+            tcp::RecvError::InvalidState => Error::NotConnected,
+            tcp::RecvError::Finished => {
+                Error::Transport(TransportError::Finished)
+            }
+        }
+    }
+}
+
+impl From<udp::SendError> for Error {
+    fn from(err: udp::SendError) -> Self {
+        match err {
+            udp::SendError::Unaddressable => Error::NotConnected,
+            udp::SendError::BufferFull => Error::WouldBlock,
+        }
+    }
+}
+
+impl embedded_can::Error for Error {
+    fn kind(&self) -> embedded_can::ErrorKind {
+        embedded_can::ErrorKind::Other
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harness::{FdFrame, Harness, MAC_ADDR};
+    use crate::tcp::MultiServer;
+    use embedded_can::{Frame as CanFrame, StandardId};
+    use tritiumcan::{codec::HEADER_LEN, datagram::Frame, BusNumber};
+
+    fn frame(id: u16) -> Frame {
+        CanFrame::new(StandardId::new(id).unwrap(), &[1, 2]).unwrap()
+    }
+
+    #[test]
+    fn not_connected() {
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 1024);
+        harness.run(1, |sockets, now| server.poll(sockets, now));
+
+        assert_eq!(
+            server.send_frame(&mut harness.sockets, &frame(1)),
+            Err(Error::NotConnected)
+        );
+    }
+
+    #[test]
+    fn queue_full() {
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 64);
+        harness.connect(&mut server);
+
+        let err = (0..100).find_map(|id| {
+            server.send_frame(&mut harness.sockets, &frame(id)).err()
+        });
+        assert_eq!(err, Some(Error::QueueFull));
+    }
+
+    #[test]
+    fn would_block() {
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 64);
+        harness.connect(&mut server);
+
+        // the heartbeat that doesn't fit is kept to finish first
+        let err = (0..10)
+            .find_map(|_| server.send_heartbeat(&mut harness.sockets).err());
+        assert_eq!(err, Some(Error::WouldBlock));
+    }
+
+    #[test]
+    fn unknown_client() {
+        let mut harness = Harness::new();
+        let mut server = MultiServer::<1>::new(
+            &mut harness.sockets,
+            [(Harness::tcp_buffer(1024), Harness::tcp_buffer(1024))],
+            MAC_ADDR,
+            harness.now,
+            BusNumber::default(),
+            500,
+        );
+        let [(client, _)] = server.poll(&mut harness.sockets, harness.now);
+
+        assert_eq!(
+            server.send_frame_to(&mut harness.sockets, client, &frame(1)),
+            Err(Error::UnknownClient)
+        );
+    }
+
+    #[test]
+    fn frame_too_long() {
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 1024);
+        harness.connect(&mut server);
+
+        let fd = FdFrame::new(StandardId::new(1).unwrap(), &[0; 12]).unwrap();
+        assert_eq!(
+            server.send_frame(&mut harness.sockets, &fd),
+            Err(Error::FrameTooLong)
+        );
+        assert_eq!(
+            server.send_frame_as(&mut harness.sockets, 7, &fd),
+            Err(Error::FrameTooLong)
+        );
+    }
+
+    #[test]
+    fn malformed() {
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 1024);
+        let client = harness.connect(&mut server);
+
+        // a zeroed header has no valid version
+        harness
+            .tcp_socket(client)
+            .send_slice(&[0; HEADER_LEN])
+            .unwrap();
+        harness.run(10, |_, _| {});
+        assert_eq!(
+            server.recv_frame(&mut harness.sockets),
+            Err(Error::Malformed)
+        );
+    }
+
+    #[test]
+    fn from_smoltcp() {
+        assert_eq!(
+            Error::from(tcp::SendError::InvalidState),
+            Error::NotConnected
+        );
+        assert_eq!(
+            Error::from(tcp::RecvError::InvalidState),
+            Error::NotConnected
+        );
+        assert_eq!(
+            Error::from(tcp::RecvError::Finished),
+            Error::Transport(TransportError::Finished)
+        );
+        assert_eq!(
+            Error::from(udp::SendError::Unaddressable),
+            Error::NotConnected
+        );
+        assert_eq!(Error::from(udp::SendError::BufferFull), Error::WouldBlock);
+    }
+}
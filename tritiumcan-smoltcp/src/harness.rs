@@ -0,0 +1,459 @@
+//! Loopback network used to exercise the drivers in unit tests.
+
+use std::collections::VecDeque;
+
+use smoltcp::{
+    iface::{Config, Interface, SocketHandle, SocketSet},
+    phy::{Device, DeviceCapabilities, Loopback, Medium, TxToken},
+    socket::{tcp, udp},
+    time::{Duration, Instant},
+    wire::{
+        EthernetAddress, EthernetFrame, IpAddress, IpCidr, Ipv4Address,
+        Ipv4Packet, UdpPacket,
+    },
+};
+use tritiumcan::{BusNumber, PORT};
+
+use crate::{tcp::Server, transport::Transport};
+
+pub const MAC_ADDR: EthernetAddress =
+    EthernetAddress([0x02, 0x00, 0x00, 0x00, 0x00, 0x01]);
+
+pub const LOCALHOST: IpAddress = IpAddress::v4(127, 0, 0, 1);
+
+/// Unique local address, smoltcp won't pick `::1` as a source address.
+#[cfg(feature = "ipv6")]
+pub const LOCAL_V6: IpAddress = IpAddress::Ipv6(
+    smoltcp::wire::Ipv6Address::new(0xfd00, 0, 0, 0, 0, 0, 0, 1),
+);
+
+/// Time advanced by each call to [`Harness::step`].
+pub const STEP: Duration = Duration::from_millis(1);
+
+/// Loopback device keeping a copy of every transmitted packet.
+pub struct CaptureDevice {
+    inner: Loopback,
+    pub sent: VecDeque<Vec<u8>>,
+}
+
+pub struct CaptureTxToken<'a> {
+    inner: <Loopback as Device>::TxToken<'a>,
+    sent: &'a mut VecDeque<Vec<u8>>,
+}
+
+impl TxToken for CaptureTxToken<'_> {
+    fn consume<R, F>(self, len: usize, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        let sent = self.sent;
+        self.inner.consume(len, |buf| {
+            let result = f(buf);
+            sent.push_back(buf.to_vec());
+            result
+        })
+    }
+}
+
+impl Device for CaptureDevice {
+    type RxToken<'a> = <Loopback as Device>::RxToken<'a>;
+    type TxToken<'a> = CaptureTxToken<'a>;
+
+    fn receive(
+        &mut self,
+        timestamp: Instant,
+    ) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        let (rx, tx) = self.inner.receive(timestamp)?;
+        let tx = CaptureTxToken {
+            inner: tx,
+            sent: &mut self.sent,
+        };
+        Some((rx, tx))
+    }
+
+    fn transmit(&mut self, timestamp: Instant) -> Option<Self::TxToken<'_>> {
+        let tx = self.inner.transmit(timestamp)?;
+        Some(CaptureTxToken {
+            inner: tx,
+            sent: &mut self.sent,
+        })
+    }
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        self.inner.capabilities()
+    }
+}
+
+pub struct Harness {
+    pub device: CaptureDevice,
+    pub iface: Interface,
+    pub sockets: SocketSet<'static>,
+    pub now: Instant,
+}
+
+impl Harness {
+    pub fn new() -> Self {
+        let mut device = CaptureDevice {
+            inner: Loopback::new(Medium::Ethernet),
+            sent: VecDeque::new(),
+        };
+        let config = Config::new(MAC_ADDR.into());
+        let now = Instant::ZERO;
+        let mut iface = Interface::new(config, &mut device, now);
+        iface.update_ip_addrs(|addrs| {
+            addrs.push(IpCidr::new(LOCALHOST, 8)).unwrap();
+            #[cfg(feature = "ipv6")]
+            addrs.push(IpCidr::new(LOCAL_V6, 64)).unwrap();
+        });
+        // route the broadcast group back to ourselves
+        iface
+            .routes_mut()
+            .add_default_ipv4_route(Ipv4Address::new(127, 0, 0, 1))
+            .unwrap();
+
+        Self {
+            device,
+            iface,
+            sockets: SocketSet::new(vec![]),
+            now,
+        }
+    }
+
+    pub fn tcp_buffer(len: usize) -> tcp::SocketBuffer<'static> {
+        tcp::SocketBuffer::new(vec![0; len])
+    }
+
+    /// Add a TCP socket connecting to the driver's port.
+    pub fn tcp_client(&mut self) -> SocketHandle {
+        self.tcp_client_to(PORT, 49152)
+    }
+
+    /// Add a TCP socket connecting to `port` from `local_port`.
+    pub fn tcp_client_to(
+        &mut self,
+        port: u16,
+        local_port: u16,
+    ) -> SocketHandle {
+        let mut socket =
+            tcp::Socket::new(Self::tcp_buffer(1024), Self::tcp_buffer(1024));
+        socket
+            .connect(self.iface.context(), (LOCALHOST, port), local_port)
+            .unwrap();
+        self.sockets.add(socket)
+    }
+
+    /// Add a TCP server with `rx_len` and `tx_len` byte buffers, the default
+    /// bus number and a 500 kbps data rate.
+    pub fn tcp_server(&mut self, rx_len: usize, tx_len: usize) -> Server {
+        Server::new(
+            &mut self.sockets,
+            Self::tcp_buffer(rx_len),
+            Self::tcp_buffer(tx_len),
+            MAC_ADDR,
+            self.now,
+            BusNumber::default(),
+            500,
+        )
+    }
+
+    /// Connect a TCP client to `server`, polling it until the headers have
+    /// been exchanged.
+    pub fn connect(&mut self, server: &mut Server) -> SocketHandle {
+        self.run(1, |sockets, now| server.poll(sockets, now));
+        let client = self.tcp_client();
+        self.run(10, |sockets, now| server.poll(sockets, now));
+        client
+    }
+
+    pub fn udp_buffer(len: usize) -> udp::PacketBuffer<'static> {
+        udp::PacketBuffer::new(
+            vec![udp::PacketMetadata::EMPTY; len],
+            vec![0; len * 64],
+        )
+    }
+
+    pub fn tcp_socket(
+        &mut self,
+        handle: SocketHandle,
+    ) -> &mut tcp::Socket<'static> {
+        self.sockets.get_mut::<tcp::Socket>(handle)
+    }
+
+    /// Add a TCP socket listening on `port`.
+    pub fn tcp_listener(&mut self, port: u16) -> SocketHandle {
+        let mut socket =
+            tcp::Socket::new(Self::tcp_buffer(1024), Self::tcp_buffer(1024));
+        socket.listen(port).unwrap();
+        self.sockets.add(socket)
+    }
+
+    /// Run the interface and `driver` for `steps` iterations, advancing time
+    /// by [`STEP`] each iteration.
+    pub fn run<R>(
+        &mut self,
+        steps: usize,
+        mut driver: impl FnMut(&mut SocketSet<'static>, Instant) -> R,
+    ) {
+        self.run_iface(steps, |_, sockets, now| driver(sockets, now));
+    }
+
+    /// Like [`Harness::run`], also giving `driver` access to the interface.
+    pub fn run_iface<R>(
+        &mut self,
+        steps: usize,
+        mut driver: impl FnMut(
+            &mut Interface,
+            &mut SocketSet<'static>,
+            Instant,
+        ) -> R,
+    ) {
+        for _ in 0..steps {
+            driver(&mut self.iface, &mut self.sockets, self.now);
+            self.iface
+                .poll(self.now, &mut self.device, &mut self.sockets);
+            self.now += STEP;
+        }
+    }
+
+    /// Move the socket set out for the async APIs, which borrow it through a
+    /// [`RefCell`](core::cell::RefCell).
+    #[cfg(feature = "async")]
+    pub fn share_sockets(&mut self) -> core::cell::RefCell<SocketSet<'static>> {
+        core::cell::RefCell::new(core::mem::replace(
+            &mut self.sockets,
+            SocketSet::new(vec![]),
+        ))
+    }
+
+    /// Put a shared socket set back for the duration of `f`.
+    #[cfg(feature = "async")]
+    pub fn with_sockets<R>(
+        &mut self,
+        shared: &core::cell::RefCell<SocketSet<'static>>,
+        f: impl FnOnce(&mut Self) -> R,
+    ) -> R {
+        core::mem::swap(&mut self.sockets, &mut shared.borrow_mut());
+        let result = f(self);
+        core::mem::swap(&mut self.sockets, &mut shared.borrow_mut());
+        result
+    }
+
+    /// Move the interface out for the async APIs, leaving an unconfigured
+    /// one in its place.
+    #[cfg(feature = "async")]
+    pub fn share_iface(&mut self) -> core::cell::RefCell<Interface> {
+        let config = Config::new(MAC_ADDR.into());
+        let placeholder = Interface::new(config, &mut self.device, self.now);
+        core::cell::RefCell::new(core::mem::replace(
+            &mut self.iface,
+            placeholder,
+        ))
+    }
+
+    /// Put a shared interface and socket set back for the duration of `f`.
+    #[cfg(feature = "async")]
+    pub fn with_shared<R>(
+        &mut self,
+        iface: &core::cell::RefCell<Interface>,
+        sockets: &core::cell::RefCell<SocketSet<'static>>,
+        f: impl FnOnce(&mut Self) -> R,
+    ) -> R {
+        core::mem::swap(&mut self.iface, &mut iface.borrow_mut());
+        let result = self.with_sockets(sockets, f);
+        core::mem::swap(&mut self.iface, &mut iface.borrow_mut());
+        result
+    }
+
+    /// Dispatch queued packets and take the payload of the oldest UDP
+    /// datagram put on the wire.
+    pub fn take_udp_payload(&mut self) -> Option<Vec<u8>> {
+        self.iface
+            .poll(self.now, &mut self.device, &mut self.sockets);
+
+        while let Some(bytes) = self.device.sent.pop_front() {
+            let frame = EthernetFrame::new_checked(&bytes[..]).ok()?;
+            let Ok(packet) = Ipv4Packet::new_checked(frame.payload()) else {
+                continue;
+            };
+            if let Ok(datagram) = UdpPacket::new_checked(packet.payload()) {
+                return Some(datagram.payload().to_vec());
+            }
+        }
+
+        None
+    }
+}
+
+/// Waker recording whether it has been woken.
+#[cfg(feature = "async")]
+#[derive(Default)]
+pub struct WakeFlag(std::sync::atomic::AtomicBool);
+
+#[cfg(feature = "async")]
+impl WakeFlag {
+    pub fn waker() -> (std::sync::Arc<Self>, std::task::Waker) {
+        let flag = std::sync::Arc::new(Self::default());
+        (flag.clone(), flag.into())
+    }
+
+    /// Whether the waker was woken since the last call.
+    pub fn take(&self) -> bool {
+        self.0.swap(false, std::sync::atomic::Ordering::SeqCst)
+    }
+}
+
+#[cfg(feature = "async")]
+impl std::task::Wake for WakeFlag {
+    fn wake(self: std::sync::Arc<Self>) {
+        self.0.store(true, std::sync::atomic::Ordering::SeqCst);
+    }
+}
+
+/// In-memory [`Transport`] standing in for a connected socket.
+pub struct MockTransport {
+    pub state: tcp::State,
+    /// Bytes from the peer waiting to be read.
+    pub rx: VecDeque<u8>,
+    /// Bytes written and not yet taken by the peer, clear to acknowledge.
+    pub tx: Vec<u8>,
+    /// Length of every write.
+    pub writes: Vec<usize>,
+    pub capacity: usize,
+    pub timeout: Option<Duration>,
+}
+
+impl MockTransport {
+    /// Whether the connection is open for writing, full or not.
+    fn may_send(&self) -> bool {
+        matches!(self.state, tcp::State::Established | tcp::State::CloseWait)
+    }
+
+    pub fn new(state: tcp::State) -> Self {
+        Self {
+            state,
+            rx: VecDeque::new(),
+            tx: Vec::new(),
+            writes: Vec::new(),
+            capacity: 1024,
+            timeout: Some(Duration::from_secs(3)),
+        }
+    }
+}
+
+impl Transport for MockTransport {
+    fn state(&self) -> tcp::State {
+        self.state
+    }
+
+    fn can_send(&self) -> bool {
+        self.may_send() && self.tx.len() < self.capacity
+    }
+
+    fn can_recv(&self) -> bool {
+        !self.rx.is_empty()
+    }
+
+    fn send_slice(&mut self, data: &[u8]) -> Result<usize, tcp::SendError> {
+        if !self.may_send() {
+            return Err(tcp::SendError::InvalidState);
+        }
+        let len = data.len().min(self.capacity - self.tx.len());
+        self.tx.extend_from_slice(&data[..len]);
+        self.writes.push(len);
+        Ok(len)
+    }
+
+    fn send<F, R>(&mut self, f: F) -> Result<R, tcp::SendError>
+    where
+        F: FnOnce(&mut [u8]) -> (usize, R),
+    {
+        if !self.may_send() {
+            return Err(tcp::SendError::InvalidState);
+        }
+        let mut buf = vec![0; self.capacity - self.tx.len()];
+        let (len, result) = f(&mut buf);
+        self.tx.extend_from_slice(&buf[..len]);
+        self.writes.push(len);
+        Ok(result)
+    }
+
+    fn recv_slice(&mut self, data: &mut [u8]) -> Result<usize, tcp::RecvError> {
+        let len = self.peek_slice(data)?;
+        self.rx.drain(..len);
+        Ok(len)
+    }
+
+    fn peek_slice(&mut self, data: &mut [u8]) -> Result<usize, tcp::RecvError> {
+        let len = data.len().min(self.rx.len());
+        for (out, byte) in data.iter_mut().zip(&self.rx) {
+            *out = *byte;
+        }
+        Ok(len)
+    }
+
+    fn recv_queue(&self) -> usize {
+        self.rx.len()
+    }
+
+    fn send_queue(&self) -> usize {
+        self.tx.len()
+    }
+
+    fn send_capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn close(&mut self) {
+        self.state = match self.state {
+            tcp::State::CloseWait => tcp::State::LastAck,
+            _ => tcp::State::FinWait1,
+        };
+    }
+
+    fn abort(&mut self) {
+        self.state = tcp::State::Closed;
+    }
+
+    fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+}
+
+/// CAN FD frame with more data than the protocol carries.
+pub struct FdFrame {
+    id: embedded_can::Id,
+    data: [u8; 12],
+}
+
+impl embedded_can::Frame for FdFrame {
+    fn new(id: impl Into<embedded_can::Id>, data: &[u8]) -> Option<Self> {
+        Some(FdFrame {
+            id: id.into(),
+            data: data.try_into().ok()?,
+        })
+    }
+
+    fn new_remote(_: impl Into<embedded_can::Id>, _: usize) -> Option<Self> {
+        None
+    }
+
+    fn is_extended(&self) -> bool {
+        matches!(self.id, embedded_can::Id::Extended(_))
+    }
+
+    fn is_remote_frame(&self) -> bool {
+        false
+    }
+
+    fn id(&self) -> embedded_can::Id {
+        self.id
+    }
+
+    fn dlc(&self) -> usize {
+        self.data.len()
+    }
+
+    fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
@@ -7,12 +7,30 @@
 //!
 //! - `async` enable the async feature for `smoltcp` and the associated methods.
 //! - `defmt-03` enable defmt formatting attributes.
+//! - `frame-crc` enable [`tcp::Server::set_frame_crc`], a trailing CRC16 on
+//!   every frame for noisy links. Not part of the Tritium protocol.
+//! - `heapless` enable [`tcp::Server::recv_into`], receiving a batch of
+//!   frames into a [`heapless::Vec`].
+//! - `ipv6` enable IPv6 in `smoltcp` so a [`tcp::Client`] can connect to an
+//!   IPv6 endpoint.
+//! - `log` trace every CAN frame sent and received with the `log` crate.
+//! - `test-util` enable [`tcp::Server::inject_rx`], feeding frames to the
+//!   receive path without a peer.
 
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 
+pub mod can;
+pub mod clock;
+pub mod error;
 pub mod tcp;
+pub mod transport;
 pub mod udp;
 
+#[cfg(test)]
+mod harness;
+
+pub use error::Error;
+
 // re-export
 pub use tritiumcan as proto;
 
@@ -1,18 +1,271 @@
 //! TCP protocol.
 
-use core::mem::size_of;
-
+use crate::clock::Clock;
+use crate::error::Error;
+use crate::transport::Transport;
 use smoltcp::{
-    iface::{SocketHandle, SocketSet},
-    socket::tcp::{RecvError, SendError, Socket, SocketBuffer, State},
+    iface::{Context, SocketHandle, SocketSet},
+    socket::tcp::{Socket, SocketBuffer, State},
     time::{Duration, Instant},
-    wire::EthernetAddress,
+    wire::{EthernetAddress, IpEndpoint},
 };
 use tritiumcan::{
-    datagram::{Frame, Header, Packet},
-    BusNumber, HEARTBEAT_INTERVAL, PORT, PROTOCOL_VERSION,
+    capture::{Capture, Direction},
+    codec::{Codec, CodecError, Endianness, HEADER_LEN},
+    datagram::{
+        Frame, FrameRef, Packet, PacketBuilder, ReceivedItem, FRAME_LEN,
+    },
+    filter::IdFilter,
+    queue::FrameQueue,
+    BusNumber, HEARTBEAT_INTERVAL, PORT,
 };
-use zerocopy::{AsBytes, FromZeroes};
+use zerocopy::AsBytes;
+
+#[cfg(feature = "async")]
+use crate::error::TransportError;
+#[cfg(feature = "async")]
+use core::task::Poll;
+#[cfg(feature = "async")]
+use smoltcp::{iface::Interface, socket::tcp};
+#[cfg(feature = "frame-crc")]
+use tritiumcan::codec::CRC_LEN;
+
+/// Number of frames [`Server::send_frame`] can queue while the send buffer
+/// is full.
+pub const PENDING_LEN: usize = 16;
+
+/// Default for [`Server::set_max_segment`], the MSS smoltcp assumes until
+/// the peer advertises one.
+pub const DEFAULT_MAX_SEGMENT: usize = 536;
+
+/// Number of recent frames kept once [`Server::set_capture_enabled`].
+pub const CAPTURE_LEN: usize = 16;
+
+/// Longest encoding of a frame on the wire.
+#[cfg(not(feature = "frame-crc"))]
+const MAX_WIRE_LEN: usize = FRAME_LEN;
+#[cfg(feature = "frame-crc")]
+const MAX_WIRE_LEN: usize = FRAME_LEN + CRC_LEN;
+
+/// Error connecting a [`Client`] with [`Client::connect_async`].
+#[cfg(feature = "async")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub enum ConnectError {
+    /// The socket couldn't start connecting.
+    Connect(tcp::ConnectError),
+    /// The remote refused the connection.
+    Refused,
+    /// The remote didn't answer within the socket timeout.
+    TimedOut,
+}
+
+/// Smallest socket buffer [`Server::try_new`] accepts, room for the header
+/// and a frame.
+pub const MIN_BUFFER_LEN: usize = HEADER_LEN + MAX_WIRE_LEN;
+
+/// Error creating a [`Server`] or [`Client`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub enum ConfigError {
+    /// The receive buffer is smaller than [`MIN_BUFFER_LEN`].
+    RxBufferTooSmall,
+    /// The send buffer is smaller than [`MIN_BUFFER_LEN`].
+    TxBufferTooSmall,
+}
+
+/// Check both socket buffers can hold the header and a whole frame.
+fn check_buffers(
+    rx_buffer: &SocketBuffer,
+    tx_buffer: &SocketBuffer,
+) -> Result<(), ConfigError> {
+    if rx_buffer.capacity() < MIN_BUFFER_LEN {
+        return Err(ConfigError::RxBufferTooSmall);
+    }
+    if tx_buffer.capacity() < MIN_BUFFER_LEN {
+        return Err(ConfigError::TxBufferTooSmall);
+    }
+    Ok(())
+}
+
+/// Socket buffer size [`ServerBuffers`] defaults to, room for the header
+/// and 64 frames.
+pub const DEFAULT_BUFFER_LEN: usize = HEADER_LEN + 64 * MAX_WIRE_LEN;
+
+/// Backing storage for a [`Server`]'s receive and send buffers.
+///
+/// Sizes below [`MIN_BUFFER_LEN`] fail to compile.
+///
+/// ```rust,ignore
+/// let mut buffers = ServerBuffers::<DEFAULT_BUFFER_LEN>::new();
+/// let (rx_buffer, tx_buffer) = buffers.socket_buffers();
+/// let server = Server::new(&mut sockets, rx_buffer, tx_buffer, ..);
+/// ```
+#[derive(Debug)]
+pub struct ServerBuffers<
+    const RX: usize = DEFAULT_BUFFER_LEN,
+    const TX: usize = DEFAULT_BUFFER_LEN,
+> {
+    rx: [u8; RX],
+    tx: [u8; TX],
+}
+
+impl<const RX: usize, const TX: usize> ServerBuffers<RX, TX> {
+    const CHECK_LEN: () = {
+        assert!(RX >= MIN_BUFFER_LEN, "receive buffer too small");
+        assert!(TX >= MIN_BUFFER_LEN, "send buffer too small");
+    };
+
+    pub const fn new() -> Self {
+        let () = Self::CHECK_LEN;
+
+        Self {
+            rx: [0; RX],
+            tx: [0; TX],
+        }
+    }
+
+    /// Receive and send buffers to pass to [`Server::new`].
+    pub fn socket_buffers(&mut self) -> (SocketBuffer<'_>, SocketBuffer<'_>) {
+        (
+            SocketBuffer::new(&mut self.rx[..]),
+            SocketBuffer::new(&mut self.tx[..]),
+        )
+    }
+}
+
+impl<const RX: usize, const TX: usize> Default for ServerBuffers<RX, TX> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Traffic counters for a [`Server`].
+///
+/// Counters saturate at [`u32::MAX`] rather than wrapping.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub struct Stats {
+    pub frames_sent: u32,
+    pub frames_received: u32,
+    pub heartbeats_sent: u32,
+    pub bytes_sent: u32,
+    pub bytes_received: u32,
+    /// Received frames with an invalid DLC and bytes discarded without
+    /// forming a whole frame.
+    pub malformed_frames: u32,
+    /// Frames dropped by the transmit or receive [`IdFilter`].
+    pub frames_filtered: u32,
+    /// Received frames dropped as duplicates, see
+    /// [`Server::set_dedup_window`].
+    pub frames_deduped: u32,
+    /// Frames dropped for exceeding [`Server::set_tx_rate_limit`].
+    pub frames_rate_limited: u32,
+    /// Received frames that didn't match their CRC.
+    #[cfg(feature = "frame-crc")]
+    pub crc_errors: u32,
+}
+
+impl Stats {
+    fn count(counter: &mut u32, n: usize) {
+        *counter = counter.saturating_add(n.try_into().unwrap_or(u32::MAX));
+    }
+
+    fn sent(&mut self, frames: usize, wire_len: usize) {
+        Self::count(&mut self.frames_sent, frames);
+        Self::count(&mut self.bytes_sent, frames * wire_len);
+    }
+}
+
+/// Received frame with the time it was read.
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub struct TimedFrame {
+    pub frame: Frame,
+    /// `now` of the [`Server::poll`] before the frame was read.
+    pub at: Instant,
+}
+
+impl Default for TimedFrame {
+    fn default() -> Self {
+        Self {
+            frame: Frame::default(),
+            at: Instant::ZERO,
+        }
+    }
+}
+
+/// Connection change reported by [`Server::poll`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub enum PollEvent {
+    /// Nothing changed.
+    None,
+    /// A client connected and the header has been sent.
+    ClientConnected,
+    /// The client disconnected.
+    ClientDisconnected,
+    /// The connection was dropped after sent data went unacknowledged for
+    /// the socket timeout.
+    ///
+    /// Per-connection state is reset as for
+    /// [`PollEvent::ClientDisconnected`].
+    TimedOut,
+}
+
+/// What a call to [`Server::poll_readiness`] found, for deciding whether
+/// to sleep.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub struct Readiness {
+    /// Connection change, as returned by [`Server::poll`].
+    pub event: PollEvent,
+    /// The poll wrote a header, heartbeat or pending frames to the send
+    /// buffer.
+    pub sent: bool,
+    /// Received bytes are waiting to be read.
+    pub recv: bool,
+}
+
+impl Readiness {
+    /// Whether nothing happened and nothing is waiting to be read.
+    pub fn is_idle(&self) -> bool {
+        self.event == PollEvent::None && !self.sent && !self.recv
+    }
+}
+
+/// Connection state of a [`Server`] or [`Client`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub enum ConnectionState {
+    /// Waiting for a client to connect.
+    Listening,
+    /// Connecting to a remote server.
+    Connecting,
+    /// A client is connected.
+    Connected,
+    /// The connection is being shut down.
+    Closing,
+    /// The socket is closed and will listen again on the next poll.
+    Closed,
+}
+
+impl From<State> for ConnectionState {
+    fn from(state: State) -> Self {
+        match state {
+            State::Listen | State::SynReceived => ConnectionState::Listening,
+            State::SynSent => ConnectionState::Connecting,
+            State::Established => ConnectionState::Connected,
+            State::FinWait1
+            | State::FinWait2
+            | State::CloseWait
+            | State::Closing
+            | State::LastAck
+            | State::TimeWait => ConnectionState::Closing,
+            State::Closed => ConnectionState::Closed,
+        }
+    }
+}
 
 #[derive(Debug)]
 #[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
@@ -22,11 +275,65 @@ pub struct Server {
     mac_addr: [u8; 6],
     bus_number: BusNumber,
     data_rate: u16,
+    heartbeat_interval: Duration,
+    port: u16,
+    client_identifier: u64,
+    forward: bool,
+    tx_filter: IdFilter,
+    rx_filter: IdFilter,
+    strict_bus_check: bool,
+    listen_only: bool,
+    dedup_window: Option<Duration>,
+    max_segment: usize,
+    endianness: Endianness,
+    /// Frames per second.
+    tx_rate_limit: Option<u32>,
+    #[cfg(feature = "frame-crc")]
+    frame_crc: bool,
+    /// Every frame after the header goes with a header of its own, for
+    /// [`MuxServer`].
+    tagged: bool,
 
     // state
+    last_poll: Instant,
     last_heartbeat: Instant,
+    /// When the peer's last heartbeat was received, or when it connected.
+    last_peer_heartbeat: Instant,
     tx_start: bool,
     rx_start: bool,
+    /// Client identifier of the last header sent.
+    tx_client_id: u64,
+    /// Bus number and client identifier from the peer's header.
+    peer_bus_number: Option<BusNumber>,
+    peer_client_id: Option<u64>,
+    /// Partially received frame.
+    rx_buf: [u8; MAX_WIRE_LEN],
+    rx_len: usize,
+    /// Frame returned by the last peek, in protocol byte order.
+    peek_buf: [u8; FRAME_LEN],
+    /// Frames waiting for send buffer space.
+    pending: FrameQueue<PENDING_LEN>,
+    /// Tail of a write that only partly fit in the send buffer.
+    tx_rest: [u8; HEADER_LEN + MAX_WIRE_LEN],
+    tx_rest_len: usize,
+    /// Last CAN frame received and when, for the dedup window.
+    last_rx: Option<([u8; FRAME_LEN], Instant)>,
+    /// When the bytes in the send buffer were first seen unacknowledged.
+    rtt_start: Option<Instant>,
+    rtt: Option<Duration>,
+    /// When the send queue was last seen making progress while non-empty,
+    /// to tell a timeout from a reset.
+    unacked_since: Option<Instant>,
+    last_send_queue: usize,
+    /// Start of the current rate limit window and frames sent in it.
+    rate_window_start: Instant,
+    rate_window_sent: u32,
+    /// Recent frames, when capture is enabled.
+    capture: Option<Capture<CAPTURE_LEN>>,
+    stats: Stats,
+    /// Frames from [`Server::inject_rx`] waiting to be received.
+    #[cfg(feature = "test-util")]
+    injected: FrameQueue<PENDING_LEN>,
 }
 
 impl Server {
@@ -46,50 +353,236 @@ impl Server {
         Self {
             handle,
             mac_addr: mac_addr.0,
+            last_poll: now,
             last_heartbeat: now,
+            last_peer_heartbeat: now,
             bus_number,
             data_rate,
+            heartbeat_interval: HEARTBEAT_INTERVAL.into(),
+            port: PORT,
+            client_identifier: 0,
+            forward: false,
+            tx_filter: IdFilter::new(),
+            rx_filter: IdFilter::new(),
+            strict_bus_check: false,
+            listen_only: false,
+            dedup_window: None,
+            max_segment: DEFAULT_MAX_SEGMENT,
+            endianness: Endianness::Big,
+            tx_rate_limit: None,
+            #[cfg(feature = "frame-crc")]
+            frame_crc: false,
+            tagged: false,
             tx_start: false,
             rx_start: false,
+            tx_client_id: 0,
+            peer_bus_number: None,
+            peer_client_id: None,
+            rx_buf: [0; MAX_WIRE_LEN],
+            rx_len: 0,
+            peek_buf: [0; FRAME_LEN],
+            pending: FrameQueue::new(),
+            tx_rest: [0; HEADER_LEN + MAX_WIRE_LEN],
+            tx_rest_len: 0,
+            last_rx: None,
+            rtt_start: None,
+            rtt: None,
+            unacked_since: None,
+            last_send_queue: 0,
+            rate_window_start: now,
+            rate_window_sent: 0,
+            capture: None,
+            stats: Stats::default(),
+            #[cfg(feature = "test-util")]
+            injected: FrameQueue::new(),
+        }
+    }
+
+    /// Like [`Server::new`], first checking both buffers are at least
+    /// [`MIN_BUFFER_LEN`] bytes.
+    ///
+    /// A smaller buffer can never hold a whole frame so the connection would
+    /// stall without an error.
+    pub fn try_new<'a>(
+        sockets: &mut SocketSet<'a>,
+        rx_buffer: SocketBuffer<'a>,
+        tx_buffer: SocketBuffer<'a>,
+        mac_addr: EthernetAddress,
+        now: Instant,
+        bus_number: BusNumber,
+        data_rate: u16,
+    ) -> Result<Self, ConfigError> {
+        check_buffers(&rx_buffer, &tx_buffer)?;
+
+        Ok(Self::new(
+            sockets, rx_buffer, tx_buffer, mac_addr, now, bus_number, data_rate,
+        ))
+    }
+
+    /// Listen on `port` rather than [`PORT`].
+    ///
+    /// Lets several servers share a [`SocketSet`] on distinct ports.
+    pub fn with_port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    /// Port the server listens on.
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Like [`Server::poll`], taking the time from `clock`.
+    pub fn poll_clock(
+        &mut self,
+        sockets: &mut SocketSet,
+        clock: &impl Clock,
+    ) -> PollEvent {
+        self.poll(sockets, clock.now())
+    }
+
+    /// Like [`Server::poll`], also reporting whether anything was sent or
+    /// is waiting to be received.
+    pub fn poll_readiness(
+        &mut self,
+        sockets: &mut SocketSet,
+        now: Instant,
+    ) -> Readiness {
+        let queued = sockets.get::<Socket>(self.handle).send_queue();
+        let event = self.poll(sockets, now);
+        self.readiness(sockets, event, queued)
+    }
+
+    /// Readiness after a poll that reported `event`, `queued` being the
+    /// bytes in the send buffer before it.
+    fn readiness(
+        &self,
+        sockets: &SocketSet,
+        event: PollEvent,
+        queued: usize,
+    ) -> Readiness {
+        // nothing else touches the buffers during a poll, so any growth was
+        // written by it
+        let socket = sockets.get::<Socket>(self.handle);
+        Readiness {
+            event,
+            sent: socket.send_queue() > queued,
+            recv: socket.recv_queue() > 0,
         }
     }
 
-    pub fn poll(&mut self, sockets: &mut SocketSet, now: Instant) {
+    /// Listen for a client, send the header once connected and send
+    /// heartbeats, reporting connection changes.
+    pub fn poll(&mut self, sockets: &mut SocketSet, now: Instant) -> PollEvent {
         let socket = sockets.get_mut::<Socket>(self.handle);
 
-        if !socket.is_open() {
-            if !socket.is_listening() {
-                if let Err(_err) = socket.listen(PORT) {
+        // an ended connection is reported first so an aborted socket gets to
+        // send its reset before listening again
+        match socket.state() {
+            // an aborted socket still has its reset to send
+            State::Closed if socket.remote_endpoint().is_some() => {}
+            // closed, or only waiting out stray segments of the old connection
+            // which a new listen discards
+            State::Closed | State::TimeWait if !self.tx_start => {
+                if let Err(_err) = socket.listen(self.port) {
                     #[cfg(feature = "defmt-03")]
-                    defmt::error!("Failed to bind to {}: {}", PORT, _err);
+                    defmt::error!("Failed to bind to {}: {}", self.port, _err);
                 }
             }
+            // still closing, the peer has to acknowledge our close first
+            _ => {}
         }
 
+        self.poll_socket(socket, now)
+    }
+
+    /// Connection handling shared by [`Server`] and [`Client`].
+    fn poll_socket<T: Transport>(
+        &mut self,
+        socket: &mut T,
+        now: Instant,
+    ) -> PollEvent {
+        let poll_gap = now - self.last_poll;
+        self.last_poll = now;
+
         // if client closes, close on our end as well
         if socket.state() == State::CloseWait {
             socket.close();
-            self.tx_start = false;
-            self.rx_start = false;
-            return;
+            return self.disconnected();
+        }
+
+        // connection was reset or timed out, possibly after the peer's
+        // header arrived but before ours went out
+        if (self.tx_start || self.rx_start) && !socket.is_active() {
+            // smoltcp closes the socket the same way for both, but it only
+            // times out once nothing has been acknowledged for the timeout,
+            // allowing for the time since the queue was last seen moving
+            let timed_out = socket.state() == State::Closed
+                && matches!(
+                    (self.unacked_since, socket.timeout()),
+                    (Some(since), Some(timeout))
+                        if now - since + poll_gap >= timeout
+                );
+            let event = self.disconnected();
+            return if timed_out {
+                PollEvent::TimedOut
+            } else {
+                event
+            };
+        }
+
+        let send_queue = socket.send_queue();
+        if send_queue == 0 {
+            self.unacked_since = None;
+        } else if self.unacked_since.is_none()
+            || send_queue < self.last_send_queue
+        {
+            self.unacked_since = Some(now);
+        }
+        self.last_send_queue = send_queue;
+
+        let mut event = PollEvent::None;
+
+        // nothing is ever sent, the connection starts once established
+        if self.listen_only {
+            if !self.tx_start && socket.state() == State::Established {
+                self.tx_start = true;
+                self.last_peer_heartbeat = now;
+                event = PollEvent::ClientConnected;
+            }
+            return event;
         }
 
         if socket.can_send() {
             if !self.tx_start {
-                let mut packet = Packet {
-                    header: Header::new(),
-                    frame: Frame::new_zeroed(),
-                };
-                packet.header.set_version(PROTOCOL_VERSION);
-                packet.header.set_bus_number(self.bus_number.into());
-                packet.header.set_client_identifier(0);
-
-                if socket.send_slice(packet.as_bytes()).is_ok() {
+                // the header carries a heartbeat so the peer learns the data
+                // rate straight away
+                let heartbeat = Packet::new_heartbeat(
+                    &self.mac_addr,
+                    &self.bus_number,
+                    &self.data_rate,
+                );
+                let mut buf = [0; HEADER_LEN + MAX_WIRE_LEN];
+                let len = self.encode_packet(
+                    self.bus_number,
+                    &heartbeat.frame,
+                    &mut buf,
+                );
+
+                if self.write_bytes(socket, &buf[..len]).is_ok() {
+                    Stats::count(&mut self.stats.bytes_sent, len);
                     self.tx_start = true;
+                    self.tx_client_id = self.client_identifier;
+                    self.last_peer_heartbeat = now;
+                    event = PollEvent::ClientConnected;
                 }
             }
 
-            if now - self.last_heartbeat > HEARTBEAT_INTERVAL.into() {
+            if self.tx_start {
+                self.flush_pending(socket);
+            }
+
+            if now - self.last_heartbeat > self.heartbeat_interval {
                 match self.write_heartbeat(socket) {
                     Ok(_) => self.last_heartbeat = now,
                     Err(_err) => {
@@ -98,7 +591,161 @@ impl Server {
                     }
                 }
             }
+
+            self.sample_rtt(socket, now);
+        }
+
+        event
+    }
+
+    /// Time how long sent bytes take to be acknowledged, as smoltcp's own
+    /// estimate isn't public.
+    fn sample_rtt<T: Transport>(&mut self, socket: &T, now: Instant) {
+        match self.rtt_start {
+            Some(start) if socket.send_queue() == 0 => {
+                let sample = now - start;
+                // smoothed like RFC 6298
+                self.rtt = Some(match self.rtt {
+                    Some(rtt) => (rtt * 7 + sample) / 8,
+                    None => sample,
+                });
+                self.rtt_start = None;
+            }
+            None if socket.send_queue() > 0 => self.rtt_start = Some(now),
+            _ => {}
+        }
+    }
+
+    /// Reset per-connection state after the client has gone.
+    fn disconnected(&mut self) -> PollEvent {
+        let was_connected = self.tx_start;
+
+        if self.rx_len > 0 {
+            Stats::count(&mut self.stats.malformed_frames, 1);
         }
+        self.tx_start = false;
+        self.rx_start = false;
+        self.peer_bus_number = None;
+        self.peer_client_id = None;
+        self.rx_len = 0;
+        self.pending.clear();
+        self.tx_rest_len = 0;
+        self.last_rx = None;
+        self.rtt_start = None;
+        self.rtt = None;
+        self.unacked_since = None;
+        self.last_send_queue = 0;
+
+        if was_connected {
+            PollEvent::ClientDisconnected
+        } else {
+            PollEvent::None
+        }
+    }
+
+    /// Set the interval between heartbeats.
+    ///
+    /// Defaults to [`HEARTBEAT_INTERVAL`].
+    pub fn set_heartbeat_interval(&mut self, interval: Duration) {
+        self.heartbeat_interval = interval;
+    }
+
+    /// Set the client identifier sent in the header, 0 by default.
+    ///
+    /// Takes effect from the next connection.
+    pub fn set_client_identifier(&mut self, client_identifier: u64) {
+        self.client_identifier = client_identifier;
+    }
+
+    /// Client identifier sent in the header.
+    pub fn client_identifier(&self) -> u64 {
+        self.client_identifier
+    }
+
+    /// Ask the peer to forward frames to its other buses by setting the
+    /// forwarding flag in the header, off by default.
+    ///
+    /// Takes effect from the next connection.
+    pub fn set_forward(&mut self, forward: bool) {
+        self.forward = forward;
+    }
+
+    /// Send TCP keep-alive probes after `interval` without traffic, off by
+    /// default.
+    ///
+    /// The socket's 3 second timeout also applies to a probe, so combined
+    /// with heartbeats a dead peer is detected within `interval` plus 3
+    /// seconds even when neither side has frames to send.
+    pub fn set_keep_alive(
+        &mut self,
+        sockets: &mut SocketSet,
+        interval: Option<Duration>,
+    ) {
+        sockets
+            .get_mut::<Socket>(self.handle)
+            .set_keep_alive(interval);
+    }
+
+    /// Current connection state.
+    pub fn state(&self, sockets: &SocketSet) -> ConnectionState {
+        sockets.get::<Socket>(self.handle).state().into()
+    }
+
+    /// Whether a client is connected.
+    pub fn is_connected(&self, sockets: &SocketSet) -> bool {
+        self.state(sockets) == ConnectionState::Connected
+    }
+
+    /// Whether the connection is established and [`Server::poll`] has sent
+    /// the header, so frames can be sent.
+    pub fn is_ready(&self, sockets: &SocketSet) -> bool {
+        self.tx_start
+            && !self.listen_only
+            && sockets.get::<Socket>(self.handle).is_active()
+    }
+
+    /// Drop the client with a reset, discarding anything still buffered.
+    ///
+    /// The next [`Server::poll`] listens for a new client once the reset has
+    /// gone out, without reporting [`PollEvent::ClientDisconnected`].
+    pub fn disconnect(&mut self, sockets: &mut SocketSet) {
+        sockets.get_mut::<Socket>(self.handle).abort();
+        self.disconnected();
+    }
+
+    /// Gracefully close the connection once buffered data has been sent.
+    ///
+    /// Frames sent by the client in the meantime can still be received.
+    /// [`Server::poll`] reports [`PollEvent::ClientDisconnected`] and listens
+    /// again once the close completes.
+    pub fn close(&mut self, sockets: &mut SocketSet) {
+        sockets.get_mut::<Socket>(self.handle).close();
+    }
+
+    /// Bus number declared in the peer's header.
+    ///
+    /// `None` until the header has been received by [`Server::recv_frame`].
+    pub fn peer_bus_number(&self) -> Option<BusNumber> {
+        self.peer_bus_number
+    }
+
+    /// Client identifier declared in the peer's header.
+    ///
+    /// `None` until the header has been received by [`Server::recv_frame`].
+    pub fn peer_client_id(&self) -> Option<u64> {
+        self.peer_client_id
+    }
+
+    /// Whether the peer has sent a heartbeat within three heartbeat
+    /// intervals.
+    ///
+    /// Heartbeats are noticed by [`Server::recv_frame`] and
+    /// [`Server::recv_item`] at the time of the last [`Server::poll`]. A
+    /// newly connected peer is given the same grace period before its first
+    /// heartbeat.
+    pub fn peer_alive(&self, now: Instant) -> bool {
+        (self.tx_start || self.rx_start)
+            && now - self.last_peer_heartbeat <= self.heartbeat_interval * 3
     }
 
     /// Send heartbeat.
@@ -107,92 +754,4106 @@ impl Server {
     pub fn send_heartbeat(
         &mut self,
         sockets: &mut SocketSet,
-    ) -> Result<(), SendError> {
+    ) -> Result<(), Error> {
         let socket = sockets.get_mut::<Socket>(self.handle);
 
         self.write_heartbeat(socket)
     }
 
-    fn write_heartbeat(&self, socket: &mut Socket) -> Result<(), SendError> {
+    /// Push a data rate change to the peer with a settings frame.
+    pub fn send_settings(
+        &mut self,
+        sockets: &mut SocketSet,
+        data_rate: u16,
+    ) -> Result<(), Error> {
+        let socket = sockets.get_mut::<Socket>(self.handle);
+
+        if !socket.is_active() || !self.tx_start || self.listen_only {
+            return Err(Error::NotConnected);
+        }
+
+        let packet = Packet::new_settings(&self.bus_number, &data_rate);
+        let mut buf = [0; MAX_WIRE_LEN];
+        let len = self.encode(&packet.frame, &mut buf);
+        self.write_bytes(socket, &buf[..len])?;
+        Stats::count(&mut self.stats.bytes_sent, len);
+        Ok(())
+    }
+
+    /// Move to another bus number and data rate.
+    ///
+    /// Later headers and heartbeats use the new values and, if
+    /// [`Server::is_ready`], a settings frame pushes the data rate to the
+    /// peer straight away. The connected peer's header was checked against
+    /// the old bus number, the new one applies from the next connection.
+    pub fn reconfigure(
+        &mut self,
+        sockets: &mut SocketSet,
+        bus_number: BusNumber,
+        data_rate: u16,
+    ) -> Result<(), Error> {
+        self.bus_number = bus_number;
+        self.data_rate = data_rate;
+
+        if self.is_ready(sockets) {
+            self.send_settings(sockets, data_rate)?;
+        }
+
+        Ok(())
+    }
+
+    fn write_heartbeat<T: Transport>(
+        &mut self,
+        socket: &mut T,
+    ) -> Result<(), Error> {
+        if self.listen_only {
+            return Err(Error::NotConnected);
+        }
+
         let packet = Packet::new_heartbeat(
             &self.mac_addr,
             &self.bus_number,
             &self.data_rate,
         );
 
-        socket.send_slice(&packet.frame.0).map(|_| ())
+        let mut buf = [0; HEADER_LEN + MAX_WIRE_LEN];
+        let len = if self.tagged {
+            self.encode_packet(self.bus_number, &packet.frame, &mut buf)
+        } else {
+            self.encode(&packet.frame, &mut buf)
+        };
+        self.write_bytes(socket, &buf[..len])?;
+        Stats::count(&mut self.stats.heartbeats_sent, 1);
+        Stats::count(&mut self.stats.bytes_sent, len);
+        Ok(())
     }
 
-    /// Send a CAN frame.
-    pub fn send_frame(
-        &mut self,
-        sockets: &mut SocketSet,
-        frame: &impl embedded_can::Frame,
-    ) -> Result<(), SendError> {
-        let socket = sockets.get_mut::<Socket>(self.handle);
+    /// Only send frames accepted by `filter`.
+    ///
+    /// Frames that don't match are silently dropped by
+    /// [`Server::send_frame`] and [`Server::send_frames`] and counted in
+    /// [`Stats::frames_filtered`]. Defaults to an empty filter, which
+    /// accepts every frame.
+    pub fn set_tx_filter(&mut self, filter: IdFilter) {
+        self.tx_filter = filter;
+    }
 
-        let frame = Frame::from_frame(frame).unwrap();
+    /// Only receive frames accepted by `filter`.
+    ///
+    /// Filtering happens after a whole frame has been reassembled, frames
+    /// that don't match are consumed and counted in
+    /// [`Stats::frames_filtered`] but never returned by
+    /// [`Server::recv_frame`] or [`Server::recv_frames`].
+    pub fn set_rx_filter(&mut self, filter: IdFilter) {
+        self.rx_filter = filter;
+    }
 
-        if socket.can_send() && self.tx_start {
-            socket.send_slice(frame.as_bytes()).map(|_| ())?
-        }
+    /// Drop a received CAN frame identical to the previous one if it
+    /// arrives within `window`, off by default.
+    ///
+    /// Dropped frames are counted in [`Stats::frames_deduped`]. Receive has
+    /// no clock so frames are timed by the last [`Server::poll`].
+    pub fn set_dedup_window(&mut self, window: Option<Duration>) {
+        self.dedup_window = window;
+    }
 
-        Err(SendError::InvalidState)
+    /// Send at most `limit` frames per second, off by default.
+    ///
+    /// Frames over the limit are dropped by [`Server::send_frame`] and
+    /// [`Server::send_frames`] and counted in
+    /// [`Stats::frames_rate_limited`]. Send has no clock so frames are timed
+    /// by the last [`Server::poll`], in windows of one second.
+    pub fn set_tx_rate_limit(&mut self, limit: Option<u32>) {
+        self.tx_rate_limit = limit;
     }
 
-    /// Receive a CAN frame.
-    pub fn recv_frame(
-        &mut self,
-        sockets: &mut SocketSet,
-    ) -> Result<Option<Frame>, RecvError> {
-        let socket = sockets.get_mut::<Socket>(self.handle);
+    /// Frames that can still be sent in the current rate limit window,
+    /// starting a new window once the last one is over.
+    fn rate_budget(&mut self) -> u32 {
+        let Some(limit) = self.tx_rate_limit else {
+            return u32::MAX;
+        };
 
-        if socket.can_recv() {
-            if !self.rx_start {
-                socket.recv_slice(&mut [0; 30]).ok();
-                self.rx_start = true;
+        if self.last_poll - self.rate_window_start >= Duration::from_secs(1) {
+            self.rate_window_start = self.last_poll;
+            self.rate_window_sent = 0;
+        }
+        limit.saturating_sub(self.rate_window_sent)
+    }
+
+    /// Whether `frame` repeats the last received frame within the dedup
+    /// window.
+    fn is_duplicate(&self, frame: &Frame) -> bool {
+        match (self.dedup_window, &self.last_rx) {
+            (Some(window), Some((last, at))) => {
+                last == frame.as_bytes() && self.last_poll - *at <= window
             }
-        } else {
-            return Ok(None);
+            _ => false,
         }
+    }
 
-        let mut frame = Frame::new_zeroed();
-        let len = socket.recv_slice(frame.as_bytes_mut())?;
+    /// Append a CRC16 to every frame sent and check it on every frame
+    /// received, off by default.
+    ///
+    /// This isn't part of the Tritium protocol so both ends have to enable
+    /// it, before the connection opens.
+    #[cfg(feature = "frame-crc")]
+    pub fn set_frame_crc(&mut self, enabled: bool) {
+        self.frame_crc = enabled;
+    }
 
-        if len != size_of::<Frame>() {
-            Ok(None)
-        } else {
-            Ok(Some(frame))
+    /// Length of a frame on the wire.
+    fn wire_len(&self) -> usize {
+        #[cfg(feature = "frame-crc")]
+        if self.frame_crc {
+            return FRAME_LEN + CRC_LEN;
         }
+
+        FRAME_LEN
     }
 
-    /// Register a waker for receive operations.
+    /// Encode `frame` for the wire into `buf`, which holds at least
+    /// [`MAX_WIRE_LEN`] bytes, returning the encoded length.
+    fn encode(&self, frame: &Frame, buf: &mut [u8]) -> usize {
+        #[cfg(feature = "frame-crc")]
+        if self.frame_crc {
+            let frame = self.endianness.convert(frame);
+            return Codec::encode_frame_crc(&frame, buf).unwrap();
+        }
+
+        Codec::encode_frame_with(frame, buf, self.endianness).unwrap()
+    }
+
+    /// Encode a header declaring `bus_number` followed by `frame` into
+    /// `buf`, which holds at least `HEADER_LEN + MAX_WIRE_LEN` bytes,
+    /// returning the encoded length.
+    fn encode_packet(
+        &self,
+        bus_number: BusNumber,
+        frame: &Frame,
+        buf: &mut [u8],
+    ) -> usize {
+        let header = PacketBuilder::new()
+            .bus_number(bus_number)
+            .client_id(self.client_identifier)
+            .forward(self.forward)
+            .build()
+            .header;
+        buf[..HEADER_LEN].copy_from_slice(header.as_bytes());
+
+        HEADER_LEN + self.encode(frame, &mut buf[HEADER_LEN..])
+    }
+
+    fn decode(&self, buf: &[u8]) -> Result<(Option<Frame>, usize), CodecError> {
+        #[cfg(feature = "frame-crc")]
+        if self.frame_crc {
+            let (frame, len) = Codec::decode_crc(buf)?;
+            let frame = frame.map(|frame| self.endianness.convert(&frame));
+            return Ok((frame, len));
+        }
+
+        Codec::decode_with(buf, self.endianness)
+    }
+
+    /// Close connections whose header declares a bus number other than
+    /// this server's, off by default.
+    pub fn set_strict_bus_check(&mut self, strict: bool) {
+        self.strict_bus_check = strict;
+    }
+
+    /// Never send anything to the peer, off by default.
     ///
-    /// See [smoltcp documentation](https://docs.rs/smoltcp/latest/smoltcp/socket/tcp/struct.Socket.html#method.register_recv_waker)
-    /// for the rules around receive wakers.
-    #[cfg(feature = "async")]
-    pub fn register_recv_waker(
-        &mut self,
-        sockets: &mut SocketSet,
-        waker: &core::task::Waker,
-    ) {
-        let socket = sockets.get_mut::<Socket>(self.handle);
+    /// [`Server::poll`] only tracks the connection, no header or heartbeats
+    /// are sent, and every send returns [`Error::NotConnected`].
+    /// Received frames are unaffected.
+    pub fn set_listen_only(&mut self, listen_only: bool) {
+        self.listen_only = listen_only;
+    }
 
-        socket.register_recv_waker(waker);
+    /// Free space in bytes in the send buffer, less frames still pending.
+    ///
+    /// `send_capacity / FRAME_LEN` frames can be sent without any waiting.
+    pub fn send_capacity(&self, sockets: &SocketSet) -> usize {
+        let socket = sockets.get::<Socket>(self.handle);
+        let free = socket.send_capacity() - socket.send_queue();
+        free.saturating_sub(
+            self.pending.len() * self.wire_len() + self.tx_rest_len,
+        )
     }
 
-    /// Register a waker for send operations.
+    /// Bytes received and waiting to be read.
+    pub fn recv_queue(&self, sockets: &SocketSet) -> usize {
+        sockets.get::<Socket>(self.handle).recv_queue()
+    }
+
+    /// Receive window advertised to the peer, the free space in the receive
+    /// buffer.
     ///
-    /// See [smoltcp documentation](https://docs.rs/smoltcp/latest/smoltcp/socket/tcp/struct.Socket.html#method.register_send_waker)
-    /// for the rules around receive wakers.
-    #[cfg(feature = "async")]
-    pub fn register_send_waker(
-        &mut self,
-        sockets: &mut SocketSet,
-        waker: &core::task::Waker,
-    ) {
-        let socket = sockets.get_mut::<Socket>(self.handle);
+    /// smoltcp sizes the window from the receive buffer and fixes its scale
+    /// when the connection opens, so it can't be changed at runtime. Create
+    /// the server with a larger buffer to absorb bigger bursts, or read more
+    /// often so the window stays open.
+    pub fn recv_window(&self, sockets: &SocketSet) -> usize {
+        let socket = sockets.get::<Socket>(self.handle);
+        socket.recv_capacity() - socket.recv_queue()
+    }
 
-        socket.register_send_waker(waker);
+    /// Number of whole frames received and waiting to be read, counting a
+    /// partially received frame already taken from the socket.
+    ///
+    /// The peer's header isn't counted until it has been read. Heartbeats,
+    /// settings and frames the receive filter drops count as well, so
+    /// [`Server::recv_frames`] may return fewer.
+    pub fn available_frames(&self, sockets: &SocketSet) -> usize {
+        let mut queued = self.rx_len + self.recv_queue(sockets);
+        if !self.rx_start {
+            queued = queued.saturating_sub(HEADER_LEN);
+        }
+        queued / self.wire_len()
+    }
+
+    /// Smoothed round trip time of the connection, `None` until sent data
+    /// has been acknowledged.
+    ///
+    /// Measured between calls to [`Server::poll`], so it includes up to one
+    /// poll interval and any delayed acknowledgement from the peer.
+    pub fn rtt(&self) -> Option<Duration> {
+        self.rtt
+    }
+
+    /// Time without an acknowledgement after which the connection is
+    /// dropped, 3 seconds by default.
+    pub fn timeout(&self, sockets: &SocketSet) -> Option<Duration> {
+        sockets.get::<Socket>(self.handle).timeout()
+    }
+
+    /// Keep the last [`CAPTURE_LEN`] CAN frames sent and received, off by
+    /// default.
+    ///
+    /// The capture survives disconnects so it can be dumped after an error.
+    /// Disabling it drops what was captured.
+    pub fn set_capture_enabled(&mut self, enabled: bool) {
+        if !enabled {
+            self.capture = None;
+        } else if self.capture.is_none() {
+            self.capture = Some(Capture::new());
+        }
+    }
+
+    /// Captured frames, oldest first, see [`Server::set_capture_enabled`].
+    pub fn capture(&self) -> impl Iterator<Item = (Direction, Frame)> + '_ {
+        self.capture.iter().flat_map(Capture::iter)
+    }
+
+    /// Capture, and with the `log` feature trace, a CAN frame sent or
+    /// received.
+    fn record(&mut self, direction: Direction, frame: &Frame) {
+        #[cfg(feature = "log")]
+        log::trace!("{:?} {}", direction, frame);
+
+        if let Some(capture) = &mut self.capture {
+            capture.record(direction, frame);
+        }
+    }
+
+    /// Traffic counters.
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    /// Reset all traffic counters to zero.
+    pub fn reset_stats(&mut self) {
+        self.stats = Stats::default();
+    }
+
+    /// Number of frames queued waiting for send buffer space.
+    pub fn pending_len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Write queued frames to the send buffer now rather than on the next
+    /// [`Server::poll`], returning how many are still waiting for room.
+    ///
+    /// The interface transmits them on its next poll, combine with
+    /// [`Server::set_nagle_enabled`] so small segments aren't held back.
+    pub fn flush(&mut self, sockets: &mut SocketSet) -> Result<usize, Error> {
+        let socket = sockets.get_mut::<Socket>(self.handle);
+
+        if !socket.is_active() || !self.tx_start || self.listen_only {
+            return Err(Error::NotConnected);
+        }
+
+        self.flush_pending(socket);
+        Ok(self.pending.len())
+    }
+
+    /// Hold back small segments while earlier data is unacknowledged, on by
+    /// default.
+    ///
+    /// Disable for low-latency control loops that send single frames.
+    pub fn set_nagle_enabled(
+        &mut self,
+        sockets: &mut SocketSet,
+        enabled: bool,
+    ) {
+        sockets
+            .get_mut::<Socket>(self.handle)
+            .set_nagle_enabled(enabled);
+    }
+
+    /// Finish a partial write then write queued frames while there is room
+    /// for them.
+    fn flush_pending<T: Transport>(&mut self, socket: &mut T) {
+        while self.flush_rest(socket) && send_room(socket) > 0 {
+            let Some(frame) = self.pending.pop() else {
+                break;
+            };
+            let mut buf = [0; MAX_WIRE_LEN];
+            let len = self.encode(&frame, &mut buf);
+            if self.write_bytes(socket, &buf[..len]).is_err() {
+                break;
+            }
+            self.stats.sent(1, len);
+        }
+    }
+
+    /// Write what's left of a partial write, returning whether it's all
+    /// gone.
+    fn flush_rest<T: Transport>(&mut self, socket: &mut T) -> bool {
+        if self.tx_rest_len > 0 {
+            if let Ok(len) =
+                socket.send_slice(&self.tx_rest[..self.tx_rest_len])
+            {
+                self.tx_rest.copy_within(len..self.tx_rest_len, 0);
+                self.tx_rest_len -= len;
+            }
+        }
+        self.tx_rest_len == 0
+    }
+
+    /// Write all of `bytes`, keeping what doesn't fit in the send buffer to
+    /// finish before anything else is written.
+    ///
+    /// Returns [`Error::WouldBlock`] if an earlier partial write still
+    /// hasn't finished.
+    fn write_bytes<T: Transport>(
+        &mut self,
+        socket: &mut T,
+        bytes: &[u8],
+    ) -> Result<(), Error> {
+        if !self.flush_rest(socket) {
+            return Err(Error::WouldBlock);
+        }
+
+        let len = socket.send_slice(bytes)?;
+        let rest = &bytes[len..];
+        self.tx_rest[..rest.len()].copy_from_slice(rest);
+        self.tx_rest_len = rest.len();
+        Ok(())
+    }
+
+    /// Send a CAN frame.
+    ///
+    /// Only call this once [`Server::is_ready`], before then it returns
+    /// [`Error::NotConnected`]. If the send buffer is full the frame is
+    /// queued and written by a later [`Server::poll`], returning
+    /// [`Error::QueueFull`] once [`PENDING_LEN`] frames are waiting. A frame
+    /// with more than 8 bytes of data returns [`Error::FrameTooLong`].
+    pub fn send_frame(
+        &mut self,
+        sockets: &mut SocketSet,
+        frame: &impl embedded_can::Frame,
+    ) -> Result<(), Error> {
+        let socket = sockets.get_mut::<Socket>(self.handle);
+        let frame =
+            Frame::from_frame(frame).map_err(|_| Error::FrameTooLong)?;
+
+        self.send_frame_on(socket, frame)
+    }
+
+    /// Send a CAN frame as if from another client, for emulating several
+    /// sources over one connection.
+    ///
+    /// A header carrying `client_id` goes out before the frame whenever it
+    /// differs from the client identifier of the last header sent. Frames
+    /// already queued are written first so they keep their identifier,
+    /// returning [`Error::QueueFull`] if they don't all fit. A frame dropped
+    /// by the tx filter or rate limit sends no header.
+    pub fn send_frame_as(
+        &mut self,
+        sockets: &mut SocketSet,
+        client_id: u64,
+        frame: &impl embedded_can::Frame,
+    ) -> Result<(), Error> {
+        let socket = sockets.get_mut::<Socket>(self.handle);
+        let frame =
+            Frame::from_frame(frame).map_err(|_| Error::FrameTooLong)?;
+
+        if !socket.is_active() || !self.tx_start || self.listen_only {
+            return Err(Error::NotConnected);
+        }
+
+        if !self.admit(&frame) {
+            return Ok(());
+        }
+
+        if client_id != self.tx_client_id {
+            self.flush_pending(socket);
+            if !self.pending.is_empty() {
+                return Err(Error::QueueFull);
+            }
+
+            let header = PacketBuilder::new()
+                .bus_number(self.bus_number)
+                .client_id(client_id)
+                .forward(self.forward)
+                .build()
+                .header;
+            self.write_bytes(socket, header.as_bytes())?;
+            Stats::count(&mut self.stats.bytes_sent, HEADER_LEN);
+            self.tx_client_id = client_id;
+        }
+
+        self.write_frame_on(socket, frame)
+    }
+
+    /// Whether the tx filter and rate limit let `frame` through, counting
+    /// it in the stats if not.
+    fn admit(&mut self, frame: &Frame) -> bool {
+        if !self.tx_filter.matches(frame.id()) {
+            Stats::count(&mut self.stats.frames_filtered, 1);
+            return false;
+        }
+
+        if self.rate_budget() == 0 {
+            Stats::count(&mut self.stats.frames_rate_limited, 1);
+            return false;
+        }
+
+        true
+    }
+
+    /// Send, or queue, a CAN frame on `socket`.
+    fn send_frame_on<T: Transport>(
+        &mut self,
+        socket: &mut T,
+        frame: Frame,
+    ) -> Result<(), Error> {
+        if !socket.is_active() || !self.tx_start || self.listen_only {
+            return Err(Error::NotConnected);
+        }
+
+        if !self.admit(&frame) {
+            return Ok(());
+        }
+
+        self.write_frame_on(socket, frame)
+    }
+
+    /// Send, or queue, an admitted CAN frame on `socket`.
+    fn write_frame_on<T: Transport>(
+        &mut self,
+        socket: &mut T,
+        frame: Frame,
+    ) -> Result<(), Error> {
+        self.flush_pending(socket);
+
+        let mut buf = [0; MAX_WIRE_LEN];
+        let len = self.encode(&frame, &mut buf);
+
+        if self.pending.is_empty()
+            && self.tx_rest_len == 0
+            && send_room(socket) > 0
+        {
+            self.write_bytes(socket, &buf[..len])?;
+            self.stats.sent(1, len);
+            self.rate_window_sent = self.rate_window_sent.saturating_add(1);
+            self.record(Direction::Tx, &frame);
+            return Ok(());
+        }
+
+        if self.pending.is_full() {
+            return Err(Error::QueueFull);
+        }
+        self.rate_window_sent = self.rate_window_sent.saturating_add(1);
+        self.record(Direction::Tx, &frame);
+        self.pending.push(frame).map_err(|_| Error::QueueFull)
+    }
+
+    /// Largest number of bytes [`Server::send_frames`] writes at once,
+    /// [`DEFAULT_MAX_SEGMENT`] by default.
+    ///
+    /// Rounded down to whole frames, but always at least one. smoltcp
+    /// doesn't expose the connection's MSS, lower this to match a link with
+    /// a small path MTU.
+    pub fn set_max_segment(&mut self, max_segment: usize) {
+        self.max_segment = max_segment;
+    }
+
+    /// Byte order of the CAN ID of frames sent and received,
+    /// [`Endianness::Big`] by default as the protocol specifies.
+    ///
+    /// Set [`Endianness::Little`] to talk to a peer that sends it
+    /// little-endian. Every frame is affected, heartbeats and settings
+    /// included, and the header is not.
+    pub fn set_endianness(&mut self, endianness: Endianness) {
+        self.endianness = endianness;
+    }
+
+    /// Send as many `frames` as fit in the send buffer, in writes of at
+    /// most [`Server::set_max_segment`] bytes.
+    ///
+    /// Returns the number of frames consumed from the start of `frames`,
+    /// including any dropped by the transmit filter, which may be less than
+    /// `frames.len()` if the buffer is full.
+    pub fn send_frames(
+        &mut self,
+        sockets: &mut SocketSet,
+        frames: &[Frame],
+    ) -> Result<usize, Error> {
+        let socket = sockets.get_mut::<Socket>(self.handle);
+
+        self.send_frames_on(socket, frames)
+    }
+
+    /// Send as many `frames` as fit on `socket`.
+    fn send_frames_on<T: Transport>(
+        &mut self,
+        socket: &mut T,
+        frames: &[Frame],
+    ) -> Result<usize, Error> {
+        // not can_send, which is also false with the send buffer full
+        if !socket.is_active() || !self.tx_start || self.listen_only {
+            return Err(Error::NotConnected);
+        }
+
+        // keep frame order by writing anything already queued first
+        self.flush_pending(socket);
+        if !self.pending.is_empty() || self.tx_rest_len > 0 {
+            return Ok(0);
+        }
+
+        let wire_len = self.wire_len();
+        let segment = (self.max_segment / wire_len).max(1) * wire_len;
+        let mut consumed = 0;
+        let mut written = 0;
+        let mut limited = 0;
+
+        while consumed < frames.len() {
+            let budget = self.rate_budget();
+            let this = &*self;
+            let rest = &frames[consumed..];
+            let (mut segment_consumed, mut segment_written, segment_limited) =
+                socket.send(|buf| {
+                    let len = buf.len().min(segment);
+                    let mut chunks = buf[..len].chunks_exact_mut(wire_len);
+                    let mut consumed = 0;
+                    let mut written = 0;
+                    let mut limited = 0;
+
+                    for frame in rest {
+                        if this.tx_filter.matches(frame.id()) {
+                            if written == budget {
+                                limited += 1;
+                            } else {
+                                let Some(chunk) = chunks.next() else {
+                                    break;
+                                };
+                                this.encode(frame, chunk);
+                                written += 1;
+                            }
+                        }
+                        consumed += 1;
+                    }
+
+                    (written as usize * wire_len, (consumed, written, limited))
+                })?;
+
+            // no room for the next frame before the end of the ring buffer,
+            // copy it in across the wrap if the window has space
+            if segment_consumed == 0 {
+                let free = socket.send_capacity() - socket.send_queue();
+                if free < wire_len {
+                    // the send buffer is full
+                    break;
+                }
+                let mut buf = [0; MAX_WIRE_LEN];
+                self.encode(&frames[consumed], &mut buf);
+                socket.send_slice(&buf[..wire_len])?;
+                segment_consumed = 1;
+                segment_written = 1;
+            }
+            consumed += segment_consumed;
+            written += segment_written as usize;
+            limited += segment_limited;
+            self.rate_window_sent =
+                self.rate_window_sent.saturating_add(segment_written);
+        }
+        self.stats.sent(written, wire_len);
+        Stats::count(&mut self.stats.frames_rate_limited, limited);
+        Stats::count(
+            &mut self.stats.frames_filtered,
+            consumed - written - limited,
+        );
+        // frames over the rate limit come after every frame written
+        let mut recorded = 0;
+        for frame in &frames[..consumed] {
+            if recorded < written && self.tx_filter.matches(frame.id()) {
+                self.record(Direction::Tx, frame);
+                recorded += 1;
+            }
+        }
+
+        Ok(consumed)
+    }
+
+    /// Receive a CAN frame.
+    ///
+    /// Frames split across several reads are reassembled, returning `None`
+    /// until a whole frame has been received.
+    pub fn recv_frame(
+        &mut self,
+        sockets: &mut SocketSet,
+    ) -> Result<Option<Frame>, Error> {
+        loop {
+            match self.recv_item(sockets)? {
+                Some(ReceivedItem::Data(frame)) => return Ok(Some(frame)),
+                Some(_) => {}
+                None => return Ok(None),
+            }
+        }
+    }
+
+    /// Receive a CAN frame stamped with the last poll's `now`.
+    pub fn recv_timed_frame(
+        &mut self,
+        sockets: &mut SocketSet,
+    ) -> Result<Option<TimedFrame>, Error> {
+        let at = self.last_poll;
+        Ok(self
+            .recv_frame(sockets)?
+            .map(|frame| TimedFrame { frame, at }))
+    }
+
+    /// Receive a CAN frame, heartbeat or settings frame.
+    ///
+    /// [`Server::recv_frame`] skips anything that isn't a CAN frame, use this
+    /// to also see the peer's heartbeats and settings.
+    pub fn recv_item(
+        &mut self,
+        sockets: &mut SocketSet,
+    ) -> Result<Option<ReceivedItem>, Error> {
+        let socket = sockets.get_mut::<Socket>(self.handle);
+
+        self.recv_item_on(socket)
+    }
+
+    /// Receive a CAN frame, heartbeat or settings frame from `socket`.
+    fn recv_item_on<T: Transport>(
+        &mut self,
+        socket: &mut T,
+    ) -> Result<Option<ReceivedItem>, Error> {
+        #[cfg(feature = "test-util")]
+        while let Some(frame) = self.injected.pop() {
+            Stats::count(&mut self.stats.frames_received, 1);
+            let bus_number = self.peer_bus_number.unwrap_or_default();
+            if let Some(item) = self.accept(frame, bus_number) {
+                return Ok(Some(item));
+            }
+        }
+
+        self.recv_socket_item_on(socket)
+    }
+
+    /// Like [`Server::recv_item_on`], leaving injected frames queued.
+    fn recv_socket_item_on<T: Transport>(
+        &mut self,
+        socket: &mut T,
+    ) -> Result<Option<ReceivedItem>, Error> {
+        if !socket.can_recv() || !self.recv_header(socket)? {
+            return Ok(None);
+        }
+
+        let wire_len = self.wire_len();
+
+        loop {
+            let rx_buf = &mut self.rx_buf[self.rx_len..wire_len];
+            let len = socket.recv_slice(rx_buf)?;
+            self.rx_len += len;
+            Stats::count(&mut self.stats.bytes_received, len);
+
+            if self.rx_len < wire_len {
+                return Ok(None);
+            }
+
+            self.rx_len = 0;
+            Stats::count(&mut self.stats.frames_received, 1);
+
+            match self.decode(&self.rx_buf[..wire_len]) {
+                Ok((Some(frame), _)) => {
+                    let bus_number = self.peer_bus_number.unwrap_or_default();
+                    if let Some(item) = self.accept(frame, bus_number) {
+                        return Ok(Some(item));
+                    }
+                }
+                Ok((None, _)) => return Ok(None),
+                #[cfg(feature = "frame-crc")]
+                Err(CodecError::BadCrc) => {
+                    #[cfg(feature = "defmt-03")]
+                    defmt::warn!("Frame CRC mismatch");
+                    Stats::count(&mut self.stats.crc_errors, 1);
+                }
+                Err(_err) => {
+                    #[cfg(feature = "defmt-03")]
+                    defmt::warn!("Malformed frame: {}", _err);
+                    Stats::count(&mut self.stats.malformed_frames, 1);
+                }
+            }
+        }
+    }
+
+    /// Classify a frame received on `bus_number`, returning `None` if it is
+    /// filtered out or a duplicate.
+    fn accept(
+        &mut self,
+        frame: Frame,
+        bus_number: BusNumber,
+    ) -> Option<ReceivedItem> {
+        match ReceivedItem::classify(frame, bus_number) {
+            ReceivedItem::Data(frame)
+                if !self.rx_filter.matches(frame.id()) =>
+            {
+                Stats::count(&mut self.stats.frames_filtered, 1);
+                None
+            }
+            ReceivedItem::Data(frame) if self.is_duplicate(&frame) => {
+                Stats::count(&mut self.stats.frames_deduped, 1);
+                None
+            }
+            item => {
+                match &item {
+                    ReceivedItem::Heartbeat(_) => {
+                        // receive has no clock, use the last poll's
+                        self.last_peer_heartbeat = self.last_poll;
+                    }
+                    ReceivedItem::Data(frame) => {
+                        if self.dedup_window.is_some() {
+                            self.last_rx = Some((frame.0, self.last_poll));
+                        }
+                        self.record(Direction::Rx, frame);
+                    }
+                    _ => {}
+                }
+                Some(item)
+            }
+        }
+    }
+
+    /// Hand `frame` to the next receive as if it had come from the peer,
+    /// for testing application logic without a network.
+    ///
+    /// Up to [`PENDING_LEN`] frames are held, ahead of anything in the
+    /// socket, and go through the receive filter and dedup window. They are
+    /// returned by [`Server::recv_frame`] and [`Server::recv_item`] but not
+    /// seen by [`Server::peek_frame`]. Hands `frame` back if the queue is
+    /// full.
+    #[cfg(feature = "test-util")]
+    pub fn inject_rx(&mut self, frame: &Frame) -> Result<(), Frame> {
+        self.injected
+            .push(tritiumcan::datagram::FrameBitfield(frame.0))
+    }
+
+    /// Receive the peer's header if it hasn't been yet, returning whether it
+    /// has.
+    fn recv_header<T: Transport>(
+        &mut self,
+        socket: &mut T,
+    ) -> Result<bool, Error> {
+        if self.rx_start {
+            return Ok(true);
+        }
+
+        let mut buf = [0; HEADER_LEN];
+        if socket.recv_queue() < buf.len() {
+            return Ok(false);
+        }
+
+        let len = socket.recv_slice(&mut buf)?;
+        Stats::count(&mut self.stats.bytes_received, len);
+
+        match Codec::decode_header(&buf[..len]) {
+            Ok((None, _)) => {}
+            Ok((Some(header), _))
+                if self.strict_bus_check
+                    && header.bus_number() != self.bus_number.into() =>
+            {
+                #[cfg(feature = "defmt-03")]
+                defmt::warn!(
+                    "Peer on bus {} rather than {}",
+                    header.bus_number(),
+                    self.bus_number
+                );
+                socket.abort();
+            }
+            Ok((Some(header), _)) => {
+                self.peer_bus_number =
+                    BusNumber::try_from(header.bus_number()).ok();
+                self.peer_client_id = Some(header.client_identifier());
+                self.rx_start = true;
+            }
+            Err(_err) => {
+                #[cfg(feature = "defmt-03")]
+                defmt::warn!("Malformed header: {}", _err);
+                Stats::count(&mut self.stats.malformed_frames, 1);
+                socket.abort();
+                return Err(Error::Malformed);
+            }
+        }
+
+        Ok(self.rx_start)
+    }
+
+    /// Look at the next CAN frame without consuming it.
+    ///
+    /// The frame stays in the socket buffer so the next
+    /// [`Server::recv_frame`] returns it. Heartbeats, settings and filtered
+    /// frames in front of it are consumed as [`Server::recv_frame`] would.
+    pub fn peek_frame(
+        &mut self,
+        sockets: &mut SocketSet,
+    ) -> Result<Option<FrameRef<'_>>, Error> {
+        let wire_len = self.wire_len();
+
+        loop {
+            let socket = sockets.get_mut::<Socket>(self.handle);
+            if !socket.can_recv() || !self.recv_header(socket)? {
+                return Ok(None);
+            }
+
+            // complete any partial frame in place, leaving rx_len as it is
+            let rx_buf = &mut self.rx_buf[self.rx_len..wire_len];
+            if self.rx_len + socket.peek_slice(rx_buf)? < wire_len {
+                return Ok(None);
+            }
+
+            if let Ok((Some(frame), _)) = self.decode(&self.rx_buf[..wire_len])
+            {
+                let bus_number = self.peer_bus_number.unwrap_or_default();
+                if let ReceivedItem::Data(frame) =
+                    ReceivedItem::classify(frame, bus_number)
+                {
+                    if self.rx_filter.matches(frame.id())
+                        && !self.is_duplicate(&frame)
+                    {
+                        self.peek_buf = frame.0;
+                        return Ok(FrameRef::from_bytes(&self.peek_buf));
+                    }
+                }
+            }
+
+            // not a frame recv_frame would return, consume it as that would
+            // but without touching injected frames
+            self.recv_socket_item_on(socket)?;
+        }
+    }
+
+    /// Receive every whole frame currently buffered, up to `out.len()`.
+    ///
+    /// Returns the number of frames written to the start of `out`. Trailing
+    /// bytes of a partial frame are kept for the next call.
+    pub fn recv_frames(
+        &mut self,
+        sockets: &mut SocketSet,
+        out: &mut [Frame],
+    ) -> Result<usize, Error> {
+        let mut count = 0;
+
+        while count < out.len() {
+            match self.recv_frame(sockets)? {
+                Some(frame) => {
+                    out[count] = frame;
+                    count += 1;
+                }
+                None => break,
+            }
+        }
+
+        Ok(count)
+    }
+
+    /// Receive buffered frames onto the end of `out` until it is full,
+    /// returning how many were pushed.
+    ///
+    /// Frames that don't fit stay in the socket buffer for the next call.
+    #[cfg(feature = "heapless")]
+    pub fn recv_into<const N: usize>(
+        &mut self,
+        sockets: &mut SocketSet,
+        out: &mut heapless::Vec<Frame, N>,
+    ) -> Result<usize, Error> {
+        let mut count = 0;
+
+        while !out.is_full() {
+            match self.recv_frame(sockets)? {
+                Some(frame) => {
+                    // can't fail, checked above
+                    let _ = out.push(frame);
+                    count += 1;
+                }
+                None => break,
+            }
+        }
+
+        Ok(count)
+    }
+
+    /// Discard everything received so far, including a partially received
+    /// frame, returning the number of bytes dropped.
+    ///
+    /// The peer's header is still processed if it has arrived, so only
+    /// frames are thrown away.
+    pub fn clear_rx(
+        &mut self,
+        sockets: &mut SocketSet,
+    ) -> Result<usize, Error> {
+        let socket = sockets.get_mut::<Socket>(self.handle);
+
+        // a staged partial frame goes even when the socket is empty
+        let mut discarded = self.rx_len;
+        self.rx_len = 0;
+
+        if !socket.can_recv() || !self.recv_header(socket)? {
+            return Ok(discarded);
+        }
+
+        let mut buf = [0; 64];
+        loop {
+            let len = socket.recv_slice(&mut buf)?;
+            if len == 0 {
+                break;
+            }
+            Stats::count(&mut self.stats.bytes_received, len);
+            discarded += len;
+        }
+
+        Ok(discarded)
+    }
+
+    /// Like [`Server::recv_frames`], stamping each frame with the last
+    /// poll's `now`.
+    pub fn recv_timed_frames(
+        &mut self,
+        sockets: &mut SocketSet,
+        out: &mut [TimedFrame],
+    ) -> Result<usize, Error> {
+        let mut count = 0;
+
+        while count < out.len() {
+            match self.recv_timed_frame(sockets)? {
+                Some(frame) => {
+                    out[count] = frame;
+                    count += 1;
+                }
+                None => break,
+            }
+        }
+
+        Ok(count)
+    }
+
+    /// Register a waker for receive operations.
+    ///
+    /// See [smoltcp documentation](https://docs.rs/smoltcp/latest/smoltcp/socket/tcp/struct.Socket.html#method.register_recv_waker)
+    /// for the rules around receive wakers.
+    #[cfg(feature = "async")]
+    pub fn register_recv_waker(
+        &mut self,
+        sockets: &mut SocketSet,
+        waker: &core::task::Waker,
+    ) {
+        let socket = sockets.get_mut::<Socket>(self.handle);
+
+        socket.register_recv_waker(waker);
+    }
+
+    /// Register a waker for send operations.
+    ///
+    /// See [smoltcp documentation](https://docs.rs/smoltcp/latest/smoltcp/socket/tcp/struct.Socket.html#method.register_send_waker)
+    /// for the rules around receive wakers.
+    #[cfg(feature = "async")]
+    pub fn register_send_waker(
+        &mut self,
+        sockets: &mut SocketSet,
+        waker: &core::task::Waker,
+    ) {
+        let socket = sockets.get_mut::<Socket>(self.handle);
+
+        socket.register_send_waker(waker);
+    }
+
+    /// Send a CAN frame, waiting for room in the send buffer.
+    ///
+    /// The socket set is only borrowed while the future is polled so the
+    /// interface can keep running in between. Completes once the frame has
+    /// been written to the send buffer.
+    #[cfg(feature = "async")]
+    pub async fn send_frame_async(
+        &mut self,
+        sockets: &core::cell::RefCell<SocketSet<'_>>,
+        frame: &impl embedded_can::Frame,
+    ) -> Result<(), Error> {
+        core::future::poll_fn(|cx| {
+            let mut sockets = sockets.borrow_mut();
+            let socket = sockets.get_mut::<Socket>(self.handle);
+
+            if !socket.is_active() || !self.tx_start || self.listen_only {
+                return Poll::Ready(Err(Error::NotConnected));
+            }
+
+            self.flush_pending(socket);
+            if !self.pending.is_empty()
+                || self.tx_rest_len > 0
+                || send_room(socket) < self.wire_len()
+            {
+                socket.register_send_waker(cx.waker());
+                return Poll::Pending;
+            }
+
+            Poll::Ready(self.send_frame(&mut sockets, frame))
+        })
+        .await
+    }
+
+    /// Receive a CAN frame, waiting until a whole frame has arrived.
+    ///
+    /// Like [`Server::send_frame_async`] the socket set is only borrowed
+    /// while polling. Resolves with [`TransportError::Finished`] if the peer
+    /// closes the connection.
+    #[cfg(feature = "async")]
+    pub async fn recv_frame_async(
+        &mut self,
+        sockets: &core::cell::RefCell<SocketSet<'_>>,
+    ) -> Result<Frame, Error> {
+        core::future::poll_fn(|cx| {
+            self.poll_recv_frame(cx, &mut sockets.borrow_mut())
+        })
+        .await
+    }
+
+    /// Stream of received frames.
+    ///
+    /// The stream ends once the connection it started on has closed.
+    #[cfg(feature = "async")]
+    pub fn frame_stream<'s, 'a>(
+        &'s mut self,
+        sockets: &'s core::cell::RefCell<SocketSet<'a>>,
+    ) -> FrameStream<'s, 'a> {
+        FrameStream {
+            server: self,
+            sockets,
+            started: false,
+            done: false,
+        }
+    }
+
+    /// Try to receive a frame, registering `cx`'s waker if none is ready.
+    #[cfg(feature = "async")]
+    fn poll_recv_frame(
+        &mut self,
+        cx: &mut core::task::Context,
+        sockets: &mut SocketSet,
+    ) -> Poll<Result<Frame, Error>> {
+        match self.recv_frame(sockets) {
+            Ok(Some(frame)) => Poll::Ready(Ok(frame)),
+            Err(err) => Poll::Ready(Err(err)),
+            Ok(None) => {
+                let socket = sockets.get_mut::<Socket>(self.handle);
+                if self.rx_start && !socket.may_recv() {
+                    return Poll::Ready(Err(Error::Transport(
+                        TransportError::Finished,
+                    )));
+                }
+
+                socket.register_recv_waker(cx.waker());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Stream of frames received by a [`Server`] or [`Client`], created by
+/// [`Server::frame_stream`].
+#[cfg(feature = "async")]
+pub struct FrameStream<'s, 'a> {
+    server: &'s mut Server,
+    sockets: &'s core::cell::RefCell<SocketSet<'a>>,
+    /// Whether the peer's header has been seen on this connection.
+    started: bool,
+    done: bool,
+}
+
+#[cfg(feature = "async")]
+impl futures_core::Stream for FrameStream<'_, '_> {
+    type Item = Frame;
+
+    fn poll_next(
+        self: core::pin::Pin<&mut Self>,
+        cx: &mut core::task::Context,
+    ) -> Poll<Option<Frame>> {
+        let this = self.get_mut();
+        if this.done {
+            return Poll::Ready(None);
+        }
+
+        let poll = this
+            .server
+            .poll_recv_frame(cx, &mut this.sockets.borrow_mut());
+
+        // a poll in between may have already reset the connection state
+        if this.server.rx_start {
+            this.started = true;
+        } else if this.started {
+            this.done = true;
+        }
+
+        match poll {
+            Poll::Ready(Ok(frame)) => Poll::Ready(Some(frame)),
+            Poll::Ready(Err(_)) => {
+                this.done = true;
+                Poll::Ready(None)
+            }
+            Poll::Pending if this.done => Poll::Ready(None),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Free space in the socket's send buffer.
+fn send_room<T: Transport>(socket: &T) -> usize {
+    socket.send_capacity() - socket.send_queue()
+}
+
+/// Client connecting out to a remote endpoint instead of listening.
+///
+/// Everything but connecting is done by the wrapped [`Server`], reached
+/// through [`Client::server`] and [`Client::server_mut`].
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub struct Client {
+    inner: Server,
+    remote: IpEndpoint,
+    local_port: u16,
+}
+
+impl Client {
+    /// Create a client connecting `server`'s socket to `remote` from
+    /// `local_port`.
+    ///
+    /// `remote` may be an IPv6 endpoint with the `ipv6` feature.
+    pub fn new(server: Server, remote: IpEndpoint, local_port: u16) -> Self {
+        Self {
+            inner: server,
+            remote,
+            local_port,
+        }
+    }
+
+    /// The server sending and receiving over the connection.
+    pub fn server(&self) -> &Server {
+        &self.inner
+    }
+
+    /// The server sending and receiving over the connection.
+    ///
+    /// Poll with [`Client::poll`] rather than [`Server::poll`], which
+    /// would listen instead of connecting.
+    pub fn server_mut(&mut self) -> &mut Server {
+        &mut self.inner
+    }
+
+    /// Like [`Client::poll`], taking the time from `clock`.
+    pub fn poll_clock(
+        &mut self,
+        cx: &mut Context,
+        sockets: &mut SocketSet,
+        clock: &impl Clock,
+    ) -> PollEvent {
+        self.poll(cx, sockets, clock.now())
+    }
+
+    /// Connect if not already connected, then send the header and
+    /// heartbeats once established.
+    pub fn poll(
+        &mut self,
+        cx: &mut Context,
+        sockets: &mut SocketSet,
+        now: Instant,
+    ) -> PollEvent {
+        let socket = sockets.get_mut::<Socket>(self.inner.handle);
+
+        // see Server::poll, report the old connection before reconnecting
+        if !socket.is_open() && !self.inner.tx_start {
+            if let Err(_err) = socket.connect(cx, self.remote, self.local_port)
+            {
+                #[cfg(feature = "defmt-03")]
+                defmt::error!("Failed to connect: {}", _err);
+            }
+        }
+
+        self.inner.poll_socket(socket, now)
+    }
+
+    /// Connect and send the header, waiting until both are done.
+    ///
+    /// Like [`Server::send_frame_async`] the interface and socket set are
+    /// only borrowed while polling, so the interface can keep running in
+    /// between. Resolves straight away if already connected and fails if
+    /// the connection is refused or times out. Call [`Client::poll`] as
+    /// usual afterwards for heartbeats.
+    #[cfg(feature = "async")]
+    pub async fn connect_async(
+        &mut self,
+        iface: &core::cell::RefCell<Interface>,
+        sockets: &core::cell::RefCell<SocketSet<'_>>,
+        clock: &impl Clock,
+    ) -> Result<(), ConnectError> {
+        let mut connect_start = None;
+
+        core::future::poll_fn(|cx| {
+            let mut sockets = sockets.borrow_mut();
+            let socket = sockets.get_mut::<Socket>(self.inner.handle);
+            let now = clock.now();
+
+            let started = match connect_start {
+                Some(started) => started,
+                None => {
+                    if !socket.is_open() {
+                        let mut iface = iface.borrow_mut();
+                        if let Err(err) = socket.connect(
+                            iface.context(),
+                            self.remote,
+                            self.local_port,
+                        ) {
+                            return Poll::Ready(Err(ConnectError::Connect(
+                                err,
+                            )));
+                        }
+                    }
+                    *connect_start.insert(now)
+                }
+            };
+
+            self.inner.poll_socket(socket, now);
+            if self.inner.tx_start {
+                return Poll::Ready(Ok(()));
+            }
+
+            // smoltcp closes the socket the same way for both, see
+            // Server::poll_socket
+            if socket.state() == State::Closed {
+                let timed_out = matches!(
+                    socket.timeout(),
+                    Some(timeout) if now - started >= timeout
+                );
+                return Poll::Ready(Err(if timed_out {
+                    ConnectError::TimedOut
+                } else {
+                    ConnectError::Refused
+                }));
+            }
+
+            socket.register_send_waker(cx.waker());
+            Poll::Pending
+        })
+        .await
+    }
+
+    /// Like [`Client::poll`], also reporting whether anything was sent or
+    /// is waiting to be received, see [`Server::poll_readiness`].
+    pub fn poll_readiness(
+        &mut self,
+        cx: &mut Context,
+        sockets: &mut SocketSet,
+        now: Instant,
+    ) -> Readiness {
+        let queued = sockets.get::<Socket>(self.inner.handle).send_queue();
+        let event = self.poll(cx, sockets, now);
+        self.inner.readiness(sockets, event, queued)
+    }
+}
+
+/// Handle to one client of a [`MultiServer`].
+///
+/// Reported by [`MultiServer::poll`] when the client connects and becomes
+/// stale once it disconnects, even if another client takes its place.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub struct ClientId {
+    slot: usize,
+    generation: u32,
+}
+
+/// Server accepting up to `N` clients at once on the same port.
+///
+/// Frames are broadcast to every connected client and received frames are
+/// merged from all of them.
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub struct MultiServer<const N: usize> {
+    servers: [Server; N],
+    /// Bumped each time a socket's client goes, invalidating old ids.
+    generations: [u32; N],
+    /// Client to receive from first, so one busy client can't starve the
+    /// others.
+    next_rx: usize,
+}
+
+impl<const N: usize> MultiServer<N> {
+    /// Create a server with a socket for each pair of receive and send
+    /// buffers.
+    pub fn new<'a>(
+        sockets: &mut SocketSet<'a>,
+        buffers: [(SocketBuffer<'a>, SocketBuffer<'a>); N],
+        mac_addr: EthernetAddress,
+        now: Instant,
+        bus_number: BusNumber,
+        data_rate: u16,
+    ) -> Self {
+        let servers = buffers.map(|(rx_buffer, tx_buffer)| {
+            Server::new(
+                sockets, rx_buffer, tx_buffer, mac_addr, now, bus_number,
+                data_rate,
+            )
+        });
+
+        Self {
+            servers,
+            generations: [0; N],
+            next_rx: 0,
+        }
+    }
+
+    /// Listen on every free socket and send headers and heartbeats,
+    /// reporting the connection change of each socket along with the client
+    /// it concerns.
+    pub fn poll(
+        &mut self,
+        sockets: &mut SocketSet,
+        now: Instant,
+    ) -> [(ClientId, PollEvent); N] {
+        core::array::from_fn(|slot| {
+            let event = self.servers[slot].poll(sockets, now);
+            let client = self.client_id(slot);
+            // bumped on the way out rather than in, so frames received
+            // before the header is sent carry the same id as the connect
+            if matches!(
+                event,
+                PollEvent::ClientDisconnected | PollEvent::TimedOut
+            ) {
+                self.generations[slot] = self.generations[slot].wrapping_add(1);
+            }
+            (client, event)
+        })
+    }
+
+    fn client_id(&self, slot: usize) -> ClientId {
+        ClientId {
+            slot,
+            generation: self.generations[slot],
+        }
+    }
+
+    /// The connected server behind `client`.
+    fn client(&mut self, client: ClientId) -> Result<&mut Server, Error> {
+        match self.servers.get_mut(client.slot) {
+            Some(server)
+                if server.tx_start
+                    && self.generations[client.slot] == client.generation =>
+            {
+                Ok(server)
+            }
+            _ => Err(Error::UnknownClient),
+        }
+    }
+
+    /// Set the interval between heartbeats.
+    ///
+    /// Defaults to [`HEARTBEAT_INTERVAL`].
+    pub fn set_heartbeat_interval(&mut self, interval: Duration) {
+        for server in &mut self.servers {
+            server.set_heartbeat_interval(interval);
+        }
+    }
+
+    /// Number of connected clients.
+    pub fn connected(&self, sockets: &SocketSet) -> usize {
+        self.servers
+            .iter()
+            .filter(|server| server.is_connected(sockets))
+            .count()
+    }
+
+    /// Send a CAN frame to every connected client.
+    ///
+    /// Returns the number of clients the frame was sent or queued to,
+    /// clients whose queue is full miss out on the frame.
+    pub fn send_frame(
+        &mut self,
+        sockets: &mut SocketSet,
+        frame: &impl embedded_can::Frame,
+    ) -> Result<usize, Error> {
+        let mut sent = 0;
+        let mut connected = false;
+
+        for server in &mut self.servers {
+            if !server.tx_start {
+                continue;
+            }
+            connected = true;
+
+            if server.send_frame(sockets, frame).is_ok() {
+                sent += 1;
+            }
+        }
+
+        if connected {
+            Ok(sent)
+        } else {
+            Err(Error::NotConnected)
+        }
+    }
+
+    /// Send a CAN frame to one client.
+    ///
+    /// Returns [`Error::UnknownClient`] if `client` has disconnected.
+    pub fn send_frame_to(
+        &mut self,
+        sockets: &mut SocketSet,
+        client: ClientId,
+        frame: &impl embedded_can::Frame,
+    ) -> Result<(), Error> {
+        self.client(client)?.send_frame(sockets, frame)
+    }
+
+    /// Receive a CAN frame from any client.
+    ///
+    /// Clients take turns so a busy one can't starve the others. A client
+    /// whose receive fails is skipped.
+    pub fn recv_frame(
+        &mut self,
+        sockets: &mut SocketSet,
+    ) -> Result<Option<Frame>, Error> {
+        Ok(self.recv_frame_from(sockets)?.map(|(_, frame)| frame))
+    }
+
+    /// Receive a CAN frame from any client along with the client it came
+    /// from.
+    pub fn recv_frame_from(
+        &mut self,
+        sockets: &mut SocketSet,
+    ) -> Result<Option<(ClientId, Frame)>, Error> {
+        for offset in 0..N {
+            let slot = (self.next_rx + offset) % N;
+
+            if let Ok(Some(frame)) = self.servers[slot].recv_frame(sockets) {
+                self.next_rx = (slot + 1) % N;
+                return Ok(Some((self.client_id(slot), frame)));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+/// Server for a bridge multiplexing several CAN buses over one connection.
+///
+/// After the usual header each frame goes with a header of its own, as in
+/// a [`Packet`], declaring the bus it belongs to. Heartbeats are sent the
+/// same way on this server's bus.
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub struct MuxServer {
+    inner: Server,
+    /// Partially received header and frame.
+    rx_buf: [u8; HEADER_LEN + MAX_WIRE_LEN],
+    rx_len: usize,
+}
+
+impl MuxServer {
+    pub fn new<'a>(
+        sockets: &mut SocketSet<'a>,
+        rx_buffer: SocketBuffer<'a>,
+        tx_buffer: SocketBuffer<'a>,
+        mac_addr: EthernetAddress,
+        now: Instant,
+        bus_number: BusNumber,
+        data_rate: u16,
+    ) -> Self {
+        let mut inner = Server::new(
+            sockets, rx_buffer, tx_buffer, mac_addr, now, bus_number, data_rate,
+        );
+        inner.tagged = true;
+
+        Self {
+            inner,
+            rx_buf: [0; HEADER_LEN + MAX_WIRE_LEN],
+            rx_len: 0,
+        }
+    }
+
+    /// Listen for a client, send the header once connected and send
+    /// heartbeats, see [`Server::poll`].
+    pub fn poll(&mut self, sockets: &mut SocketSet, now: Instant) -> PollEvent {
+        let event = self.inner.poll(sockets, now);
+        if !self.inner.rx_start {
+            self.rx_len = 0;
+        }
+
+        event
+    }
+
+    /// Whether a client is connected and the header has been sent.
+    pub fn is_ready(&self, sockets: &SocketSet) -> bool {
+        self.inner.is_ready(sockets)
+    }
+
+    /// Traffic counters since creation or the last reset.
+    pub fn stats(&self) -> &Stats {
+        self.inner.stats()
+    }
+
+    /// Send a CAN frame tagged with `bus_number`.
+    ///
+    /// Frames aren't queued, returns [`Error::WouldBlock`] if the send
+    /// buffer is full.
+    pub fn send_frame(
+        &mut self,
+        sockets: &mut SocketSet,
+        bus_number: BusNumber,
+        frame: &impl embedded_can::Frame,
+    ) -> Result<(), Error> {
+        let socket = sockets.get_mut::<Socket>(self.inner.handle);
+        let frame =
+            Frame::from_frame(frame).map_err(|_| Error::FrameTooLong)?;
+        let inner = &mut self.inner;
+
+        if !socket.is_active() || !inner.tx_start || inner.listen_only {
+            return Err(Error::NotConnected);
+        }
+
+        if !inner.admit(&frame) {
+            return Ok(());
+        }
+
+        if send_room(socket) == 0 {
+            return Err(Error::WouldBlock);
+        }
+
+        let mut buf = [0; HEADER_LEN + MAX_WIRE_LEN];
+        let len = inner.encode_packet(bus_number, &frame, &mut buf);
+        inner.write_bytes(socket, &buf[..len])?;
+        inner.stats.sent(1, len);
+        inner.rate_window_sent = inner.rate_window_sent.saturating_add(1);
+        inner.record(Direction::Tx, &frame);
+        Ok(())
+    }
+
+    /// Receive a CAN frame along with the bus number it was tagged with.
+    ///
+    /// Heartbeats and settings frames are skipped, as by
+    /// [`Server::recv_frame`].
+    pub fn recv_frame_with_bus(
+        &mut self,
+        sockets: &mut SocketSet,
+    ) -> Result<Option<(BusNumber, Frame)>, Error> {
+        let socket = sockets.get_mut::<Socket>(self.inner.handle);
+        let inner = &mut self.inner;
+
+        if !socket.can_recv() || !inner.recv_header(socket)? {
+            return Ok(None);
+        }
+
+        let packet_len = HEADER_LEN + inner.wire_len();
+
+        loop {
+            let rx_buf = &mut self.rx_buf[self.rx_len..packet_len];
+            let len = socket.recv_slice(rx_buf)?;
+            self.rx_len += len;
+            Stats::count(&mut inner.stats.bytes_received, len);
+
+            if self.rx_len < packet_len {
+                return Ok(None);
+            }
+
+            self.rx_len = 0;
+            Stats::count(&mut inner.stats.frames_received, 1);
+
+            let (header, frame) =
+                self.rx_buf[..packet_len].split_at(HEADER_LEN);
+            let bus_number = match Codec::decode_header(header) {
+                Ok((Some(header), _)) => {
+                    BusNumber::try_from(header.bus_number()).unwrap_or_default()
+                }
+                _ => {
+                    #[cfg(feature = "defmt-03")]
+                    defmt::warn!("Malformed frame header");
+                    Stats::count(&mut inner.stats.malformed_frames, 1);
+                    socket.abort();
+                    return Err(Error::Malformed);
+                }
+            };
+
+            match inner.decode(frame) {
+                Ok((Some(frame), _)) => {
+                    if let Some(ReceivedItem::Data(frame)) =
+                        inner.accept(frame, bus_number)
+                    {
+                        return Ok(Some((bus_number, frame)));
+                    }
+                }
+                Ok((None, _)) => return Ok(None),
+                #[cfg(feature = "frame-crc")]
+                Err(CodecError::BadCrc) => {
+                    Stats::count(&mut inner.stats.crc_errors, 1);
+                }
+                Err(_) => {
+                    Stats::count(&mut inner.stats.malformed_frames, 1);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::clock::MockClock;
+    #[cfg(feature = "async")]
+    use crate::harness::WakeFlag;
+    use crate::harness::{Harness, MockTransport, LOCALHOST, MAC_ADDR};
+    use core::mem::size_of;
+    use embedded_can::{Frame as CanFrame, Id, StandardId};
+    use tritiumcan::{
+        datagram::{FrameBitfield, Header, Heartbeat},
+        Flags, PROTOCOL_VERSION,
+    };
+    use zerocopy::{FromBytes, FromZeroes};
+
+    /// Header a well-behaved peer sends on connect.
+    fn peer_header() -> Header {
+        let mut header = Header::new();
+        header.set_version(PROTOCOL_VERSION);
+        header.set_bus_number(BusNumber::default().into());
+        header.set_client_identifier(0x1234);
+        header
+    }
+
+    #[test]
+    fn connection_state() {
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 1024);
+        assert_eq!(server.state(&harness.sockets), ConnectionState::Closed);
+
+        harness.run(1, |sockets, now| server.poll(sockets, now));
+        assert_eq!(server.state(&harness.sockets), ConnectionState::Listening);
+        assert!(!server.is_connected(&harness.sockets));
+
+        let client = harness.tcp_client();
+        harness.run(10, |sockets, now| server.poll(sockets, now));
+        assert_eq!(server.state(&harness.sockets), ConnectionState::Connected);
+        assert!(server.is_connected(&harness.sockets));
+
+        harness.tcp_socket(client).close();
+        harness.run(1, |_, _| {});
+        assert_eq!(server.state(&harness.sockets), ConnectionState::Closing);
+    }
+
+    #[test]
+    fn custom_port() {
+        let mut harness = Harness::new();
+        let mut default = harness.tcp_server(1024, 1024);
+        let mut custom = harness.tcp_server(1024, 1024).with_port(PORT + 1);
+        assert_eq!(custom.port(), PORT + 1);
+
+        harness.run(1, |sockets, now| {
+            default.poll(sockets, now);
+            custom.poll(sockets, now);
+        });
+        assert_eq!(custom.state(&harness.sockets), ConnectionState::Listening);
+
+        harness.tcp_client_to(PORT + 1, 49153);
+        harness.run(10, |sockets, now| {
+            default.poll(sockets, now);
+            custom.poll(sockets, now);
+        });
+        assert!(custom.is_connected(&harness.sockets));
+        assert!(!default.is_connected(&harness.sockets));
+        let local = harness.tcp_socket(custom.handle).local_endpoint();
+        assert_eq!(local.unwrap().port, PORT + 1);
+    }
+
+    #[test]
+    fn poll_events() {
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 1024);
+        let mut events = Vec::new();
+        let mut poll = |harness: &mut Harness, steps| {
+            harness.run(steps, |sockets, now| match server.poll(sockets, now) {
+                PollEvent::None => {}
+                event => events.push(event),
+            })
+        };
+
+        poll(&mut harness, 1);
+        let client = harness.tcp_client();
+        poll(&mut harness, 10);
+        harness.tcp_socket(client).close();
+        poll(&mut harness, 10);
+
+        assert_eq!(
+            events,
+            [PollEvent::ClientConnected, PollEvent::ClientDisconnected]
+        );
+    }
+
+    #[test]
+    fn reconnect() {
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 1024);
+        let mut events = Vec::new();
+        harness.run(1, |sockets, now| server.poll(sockets, now));
+
+        // a graceful close then a reset, each followed by a new client
+        for (local_port, abort) in [(49152, false), (49153, true)] {
+            let client = harness.tcp_client_to(PORT, local_port);
+            harness
+                .run(10, |sockets, now| events.push(server.poll(sockets, now)));
+            assert_eq!(
+                server.state(&harness.sockets),
+                ConnectionState::Connected
+            );
+
+            let socket = harness.tcp_socket(client);
+            if abort {
+                socket.abort();
+            } else {
+                socket.close();
+            }
+            harness
+                .run(10, |sockets, now| events.push(server.poll(sockets, now)));
+            assert_eq!(
+                server.state(&harness.sockets),
+                ConnectionState::Listening
+            );
+        }
+
+        let client = harness.tcp_client_to(PORT, 49154);
+        harness.run(10, |sockets, now| events.push(server.poll(sockets, now)));
+        assert!(harness.tcp_socket(client).may_recv());
+        assert!(server.is_connected(&harness.sockets));
+
+        events.retain(|&event| event != PollEvent::None);
+        assert_eq!(
+            events,
+            [
+                PollEvent::ClientConnected,
+                PollEvent::ClientDisconnected,
+                PollEvent::ClientConnected,
+                PollEvent::ClientDisconnected,
+                PollEvent::ClientConnected,
+            ]
+        );
+    }
+
+    #[test]
+    fn disconnect() {
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 1024);
+        harness.run(1, |sockets, now| server.poll(sockets, now));
+        let client = harness.tcp_client_to(PORT, 49152);
+        harness.run(10, |sockets, now| server.poll(sockets, now));
+
+        server.disconnect(&mut harness.sockets);
+        let mut events = Vec::new();
+        harness.run(10, |sockets, now| events.push(server.poll(sockets, now)));
+        assert!(!events.contains(&PollEvent::ClientDisconnected));
+
+        // reset straight to closed, without a FIN
+        assert_eq!(harness.tcp_socket(client).state(), State::Closed);
+        assert_eq!(server.state(&harness.sockets), ConnectionState::Listening);
+
+        harness.tcp_client_to(PORT, 49153);
+        harness.run(10, |sockets, now| server.poll(sockets, now));
+        assert!(server.is_connected(&harness.sockets));
+    }
+
+    #[test]
+    fn close() {
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 1024);
+        harness.run(1, |sockets, now| server.poll(sockets, now));
+        let client = harness.tcp_client_to(PORT, 49152);
+        harness.run(10, |sockets, now| server.poll(sockets, now));
+
+        let frame: Frame =
+            CanFrame::new(StandardId::new(0x123).unwrap(), &[1]).unwrap();
+        server.send_frame(&mut harness.sockets, &frame).unwrap();
+        server.close(&mut harness.sockets);
+        harness.run(10, |sockets, now| server.poll(sockets, now));
+
+        // the client still gets the frame, then the FIN
+        let socket = harness.tcp_socket(client);
+        assert_eq!(socket.state(), State::CloseWait);
+        let mut buf = [0; 64];
+        let len = socket.recv_slice(&mut buf).unwrap();
+        assert_eq!(len, size_of::<Packet>() + FRAME_LEN);
+
+        socket.close();
+        let mut events = Vec::new();
+        harness.run(10, |sockets, now| events.push(server.poll(sockets, now)));
+        assert!(events.contains(&PollEvent::ClientDisconnected));
+        assert_eq!(server.state(&harness.sockets), ConnectionState::Listening);
+
+        harness.tcp_client_to(PORT, 49153);
+        harness.run(10, |sockets, now| server.poll(sockets, now));
+        assert!(server.is_connected(&harness.sockets));
+    }
+
+    #[test]
+    fn buffer_levels() {
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 1024);
+        harness.run(1, |sockets, now| server.poll(sockets, now));
+        let client = harness.tcp_client();
+        // long enough for the delayed ack of the header packet
+        harness.run(30, |sockets, now| server.poll(sockets, now));
+        assert_eq!(server.send_capacity(&harness.sockets), 1024);
+        assert_eq!(server.recv_queue(&harness.sockets), 0);
+
+        let frame: Frame =
+            CanFrame::new(StandardId::new(0x123).unwrap(), &[1]).unwrap();
+        server.send_frame(&mut harness.sockets, &frame).unwrap();
+        assert_eq!(server.send_capacity(&harness.sockets), 1024 - FRAME_LEN);
+        harness.run(30, |_, _| {});
+        assert_eq!(server.send_capacity(&harness.sockets), 1024);
+
+        let socket = harness.tcp_socket(client);
+        socket.send_slice(peer_header().as_bytes()).unwrap();
+        socket.send_slice(frame.as_bytes()).unwrap();
+        harness.run(10, |_, _| {});
+        assert_eq!(server.recv_queue(&harness.sockets), HEADER_LEN + FRAME_LEN);
+
+        server.recv_frame(&mut harness.sockets).unwrap().unwrap();
+        assert_eq!(server.recv_queue(&harness.sockets), 0);
+    }
+
+    #[test]
+    fn server_buffers() {
+        let mut harness = Harness::new();
+        let buffers: &'static mut ServerBuffers =
+            Box::leak(Box::new(ServerBuffers::new()));
+        let (rx_buffer, tx_buffer) = buffers.socket_buffers();
+        let mut server = Server::try_new(
+            &mut harness.sockets,
+            rx_buffer,
+            tx_buffer,
+            MAC_ADDR,
+            harness.now,
+            BusNumber::default(),
+            500,
+        )
+        .unwrap();
+        harness.run(1, |sockets, now| server.poll(sockets, now));
+        let client = harness.tcp_client();
+        harness.run(30, |sockets, now| server.poll(sockets, now));
+        assert_eq!(server.send_capacity(&harness.sockets), DEFAULT_BUFFER_LEN);
+
+        let frame: Frame =
+            CanFrame::new(StandardId::new(0x123).unwrap(), &[1, 2]).unwrap();
+        server.send_frame(&mut harness.sockets, &frame).unwrap();
+        let socket = harness.tcp_socket(client);
+        socket.send_slice(peer_header().as_bytes()).unwrap();
+        socket.send_slice(frame.as_bytes()).unwrap();
+        harness.run(10, |_, _| {});
+
+        let mut buf = [0; 64];
+        let len = harness.tcp_socket(client).recv_slice(&mut buf).unwrap();
+        assert_eq!(&buf[size_of::<Packet>()..len], frame.as_bytes());
+        assert_eq!(server.recv_frame(&mut harness.sockets), Ok(Some(frame)));
+    }
+
+    #[cfg(feature = "frame-crc")]
+    #[test]
+    fn frame_crc() {
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 1024);
+        server.set_frame_crc(true);
+        let client = harness.connect(&mut server);
+
+        let frame =
+            || Frame::new(StandardId::new(0x123).unwrap(), &[1, 2]).unwrap();
+        let mut good = [0; FRAME_LEN + CRC_LEN];
+        Codec::encode_frame_crc(&frame(), &mut good).unwrap();
+        let mut bad = good;
+        bad[FRAME_LEN] ^= 0xFF;
+
+        let socket = harness.tcp_socket(client);
+        socket.send_slice(peer_header().as_bytes()).unwrap();
+        socket.send_slice(&good).unwrap();
+        socket.send_slice(&bad).unwrap();
+        socket.send_slice(&good).unwrap();
+        harness.run(10, |_, _| {});
+
+        let mut frames: [Frame; 4] = Default::default();
+        assert_eq!(
+            server.recv_frames(&mut harness.sockets, &mut frames),
+            Ok(2)
+        );
+        assert_eq!(frames[1], frame());
+        assert_eq!(server.stats().crc_errors, 1);
+        assert_eq!(server.stats().malformed_frames, 0);
+
+        // the header packet's frame carries a CRC too
+        server.send_frame(&mut harness.sockets, &frame()).unwrap();
+        harness.run(10, |_, _| {});
+        let mut buf = [0; 64];
+        let socket = harness.tcp_socket(client);
+        let len = socket.recv_slice(&mut buf).unwrap();
+        assert_eq!(len, HEADER_LEN + 2 * (FRAME_LEN + CRC_LEN));
+        assert_eq!(buf[len - CRC_LEN - FRAME_LEN..len], good);
+    }
+
+    #[cfg(feature = "frame-crc")]
+    #[test]
+    fn frame_crc_disabled() {
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 1024);
+        let client = harness.connect(&mut server);
+
+        let frame: Frame =
+            CanFrame::new(StandardId::new(0x123).unwrap(), &[1, 2]).unwrap();
+        server.send_frame(&mut harness.sockets, &frame).unwrap();
+        harness.run(10, |_, _| {});
+
+        let mut buf = [0; 64];
+        let len = harness.tcp_socket(client).recv_slice(&mut buf).unwrap();
+        assert_eq!(len, size_of::<Packet>() + FRAME_LEN);
+        assert_eq!(buf[size_of::<Packet>()..len], *frame.as_bytes());
+    }
+
+    #[test]
+    fn recv_timed_frames() {
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 1024);
+        let client = harness.connect(&mut server);
+
+        let frame =
+            || Frame::new(StandardId::new(0x123).unwrap(), &[1]).unwrap();
+        let socket = harness.tcp_socket(client);
+        socket.send_slice(peer_header().as_bytes()).unwrap();
+        for _ in 0..3 {
+            socket.send_slice(frame().as_bytes()).unwrap();
+        }
+        harness.run(10, |_, _| {});
+
+        let at = Instant::from_secs(5);
+        server.poll(&mut harness.sockets, at);
+        let timed = server.recv_timed_frame(&mut harness.sockets).unwrap();
+        assert_eq!(timed, Some(TimedFrame { frame: frame(), at }));
+
+        let mut out: [TimedFrame; 4] = Default::default();
+        let count = server.recv_timed_frames(&mut harness.sockets, &mut out);
+        assert_eq!(count, Ok(2));
+        assert!(out[..2].iter().all(|timed| timed.at == at));
+    }
+
+    #[test]
+    fn is_ready() {
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 1024);
+        harness.run(1, |sockets, now| server.poll(sockets, now));
+        let client = harness.tcp_client();
+
+        // established before the next poll sends the header
+        harness.run(10, |_, _| {});
+        assert!(server.is_connected(&harness.sockets));
+        assert!(!server.is_ready(&harness.sockets));
+
+        server.poll(&mut harness.sockets, harness.now);
+        assert!(server.is_ready(&harness.sockets));
+
+        harness.tcp_socket(client).abort();
+        harness.run(10, |_, _| {});
+        assert!(!server.is_ready(&harness.sockets));
+    }
+
+    #[test]
+    fn client_identifier() {
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 1024);
+        server.set_client_identifier(0x0011_2233_4455_6677);
+        let client = harness.connect(&mut server);
+
+        let mut buf = [0; 64];
+        let len = harness.tcp_socket(client).recv_slice(&mut buf).unwrap();
+        assert_eq!(len, size_of::<Packet>());
+        // truncated to the 56 bits on the wire
+        assert_eq!(buf[8..16], [0, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77]);
+        let header = Header::parse(&buf).unwrap();
+        assert_eq!(header.client_identifier(), 0x0011_2233_4455_6677);
+    }
+
+    #[test]
+    fn try_new_buffer_size() {
+        let mut harness = Harness::new();
+        let try_new = |harness: &mut Harness, rx_len, tx_len| {
+            Server::try_new(
+                &mut harness.sockets,
+                Harness::tcp_buffer(rx_len),
+                Harness::tcp_buffer(tx_len),
+                MAC_ADDR,
+                harness.now,
+                BusNumber::default(),
+                500,
+            )
+            .map(|_| ())
+        };
+
+        assert_eq!(
+            try_new(&mut harness, FRAME_LEN, 1024),
+            Err(ConfigError::RxBufferTooSmall)
+        );
+        assert_eq!(
+            try_new(&mut harness, 1024, MIN_BUFFER_LEN - 1),
+            Err(ConfigError::TxBufferTooSmall)
+        );
+        assert_eq!(
+            try_new(&mut harness, MIN_BUFFER_LEN, MIN_BUFFER_LEN),
+            Ok(())
+        );
+        // rejected buffers never reach the socket set
+        assert_eq!(harness.sockets.iter().count(), 1);
+
+        let mut server = Server::try_new(
+            &mut harness.sockets,
+            Harness::tcp_buffer(MIN_BUFFER_LEN),
+            Harness::tcp_buffer(MIN_BUFFER_LEN),
+            MAC_ADDR,
+            harness.now,
+            BusNumber::default(),
+            500,
+        )
+        .unwrap();
+        harness.run(1, |sockets, now| server.poll(sockets, now));
+        let client = harness.tcp_client();
+        harness.run(30, |sockets, now| server.poll(sockets, now));
+        let mut buf = [0; 64];
+        let len = harness.tcp_socket(client).recv_slice(&mut buf).unwrap();
+        assert_eq!(len, size_of::<Packet>());
+    }
+
+    #[test]
+    fn poll_readiness() {
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 1024);
+        let mut readiness = Vec::new();
+        harness.run(1, |sockets, now| {
+            readiness.push(server.poll_readiness(sockets, now))
+        });
+        assert!(readiness[0].is_idle());
+
+        let client = harness.tcp_client();
+        readiness.clear();
+        harness.run(10, |sockets, now| {
+            readiness.push(server.poll_readiness(sockets, now))
+        });
+        let connected = readiness.iter().find(|r| !r.is_idle()).unwrap();
+        assert_eq!(connected.event, PollEvent::ClientConnected);
+        assert!(connected.sent);
+        assert!(readiness.last().unwrap().is_idle());
+
+        let socket = harness.tcp_socket(client);
+        socket.send_slice(peer_header().as_bytes()).unwrap();
+        harness.run(10, |sockets, now| server.poll(sockets, now));
+        let readiness =
+            server.poll_readiness(&mut harness.sockets, harness.now);
+        assert!(readiness.recv);
+        assert!(!readiness.sent);
+        assert!(!readiness.is_idle());
+
+        // idle once everything has been read
+        assert_eq!(server.recv_frame(&mut harness.sockets), Ok(None));
+        let readiness =
+            server.poll_readiness(&mut harness.sockets, harness.now);
+        assert!(readiness.is_idle());
+
+        let frame: Frame =
+            CanFrame::new(StandardId::new(0x123).unwrap(), &[1]).unwrap();
+        harness
+            .tcp_socket(client)
+            .send_slice(frame.as_bytes())
+            .unwrap();
+        harness.run(10, |sockets, now| server.poll(sockets, now));
+        let readiness =
+            server.poll_readiness(&mut harness.sockets, harness.now);
+        assert!(readiness.recv);
+    }
+
+    #[test]
+    fn header_forward() {
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 1024);
+        server.set_forward(true);
+        let client = harness.connect(&mut server);
+
+        let mut buf = [0; 64];
+        let len = harness.tcp_socket(client).recv_slice(&mut buf).unwrap();
+        assert_eq!(len, size_of::<Packet>());
+        let header = Header::parse(&buf).unwrap();
+        assert!(header.forward());
+        assert_eq!(header.client_identifier(), 0);
+    }
+
+    #[test]
+    fn header_data_rate() {
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 1024);
+        let client = harness.connect(&mut server);
+
+        let mut buf = [0; 64];
+        let len = harness.tcp_socket(client).recv_slice(&mut buf).unwrap();
+        assert_eq!(len, size_of::<Packet>());
+
+        let packet = Packet::read_from(&buf[..len]).unwrap();
+        assert_eq!(packet.frame.as_bytes()[6..8], 500u16.to_be_bytes());
+        let heartbeat = Packet::parse_heartbeat(&buf[..len]).unwrap();
+        assert_eq!(heartbeat.data_rate, 500);
+        assert_eq!(heartbeat.mac_addr, MAC_ADDR.0);
+    }
+
+    #[test]
+    fn listen_only() {
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 1024);
+        server.set_listen_only(true);
+        server.set_heartbeat_interval(Duration::from_millis(10));
+        harness.run(1, |sockets, now| server.poll(sockets, now));
+        let client = harness.tcp_client();
+        let mut events = Vec::new();
+        harness.run(100, |sockets, now| events.push(server.poll(sockets, now)));
+        assert!(events.contains(&PollEvent::ClientConnected));
+        assert!(!server.is_ready(&harness.sockets));
+
+        let frame: Frame =
+            CanFrame::new(StandardId::new(0x123).unwrap(), &[1]).unwrap();
+        assert_eq!(
+            server.send_frame(&mut harness.sockets, &frame),
+            Err(Error::NotConnected)
+        );
+        assert_eq!(
+            server.send_heartbeat(&mut harness.sockets),
+            Err(Error::NotConnected)
+        );
+
+        let socket = harness.tcp_socket(client);
+        socket.send_slice(peer_header().as_bytes()).unwrap();
+        socket.send_slice(frame.as_bytes()).unwrap();
+        harness.run(100, |sockets, now| server.poll(sockets, now));
+        assert_eq!(server.recv_frame(&mut harness.sockets), Ok(Some(frame)));
+
+        assert_eq!(harness.tcp_socket(client).recv_queue(), 0);
+        assert_eq!(server.stats().bytes_sent, 0);
+        assert_eq!(server.stats().heartbeats_sent, 0);
+    }
+
+    #[test]
+    fn heartbeat_interval() {
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 1024);
+        server.set_heartbeat_interval(Duration::from_millis(500));
+        harness.run(1, |sockets, now| server.poll(sockets, now));
+        let client = harness.tcp_client();
+        harness.run(1100, |sockets, now| server.poll(sockets, now));
+
+        let mut buf = [0; 256];
+        let len = harness.tcp_socket(client).recv_slice(&mut buf).unwrap();
+        let heartbeats = buf[size_of::<Packet>()..len]
+            .chunks(size_of::<Frame>())
+            .filter_map(Frame::read_from)
+            .filter(|frame| frame.flags() == Flags::Heartbeat.bits())
+            .count();
+
+        assert_eq!(heartbeats, 2);
+    }
+
+    #[test]
+    fn send_frame_before_connect() {
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 1024);
+        harness.run(10, |sockets, now| server.poll(sockets, now));
+
+        let frame: Frame =
+            CanFrame::new(StandardId::new(0x123).unwrap(), &[1, 2, 3]).unwrap();
+
+        assert_eq!(
+            server.send_frame(&mut harness.sockets, &frame),
+            Err(Error::NotConnected)
+        );
+    }
+
+    #[test]
+    fn send_frame_when_connected() {
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 1024);
+        let client = harness.connect(&mut server);
+
+        let frame: Frame =
+            CanFrame::new(StandardId::new(0x123).unwrap(), &[1, 2, 3]).unwrap();
+
+        assert_eq!(server.send_frame(&mut harness.sockets, &frame), Ok(()));
+
+        harness.run(10, |_, _| {});
+
+        let mut buf = [0; 64];
+        let len = harness.tcp_socket(client).recv_slice(&mut buf).unwrap();
+        assert_eq!(len, size_of::<Packet>() + size_of::<Frame>());
+        assert_eq!(
+            &buf[size_of::<Packet>()..len],
+            Frame::from_frame(&frame).unwrap().as_bytes()
+        );
+    }
+
+    #[test]
+    fn send_frame_as() {
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 1024);
+        let client = harness.connect(&mut server);
+
+        let frame: Frame =
+            CanFrame::new(StandardId::new(0x123).unwrap(), &[1, 2, 3]).unwrap();
+        let sockets = &mut harness.sockets;
+        // the connection's own client identifier needs no header
+        server.send_frame_as(sockets, 0, &frame).unwrap();
+        server.send_frame_as(sockets, 7, &frame).unwrap();
+        server.send_frame_as(sockets, 7, &frame).unwrap();
+        server.send_frame_as(sockets, 0, &frame).unwrap();
+        harness.run(10, |_, _| {});
+
+        let mut buf = [0; 256];
+        let len = harness.tcp_socket(client).recv_slice(&mut buf).unwrap();
+        let mut bytes = &buf[size_of::<Packet>()..len];
+        let mut next = |n: usize| {
+            let (head, rest) = bytes.split_at(n);
+            bytes = rest;
+            head
+        };
+        assert_eq!(next(FRAME_LEN), frame.as_bytes());
+        assert_eq!(
+            Header::parse(next(HEADER_LEN)).unwrap().client_identifier(),
+            7
+        );
+        assert_eq!(next(FRAME_LEN), frame.as_bytes());
+        assert_eq!(next(FRAME_LEN), frame.as_bytes());
+        assert_eq!(
+            Header::parse(next(HEADER_LEN)).unwrap().client_identifier(),
+            0
+        );
+        assert_eq!(next(FRAME_LEN), frame.as_bytes());
+        assert!(bytes.is_empty());
+    }
+
+    #[test]
+    fn send_frame_as_dropped() {
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 1024);
+        let client = harness.connect(&mut server);
+
+        let frame: Frame =
+            CanFrame::new(StandardId::new(0x123).unwrap(), &[1, 2, 3]).unwrap();
+        let sockets = &mut harness.sockets;
+        server.set_tx_filter(IdFilter::from_slice(&[(0x200, 0x7FF)]).unwrap());
+        assert_eq!(server.send_frame_as(sockets, 7, &frame), Ok(()));
+        server.set_tx_filter(IdFilter::default());
+        server.set_tx_rate_limit(Some(0));
+        assert_eq!(server.send_frame_as(sockets, 9, &frame), Ok(()));
+        assert_eq!(server.stats().frames_filtered, 1);
+        assert_eq!(server.stats().frames_rate_limited, 1);
+        harness.run(10, |_, _| {});
+
+        let mut buf = [0; 256];
+        let len = harness.tcp_socket(client).recv_slice(&mut buf).unwrap();
+        assert_eq!(len, size_of::<Packet>());
+    }
+
+    #[test]
+    fn tx_filter() {
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 1024);
+        server.set_tx_filter(IdFilter::from_slice(&[(0x120, 0x7F0)]).unwrap());
+        let client = harness.connect(&mut server);
+
+        let frames: [Frame; 4] = [0x121, 0x200, 0x12F, 0x300].map(|id| {
+            CanFrame::new(StandardId::new(id).unwrap(), &[1]).unwrap()
+        });
+
+        assert_eq!(server.send_frame(&mut harness.sockets, &frames[0]), Ok(()));
+        assert_eq!(server.send_frame(&mut harness.sockets, &frames[1]), Ok(()));
+        assert_eq!(server.send_frames(&mut harness.sockets, &frames), Ok(4));
+        assert_eq!(server.stats().frames_sent, 3);
+        assert_eq!(server.stats().frames_filtered, 3);
+
+        harness.run(10, |_, _| {});
+
+        let mut buf = [0; 128];
+        let len = harness.tcp_socket(client).recv_slice(&mut buf).unwrap();
+        let expected: Vec<u8> = [&frames[0], &frames[0], &frames[2]]
+            .iter()
+            .flat_map(|frame| frame.as_bytes().to_vec())
+            .collect();
+        assert_eq!(&buf[size_of::<Packet>()..len], &expected[..]);
+    }
+
+    #[test]
+    fn send_settings() {
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 1024);
+        assert_eq!(
+            server.send_settings(&mut harness.sockets, 250),
+            Err(Error::NotConnected)
+        );
+
+        let client = harness.connect(&mut server);
+        server.send_settings(&mut harness.sockets, 250).unwrap();
+        harness.run(10, |_, _| {});
+
+        let mut buf = [0; 64];
+        let len = harness.tcp_socket(client).recv_slice(&mut buf).unwrap();
+        let frame = Frame::read_from(&buf[size_of::<Packet>()..len]).unwrap();
+        assert_eq!(frame.settings_data_rate(), Some(250));
+    }
+
+    #[test]
+    fn mock_handshake() {
+        let mut server = Harness::new().tcp_server(1024, 1024);
+        let now = Instant::ZERO;
+        let mut mock = MockTransport::new(State::Listen);
+        assert_eq!(server.poll_socket(&mut mock, now), PollEvent::None);
+        assert!(mock.tx.is_empty());
+
+        mock.state = State::Established;
+        assert_eq!(
+            server.poll_socket(&mut mock, now),
+            PollEvent::ClientConnected
+        );
+        assert_eq!(mock.tx.len(), size_of::<Packet>());
+        let header = Header::parse(&mock.tx).unwrap();
+        assert_eq!(header.bus_number(), BusNumber::default().into());
+        assert_eq!(server.poll_socket(&mut mock, now), PollEvent::None);
+
+        // the peer's header and a frame split across reads
+        let frame: Frame =
+            CanFrame::new(StandardId::new(0x123).unwrap(), &[1, 2]).unwrap();
+        let (head, tail) = frame.as_bytes().split_at(5);
+        mock.rx.extend(peer_header().as_bytes());
+        mock.rx.extend(head);
+        assert_eq!(server.recv_item_on(&mut mock), Ok(None));
+        assert_eq!(server.peer_client_id(), Some(0x1234));
+
+        mock.rx.extend(tail);
+        let item = server.recv_item_on(&mut mock).unwrap();
+        assert!(
+            matches!(item, Some(ReceivedItem::Data(data)) if data == frame)
+        );
+
+        mock.tx.clear();
+        let bytes = frame.as_bytes().to_vec();
+        server.send_frame_on(&mut mock, frame).unwrap();
+        assert_eq!(mock.tx, bytes);
+
+        mock.state = State::CloseWait;
+        assert_eq!(
+            server.poll_socket(&mut mock, now),
+            PollEvent::ClientDisconnected
+        );
+        assert_eq!(mock.state, State::LastAck);
+        assert_eq!(server.peer_client_id(), None);
+    }
+
+    #[test]
+    fn mock_endianness() {
+        let mut server = Harness::new().tcp_server(1024, 1024);
+        server.set_endianness(Endianness::Little);
+        let now = Instant::ZERO;
+        let mut mock = MockTransport::new(State::Established);
+        server.poll_socket(&mut mock, now);
+        mock.tx.clear();
+
+        let frame: Frame =
+            CanFrame::new(StandardId::new(0x123).unwrap(), &[1, 2]).unwrap();
+        let mut swapped = frame.as_bytes().to_vec();
+        swapped[..4].reverse();
+        assert_ne!(swapped, frame.as_bytes());
+
+        server
+            .send_frame_on(&mut mock, FrameBitfield(frame.0))
+            .unwrap();
+        assert_eq!(mock.tx, swapped);
+
+        mock.rx.extend(peer_header().as_bytes());
+        mock.rx.extend(&swapped);
+        let item = server.recv_item_on(&mut mock).unwrap();
+        assert!(
+            matches!(item, Some(ReceivedItem::Data(data)) if data == frame)
+        );
+    }
+
+    #[test]
+    fn mock_timeout() {
+        let mut server = Harness::new().tcp_server(1024, 1024);
+        let mut now = Instant::ZERO;
+        let mut mock = MockTransport::new(State::Established);
+        assert_eq!(
+            server.poll_socket(&mut mock, now),
+            PollEvent::ClientConnected
+        );
+        mock.rx.extend(peer_header().as_bytes());
+        server.recv_item_on(&mut mock).unwrap();
+        assert_eq!(server.peer_client_id(), Some(0x1234));
+
+        // the header is never acknowledged
+        for _ in 0..31 {
+            now += Duration::from_millis(100);
+            assert_eq!(server.poll_socket(&mut mock, now), PollEvent::None);
+        }
+        mock.state = State::Closed;
+        assert_eq!(server.poll_socket(&mut mock, now), PollEvent::TimedOut);
+        assert!(!server.tx_start);
+        assert_eq!(server.peer_client_id(), None);
+
+        // a reset soon after everything was acknowledged
+        mock.state = State::Established;
+        mock.tx.clear();
+        server.poll_socket(&mut mock, now);
+        mock.tx.clear();
+        now += Duration::from_millis(100);
+        assert_eq!(server.poll_socket(&mut mock, now), PollEvent::None);
+        mock.state = State::Closed;
+        assert_eq!(
+            server.poll_socket(&mut mock, now),
+            PollEvent::ClientDisconnected
+        );
+    }
+
+    #[test]
+    fn mock_send_queue() {
+        let mut server = Harness::new().tcp_server(1024, 1024);
+        let now = Instant::ZERO;
+        let mut mock = MockTransport::new(State::Established);
+        mock.capacity = size_of::<Packet>() + FRAME_LEN;
+        server.poll_socket(&mut mock, now);
+
+        let frame = |id| -> Frame {
+            CanFrame::new(StandardId::new(id).unwrap(), &[1]).unwrap()
+        };
+        server.send_frame_on(&mut mock, frame(1)).unwrap();
+        server.send_frame_on(&mut mock, frame(2)).unwrap();
+        assert_eq!(server.pending_len(), 1);
+        assert_eq!(mock.tx[size_of::<Packet>()..], *frame(1).as_bytes());
+
+        // acknowledged, the next poll writes the queued frame
+        mock.tx.clear();
+        server.poll_socket(&mut mock, now);
+        assert_eq!(server.pending_len(), 0);
+        assert_eq!(mock.tx, frame(2).as_bytes());
+    }
+
+    #[test]
+    fn tx_rate_limit() {
+        let mut server = Harness::new().tcp_server(1024, 1024);
+        let mut mock = MockTransport::new(State::Established);
+        let mut now = Instant::ZERO;
+        server.poll_socket(&mut mock, now);
+        server.set_tx_rate_limit(Some(3));
+
+        let frame = |id| -> Frame {
+            CanFrame::new(StandardId::new(id).unwrap(), &[1]).unwrap()
+        };
+        for id in 0..5 {
+            server.send_frame_on(&mut mock, frame(id)).unwrap();
+        }
+        assert_eq!(server.stats().frames_sent, 3);
+        assert_eq!(server.stats().frames_rate_limited, 2);
+
+        // still within the window
+        now += Duration::from_millis(500);
+        server.poll_socket(&mut mock, now);
+        let frames = [frame(5), frame(6)];
+        assert_eq!(server.send_frames_on(&mut mock, &frames), Ok(2));
+        assert_eq!(server.stats().frames_sent, 3);
+        assert_eq!(server.stats().frames_rate_limited, 4);
+
+        // a new window, the batch is cut short at the limit
+        now += Duration::from_millis(500);
+        server.poll_socket(&mut mock, now);
+        mock.tx.clear();
+        let frames: [Frame; 5] = core::array::from_fn(|n| frame(n as u16));
+        assert_eq!(server.send_frames_on(&mut mock, &frames), Ok(5));
+        assert_eq!(server.stats().frames_sent, 6);
+        assert_eq!(server.stats().frames_rate_limited, 6);
+        assert_eq!(mock.tx.len(), 3 * FRAME_LEN);
+        assert_eq!(server.send_frame_on(&mut mock, frame(7)), Ok(()));
+        assert_eq!(server.stats().frames_rate_limited, 7);
+
+        server.set_tx_rate_limit(None);
+        server.send_frame_on(&mut mock, frame(8)).unwrap();
+        assert_eq!(server.stats().frames_sent, 7);
+    }
+
+    #[test]
+    fn mock_would_block() {
+        let mut server = Harness::new().tcp_server(1024, 1024);
+        let mut mock = MockTransport::new(State::Established);
+        mock.capacity = size_of::<Packet>() + 5;
+        server.poll_socket(&mut mock, Instant::ZERO);
+
+        // only part of the frame fits, the heartbeat has to wait for the rest
+        let frame: Frame =
+            CanFrame::new(StandardId::new(0x123).unwrap(), &[1]).unwrap();
+        server.send_frame_on(&mut mock, frame).unwrap();
+        assert_eq!(server.write_heartbeat(&mut mock), Err(Error::WouldBlock));
+
+        mock.tx.clear();
+        assert_eq!(server.write_heartbeat(&mut mock), Ok(()));
+    }
+
+    #[test]
+    fn mock_malformed_header() {
+        let mut server = Harness::new().tcp_server(1024, 1024);
+        let mut mock = MockTransport::new(State::Established);
+        server.poll_socket(&mut mock, Instant::ZERO);
+
+        mock.rx.extend([0; size_of::<Header>()]);
+        assert_eq!(server.recv_item_on(&mut mock), Err(Error::Malformed));
+        assert_eq!(mock.state, State::Closed);
+        assert_eq!(server.stats().malformed_frames, 1);
+    }
+
+    #[test]
+    fn heartbeat_schedule() {
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 1024);
+        server.set_heartbeat_interval(Duration::from_millis(100));
+
+        // the server only sees the mock clock, stopped while connecting
+        let clock = MockClock::new(harness.now);
+        harness.run(1, |sockets, _| server.poll_clock(sockets, &clock));
+        let _client = harness.tcp_client();
+        harness.run(10, |sockets, _| server.poll_clock(sockets, &clock));
+        assert_eq!(server.stats().heartbeats_sent, 0);
+
+        let mut sent_at = Vec::new();
+        for _ in 0..350 {
+            clock.advance(Duration::from_millis(1));
+            server.poll_clock(&mut harness.sockets, &clock);
+            if server.stats().heartbeats_sent as usize > sent_at.len() {
+                sent_at.push(clock.now().total_millis());
+            }
+            harness.run(1, |_, _| {});
+        }
+
+        // sent once the interval has been exceeded
+        assert_eq!(sent_at, [101, 202, 303]);
+    }
+
+    #[test]
+    fn rtt() {
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 1024);
+        assert_eq!(
+            server.timeout(&harness.sockets),
+            Some(Duration::from_secs(3))
+        );
+
+        harness.run(1, |sockets, now| server.poll(sockets, now));
+        let _client = harness.tcp_client();
+        harness.run(2, |sockets, now| server.poll(sockets, now));
+        assert_eq!(server.rtt(), None);
+
+        // the header is acknowledged after the peer's delayed ACK
+        harness.run(30, |sockets, now| server.poll(sockets, now));
+        let rtt = server.rtt().unwrap();
+        assert!(rtt > Duration::ZERO && rtt < Duration::from_millis(30));
+
+        server
+            .send_frame(&mut harness.sockets, &Frame::default())
+            .unwrap();
+        harness.run(30, |sockets, now| server.poll(sockets, now));
+        assert!(server.rtt().is_some());
+    }
+
+    #[test]
+    fn keep_alive() {
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 1024);
+        let handle = server.handle;
+        let keep_alive = |harness: &Harness| {
+            harness.sockets.get::<Socket>(handle).keep_alive()
+        };
+        assert_eq!(keep_alive(&harness), None);
+
+        server
+            .set_keep_alive(&mut harness.sockets, Some(Duration::from_secs(1)));
+        assert_eq!(keep_alive(&harness), Some(Duration::from_secs(1)));
+
+        server.set_keep_alive(&mut harness.sockets, None);
+        assert_eq!(keep_alive(&harness), None);
+    }
+
+    #[test]
+    fn reconfigure() {
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 1024);
+        let bus_number = BusNumber::try_from(3).unwrap();
+        // nothing to send before a client connects
+        server
+            .reconfigure(&mut harness.sockets, BusNumber::default(), 500)
+            .unwrap();
+
+        let client = harness.connect(&mut server);
+        server
+            .reconfigure(&mut harness.sockets, bus_number, 250)
+            .unwrap();
+        server.send_heartbeat(&mut harness.sockets).unwrap();
+        harness.run(10, |_, _| {});
+
+        let mut buf = [0; 64];
+        let len = harness.tcp_socket(client).recv_slice(&mut buf).unwrap();
+        assert_eq!(len, size_of::<Packet>() + 2 * FRAME_LEN);
+        let frames = &buf[size_of::<Packet>()..len];
+        let settings = Frame::read_from(&frames[..FRAME_LEN]).unwrap();
+        assert_eq!(settings.settings_data_rate(), Some(250));
+        let heartbeat = Frame::read_from(&frames[FRAME_LEN..]).unwrap();
+        let heartbeat = Heartbeat::from_frame(&heartbeat, bus_number).unwrap();
+        assert_eq!(heartbeat.data_rate, 250);
+
+        // the next connection's header has the new bus number
+        harness.tcp_socket(client).close();
+        harness.run(10, |sockets, now| server.poll(sockets, now));
+        let client = harness.tcp_client_to(PORT, 49153);
+        harness.run(10, |sockets, now| server.poll(sockets, now));
+        let len = harness.tcp_socket(client).recv_slice(&mut buf).unwrap();
+        let header = Header::parse(&buf[..len]).unwrap();
+        assert_eq!(header.bus_number(), 3);
+    }
+
+    #[test]
+    fn send_frames_single_write() {
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 1024);
+        let client = harness.connect(&mut server);
+
+        // discard the header
+        let mut buf = [0; 128];
+        harness.tcp_socket(client).recv_slice(&mut buf).unwrap();
+
+        let frames: [Frame; 3] = core::array::from_fn(|n| {
+            CanFrame::new(StandardId::new(n as u16).unwrap(), &[n as u8])
+                .unwrap()
+        });
+
+        let handle = server.handle;
+        let queued = |harness: &Harness| {
+            harness.sockets.get::<Socket>(handle).send_queue()
+        };
+        let before = queued(&harness);
+        assert_eq!(server.send_frames(&mut harness.sockets, &frames), Ok(3));
+        assert_eq!(queued(&harness) - before, 3 * FRAME_LEN);
+
+        harness.run(10, |_, _| {});
+        let len = harness.tcp_socket(client).recv_slice(&mut buf).unwrap();
+        assert_eq!(len, 3 * FRAME_LEN);
+        for (chunk, frame) in buf[..len].chunks(FRAME_LEN).zip(&frames) {
+            assert_eq!(chunk, frame.as_bytes());
+        }
+    }
+
+    #[test]
+    fn send_frames_wrap() {
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 1024);
+        harness.run(1, |sockets, now| server.poll(sockets, now));
+        let client = harness.tcp_client();
+        harness.run(20, |sockets, now| server.poll(sockets, now));
+
+        let frames: [Frame; 200] = core::array::from_fn(|n| {
+            CanFrame::new(StandardId::new(n as u16).unwrap(), &[n as u8])
+                .unwrap()
+        });
+
+        assert_eq!(server.send_frames(&mut harness.sockets, &frames), Ok(73));
+
+        // the unread header keeps the client from taking the last 28 bytes,
+        // leaving 2 bytes free before the end of the ring buffer and the
+        // acknowledged space after the wrap
+        harness.run(200, |_, _| {});
+        let handle = server.handle;
+        assert_eq!(harness.sockets.get::<Socket>(handle).send_queue(), 28);
+        let rest = &frames[73..];
+        assert_eq!(server.send_frames(&mut harness.sockets, rest), Ok(71));
+
+        let mut received = Vec::new();
+        let mut buf = [0; 1024];
+        for _ in 0..10 {
+            let socket = harness.tcp_socket(client);
+            let len = socket.recv_slice(&mut buf).unwrap();
+            received.extend_from_slice(&buf[..len]);
+            harness.run(100, |_, _| {});
+        }
+        assert_eq!(received.len(), size_of::<Packet>() + 144 * FRAME_LEN);
+        let received = &received[size_of::<Packet>()..];
+        for (chunk, frame) in received.chunks(FRAME_LEN).zip(&frames) {
+            assert_eq!(chunk, frame.as_bytes());
+        }
+    }
+
+    #[test]
+    fn send_frames_max_segment() {
+        let mut server = Harness::new().tcp_server(1024, 1024);
+        let mut mock = MockTransport::new(State::Established);
+        server.poll_socket(&mut mock, Instant::ZERO);
+        mock.writes.clear();
+        mock.tx.clear();
+
+        let frames: [Frame; 10] = core::array::from_fn(|n| {
+            CanFrame::new(StandardId::new(n as u16).unwrap(), &[n as u8])
+                .unwrap()
+        });
+        // not a whole number of frames, rounded down to 4
+        server.set_max_segment(4 * FRAME_LEN + 3);
+        assert_eq!(server.send_frames_on(&mut mock, &frames), Ok(10));
+        assert_eq!(mock.writes, [4 * FRAME_LEN, 4 * FRAME_LEN, 2 * FRAME_LEN]);
+        for (chunk, frame) in mock.tx.chunks(FRAME_LEN).zip(&frames) {
+            assert_eq!(chunk, frame.as_bytes());
+        }
+
+        // a batch only partly fitting stops once the buffer is full
+        mock.writes.clear();
+        mock.tx.clear();
+        mock.capacity = 5 * FRAME_LEN;
+        assert_eq!(server.send_frames_on(&mut mock, &frames), Ok(5));
+        assert_eq!(mock.writes, [4 * FRAME_LEN, FRAME_LEN, 0]);
+        // a full buffer isn't a disconnect
+        assert!(!mock.can_send());
+        assert_eq!(server.send_frames_on(&mut mock, &frames), Ok(0));
+    }
+
+    #[test]
+    fn flush() {
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 64);
+        let handle = server.handle;
+        assert_eq!(
+            server.flush(&mut harness.sockets),
+            Err(Error::NotConnected)
+        );
+
+        server.set_nagle_enabled(&mut harness.sockets, false);
+        assert!(!harness.sockets.get::<Socket>(handle).nagle_enabled());
+
+        harness.run(1, |sockets, now| server.poll(sockets, now));
+        let _client = harness.tcp_client();
+        harness.run(30, |sockets, now| server.poll(sockets, now));
+
+        for n in 0..6 {
+            let frame: Frame =
+                CanFrame::new(StandardId::new(n).unwrap(), &[1]).unwrap();
+            server.send_frame(&mut harness.sockets, &frame).unwrap();
+        }
+        // the fifth frame only partly fit
+        assert_eq!(server.pending_len(), 1);
+
+        // acknowledged without polling the server, so nothing is flushed
+        harness.run(30, |_, _| {});
+        assert_eq!(server.pending_len(), 1);
+
+        // the rest of the fifth frame and the sixth
+        assert_eq!(server.flush(&mut harness.sockets), Ok(0));
+        assert_eq!(
+            harness.sockets.get::<Socket>(handle).send_queue(),
+            5 * FRAME_LEN - 64 + FRAME_LEN
+        );
+        assert_eq!(server.stats().frames_sent, 6);
+    }
+
+    #[test]
+    fn send_frame_partial_write() {
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 40);
+        harness.run(1, |sockets, now| server.poll(sockets, now));
+        let client = harness.tcp_client();
+        harness.run(30, |sockets, now| server.poll(sockets, now));
+        let mut received = Vec::new();
+        let mut buf = [0; 1024];
+        let len = harness.tcp_socket(client).recv_slice(&mut buf).unwrap();
+        assert_eq!(len, size_of::<Packet>());
+
+        // 40 bytes fit two frames and 12 bytes of a third
+        let frames: Vec<Frame> = (0..4)
+            .map(|n| CanFrame::new(StandardId::new(n).unwrap(), &[n as u8; 8]))
+            .map(Option::unwrap)
+            .collect();
+        for frame in &frames {
+            server.send_frame(&mut harness.sockets, frame).unwrap();
+        }
+        server.send_heartbeat(&mut harness.sockets).unwrap_err();
+        assert_eq!(server.pending_len(), 1);
+
+        for _ in 0..100 {
+            harness.run(1, |sockets, now| server.poll(sockets, now));
+            let len = harness.tcp_socket(client).recv_slice(&mut buf).unwrap();
+            received.extend_from_slice(&buf[..len]);
+        }
+
+        let expected: Vec<u8> = frames
+            .iter()
+            .flat_map(|frame| frame.as_bytes().to_vec())
+            .collect();
+        assert_eq!(received, expected);
+        assert_eq!(server.stats().frames_sent, 4);
+    }
+
+    #[test]
+    fn send_frame_queue_drains() {
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 64);
+        let client = harness.connect(&mut server);
+
+        let frames: Vec<Frame> = (0..PENDING_LEN + 4)
+            .map(|n| {
+                CanFrame::new(StandardId::new(n as u16).unwrap(), &[n as u8])
+                    .unwrap()
+            })
+            .collect();
+
+        let mut sent = 0;
+        for frame in &frames {
+            match server.send_frame(&mut harness.sockets, frame) {
+                Ok(()) => sent += 1,
+                Err(err) => {
+                    assert_eq!(err, Error::QueueFull);
+                    break;
+                }
+            }
+        }
+        assert_eq!(server.pending_len(), PENDING_LEN);
+
+        let mut received = Vec::new();
+        for _ in 0..500 {
+            harness.run(1, |sockets, now| server.poll(sockets, now));
+            let mut buf = [0; 1024];
+            let socket = harness.tcp_socket(client);
+            let len = socket.recv_slice(&mut buf).unwrap();
+            received.extend_from_slice(&buf[..len]);
+        }
+        assert_eq!(server.pending_len(), 0);
+
+        let expected: Vec<u8> = frames[..sent]
+            .iter()
+            .flat_map(|frame| frame.as_bytes().to_vec())
+            .collect();
+        assert_eq!(&received[size_of::<Packet>()..], &expected[..]);
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn send_frame_async_waits_for_room() {
+        use core::{future::Future, pin::pin, task::Context};
+
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 64);
+        harness.connect(&mut server);
+
+        let fill: [Frame; 3] = Default::default();
+        while server.send_frames(&mut harness.sockets, &fill).unwrap() > 0 {}
+
+        let (flag, waker) = WakeFlag::waker();
+        let mut cx = Context::from_waker(&waker);
+        let sockets = harness.share_sockets();
+
+        let frame: Frame =
+            CanFrame::new(StandardId::new(0x123).unwrap(), &[1]).unwrap();
+        let mut future = pin!(server.send_frame_async(&sockets, &frame));
+        assert!(future.as_mut().poll(&mut cx).is_pending());
+
+        // let the client acknowledge what has been sent so far
+        harness.with_sockets(&sockets, |harness| harness.run(50, |_, _| {}));
+
+        assert!(flag.take());
+        assert_eq!(future.as_mut().poll(&mut cx), Poll::Ready(Ok(())));
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn client_connect_async() {
+        use core::{future::Future, pin::pin, task::Context};
+
+        let mut harness = Harness::new();
+        let peer = harness.tcp_listener(PORT);
+        let client = |harness: &mut Harness, port| {
+            Client::new(
+                harness.tcp_server(1024, 1024),
+                (LOCALHOST, port).into(),
+                49152,
+            )
+        };
+        let mut client_ok = client(&mut harness, PORT);
+        let mut client_refused = client(&mut harness, PORT + 1);
+
+        let (flag, waker) = WakeFlag::waker();
+        let mut cx = Context::from_waker(&waker);
+        let clock = MockClock::new(harness.now);
+        let iface = harness.share_iface();
+        let sockets = harness.share_sockets();
+
+        let mut future =
+            pin!(client_ok.connect_async(&iface, &sockets, &clock));
+        assert!(future.as_mut().poll(&mut cx).is_pending());
+        let mut refused =
+            pin!(client_refused.connect_async(&iface, &sockets, &clock));
+        assert!(refused.as_mut().poll(&mut cx).is_pending());
+
+        // the handshake completes as the interface runs
+        for _ in 0..10 {
+            harness.with_shared(&iface, &sockets, |harness| {
+                harness.run(1, |_, _| {})
+            });
+            clock.set(harness.now);
+        }
+
+        assert!(flag.take());
+        assert_eq!(future.as_mut().poll(&mut cx), Poll::Ready(Ok(())));
+        assert_eq!(
+            refused.as_mut().poll(&mut cx),
+            Poll::Ready(Err(ConnectError::Refused))
+        );
+
+        harness.with_shared(&iface, &sockets, |harness| {
+            harness.run(10, |_, _| {});
+            let mut buf = [0; 64];
+            let len = harness.tcp_socket(peer).recv_slice(&mut buf).unwrap();
+            assert_eq!(len, size_of::<Packet>());
+        });
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn recv_frame_async_waits_for_whole_frame() {
+        use core::{future::Future, pin::pin, task::Context};
+
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 1024);
+        let client = harness.connect(&mut server);
+
+        let frame: Frame =
+            CanFrame::new(StandardId::new(0x123).unwrap(), &[1, 2, 3]).unwrap();
+        let (first, second) = frame.as_bytes().split_at(5);
+
+        let socket = harness.tcp_socket(client);
+        socket.send_slice(peer_header().as_bytes()).unwrap();
+        socket.send_slice(first).unwrap();
+        harness.run(10, |_, _| {});
+
+        let (flag, waker) = WakeFlag::waker();
+        let mut cx = Context::from_waker(&waker);
+        let sockets = harness.share_sockets();
+
+        let mut future = pin!(server.recv_frame_async(&sockets));
+        assert!(future.as_mut().poll(&mut cx).is_pending());
+
+        harness.with_sockets(&sockets, |harness| {
+            harness.tcp_socket(client).send_slice(second).unwrap();
+            harness.run(10, |_, _| {});
+        });
+
+        assert!(flag.take());
+        assert_eq!(future.as_mut().poll(&mut cx), Poll::Ready(Ok(frame)));
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn frame_stream() {
+        use core::{pin::pin, task::Context};
+        use futures_core::Stream;
+
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 1024);
+        let client = harness.connect(&mut server);
+
+        let frames: [Frame; 3] = core::array::from_fn(|i| {
+            CanFrame::new(StandardId::new(i as u16).unwrap(), &[i as u8])
+                .unwrap()
+        });
+
+        let socket = harness.tcp_socket(client);
+        socket.send_slice(peer_header().as_bytes()).unwrap();
+        for frame in &frames {
+            socket.send_slice(frame.as_bytes()).unwrap();
+        }
+        harness.run(10, |_, _| {});
+
+        let (flag, waker) = WakeFlag::waker();
+        let mut cx = Context::from_waker(&waker);
+        let sockets = harness.share_sockets();
+
+        let mut stream = pin!(server.frame_stream(&sockets));
+        for frame in frames {
+            let next = stream.as_mut().poll_next(&mut cx);
+            assert_eq!(next, Poll::Ready(Some(frame)));
+        }
+        assert!(stream.as_mut().poll_next(&mut cx).is_pending());
+
+        harness.with_sockets(&sockets, |harness| {
+            harness.tcp_socket(client).close();
+            harness.run(10, |_, _| {});
+        });
+
+        assert!(flag.take());
+        assert_eq!(stream.as_mut().poll_next(&mut cx), Poll::Ready(None));
+        assert_eq!(stream.as_mut().poll_next(&mut cx), Poll::Ready(None));
+    }
+
+    #[test]
+    fn stats_count_traffic() {
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 1024);
+        let client = harness.connect(&mut server);
+
+        let frame: Frame =
+            CanFrame::new(StandardId::new(0x123).unwrap(), &[1, 2, 3]).unwrap();
+        server.send_frame(&mut harness.sockets, &frame).unwrap();
+        server.send_heartbeat(&mut harness.sockets).unwrap();
+
+        let socket = harness.tcp_socket(client);
+        socket.send_slice(peer_header().as_bytes()).unwrap();
+        socket.send_slice(frame.as_bytes()).unwrap();
+        socket.send_slice(&frame.as_bytes()[..4]).unwrap();
+        harness.run(10, |_, _| {});
+        while server.recv_frame(&mut harness.sockets).unwrap().is_some() {}
+
+        assert_eq!(
+            *server.stats(),
+            Stats {
+                frames_sent: 1,
+                frames_received: 1,
+                heartbeats_sent: 1,
+                bytes_sent: (size_of::<Packet>() + 2 * FRAME_LEN) as u32,
+                bytes_received: (size_of::<Header>() + FRAME_LEN + 4) as u32,
+                malformed_frames: 0,
+                frames_filtered: 0,
+                frames_deduped: 0,
+                frames_rate_limited: 0,
+                #[cfg(feature = "frame-crc")]
+                crc_errors: 0,
+            }
+        );
+
+        harness.tcp_socket(client).close();
+        harness.run(10, |sockets, now| server.poll(sockets, now));
+        assert_eq!(server.stats().malformed_frames, 1);
+
+        server.reset_stats();
+        assert_eq!(*server.stats(), Stats::default());
+    }
+
+    #[test]
+    fn recv_before_header_sent() {
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 1024);
+        harness.run(1, |sockets, now| server.poll(sockets, now));
+
+        // the client connects and sends straight away, before the server
+        // polls and sends its own header
+        let client = harness.tcp_client();
+        harness.run(10, |_, _| {});
+        let frame: Frame =
+            CanFrame::new(StandardId::new(0x123).unwrap(), &[1, 2]).unwrap();
+        let socket = harness.tcp_socket(client);
+        socket.send_slice(peer_header().as_bytes()).unwrap();
+        socket.send_slice(frame.as_bytes()).unwrap();
+        harness.run(10, |_, _| {});
+        assert!(!server.is_ready(&harness.sockets));
+
+        assert_eq!(server.recv_frame(&mut harness.sockets), Ok(Some(frame)));
+        assert_eq!(server.peer_client_id(), Some(0x1234));
+        assert!(server.peer_alive(harness.now));
+
+        let mut events = Vec::new();
+        harness.run(10, |sockets, now| events.push(server.poll(sockets, now)));
+        assert_eq!(events[0], PollEvent::ClientConnected);
+        assert_eq!(server.peer_client_id(), Some(0x1234));
+    }
+
+    #[test]
+    fn mock_reset_before_header_sent() {
+        let mut server = Harness::new().tcp_server(1024, 1024);
+        let mut mock = MockTransport::new(State::SynReceived);
+        let frame: Frame =
+            CanFrame::new(StandardId::new(0x123).unwrap(), &[1, 2]).unwrap();
+
+        // the peer's header and half a frame, then a reset before this side
+        // got to send its header
+        mock.rx.extend(peer_header().as_bytes());
+        mock.rx.extend(&frame.as_bytes()[..5]);
+        assert_eq!(server.recv_item_on(&mut mock), Ok(None));
+        mock.state = State::Closed;
+        assert_eq!(
+            server.poll_socket(&mut mock, Instant::ZERO),
+            PollEvent::None
+        );
+        assert_eq!(server.stats().malformed_frames, 1);
+
+        // the next connection starts with its header again
+        mock.state = State::Established;
+        mock.rx.clear();
+        mock.rx.extend(peer_header().as_bytes());
+        mock.rx.extend(frame.as_bytes());
+        let item = server.recv_item_on(&mut mock).unwrap();
+        assert!(
+            matches!(item, Some(ReceivedItem::Data(data)) if data == frame)
+        );
+    }
+
+    #[test]
+    fn recv_frame_split_across_reads() {
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 1024);
+        let client = harness.connect(&mut server);
+
+        let header = peer_header();
+        harness
+            .tcp_socket(client)
+            .send_slice(header.as_bytes())
+            .unwrap();
+        harness.run(10, |_, _| {});
+        assert!(server.recv_frame(&mut harness.sockets).unwrap().is_none());
+
+        let frame: Frame =
+            CanFrame::new(StandardId::new(0x123).unwrap(), &[1, 2, 3]).unwrap();
+        let (first, second) = frame.as_bytes().split_at(5);
+
+        harness.tcp_socket(client).send_slice(first).unwrap();
+        harness.run(10, |_, _| {});
+        assert!(server.recv_frame(&mut harness.sockets).unwrap().is_none());
+
+        harness.tcp_socket(client).send_slice(second).unwrap();
+        harness.run(10, |_, _| {});
+        let received = server.recv_frame(&mut harness.sockets).unwrap();
+        assert_eq!(received.unwrap().as_bytes(), frame.as_bytes());
+    }
+
+    #[test]
+    fn peek_frame() {
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 1024);
+        let client = harness.connect(&mut server);
+
+        let heartbeat =
+            Packet::new_heartbeat(&[1; 6], &BusNumber::default(), &500);
+        let frame = |id| -> Frame {
+            CanFrame::new(StandardId::new(id).unwrap(), &[1, 2]).unwrap()
+        };
+        let first = frame(0x123);
+        let (head, tail) = first.as_bytes().split_at(5);
+
+        let socket = harness.tcp_socket(client);
+        socket.send_slice(peer_header().as_bytes()).unwrap();
+        socket.send_slice(heartbeat.frame.as_bytes()).unwrap();
+        socket.send_slice(head).unwrap();
+        harness.run(10, |_, _| {});
+        assert_eq!(server.recv_frame(&mut harness.sockets), Ok(None));
+        assert_eq!(server.peek_frame(&mut harness.sockets), Ok(None));
+
+        let socket = harness.tcp_socket(client);
+        socket.send_slice(tail).unwrap();
+        socket.send_slice(frame(0x456).as_bytes()).unwrap();
+        harness.run(10, |_, _| {});
+
+        for _ in 0..2 {
+            let peeked = server.peek_frame(&mut harness.sockets).unwrap();
+            assert_eq!(peeked.map(|frame| frame.id()), Some(first.id()));
+        }
+        assert_eq!(server.recv_frame(&mut harness.sockets), Ok(Some(first)));
+
+        let peeked = server.peek_frame(&mut harness.sockets).unwrap();
+        assert_eq!(peeked.map(Frame::from), Some(frame(0x456)));
+        assert_eq!(
+            server.recv_frame(&mut harness.sockets),
+            Ok(Some(frame(0x456)))
+        );
+        assert_eq!(server.peek_frame(&mut harness.sockets), Ok(None));
+        assert_eq!(server.stats().frames_received, 3);
+    }
+
+    #[test]
+    fn recv_frames_drains_buffer() {
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 1024);
+        let client = harness.connect(&mut server);
+
+        let header = peer_header();
+        harness
+            .tcp_socket(client)
+            .send_slice(header.as_bytes())
+            .unwrap();
+
+        let frames: [Frame; 5] = core::array::from_fn(|i| {
+            CanFrame::new(StandardId::new(i as u16).unwrap(), &[i as u8])
+                .unwrap()
+        });
+        for frame in &frames {
+            harness
+                .tcp_socket(client)
+                .send_slice(frame.as_bytes())
+                .unwrap();
+        }
+        // a partial sixth frame stays staged
+        harness
+            .tcp_socket(client)
+            .send_slice(&frames[0].as_bytes()[..4])
+            .unwrap();
+        harness.run(10, |_, _| {});
+
+        let mut out: [Frame; 8] = Default::default();
+        let count = server.recv_frames(&mut harness.sockets, &mut out).unwrap();
+        assert_eq!(count, 5);
+        assert_eq!(out[..count], frames);
+        assert_eq!(server.recv_frames(&mut harness.sockets, &mut out), Ok(0));
+    }
+
+    #[test]
+    fn recv_window() {
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(512, 1024);
+        let client = harness.connect(&mut server);
+        assert_eq!(server.recv_window(&harness.sockets), 512);
+
+        harness
+            .tcp_socket(client)
+            .send_slice(peer_header().as_bytes())
+            .unwrap();
+        harness.run(10, |_, _| {});
+        assert_eq!(server.recv_window(&harness.sockets), 512 - HEADER_LEN);
+
+        server.recv_frame(&mut harness.sockets).unwrap();
+        assert_eq!(server.recv_window(&harness.sockets), 512);
+    }
+
+    #[test]
+    fn available_frames() {
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 1024);
+        let client = harness.connect(&mut server);
+        assert_eq!(server.available_frames(&harness.sockets), 0);
+
+        let frame: Frame =
+            CanFrame::new(StandardId::new(0x123).unwrap(), &[1, 2]).unwrap();
+        let socket = harness.tcp_socket(client);
+        socket.send_slice(peer_header().as_bytes()).unwrap();
+        for _ in 0..3 {
+            socket.send_slice(frame.as_bytes()).unwrap();
+        }
+        socket
+            .send_slice(&frame.as_bytes()[..FRAME_LEN / 2])
+            .unwrap();
+        harness.run(10, |_, _| {});
+        assert_eq!(server.available_frames(&harness.sockets), 3);
+
+        let mut out: [Frame; 2] = Default::default();
+        server.recv_frames(&mut harness.sockets, &mut out).unwrap();
+        assert_eq!(server.available_frames(&harness.sockets), 1);
+
+        // the staged half counts once the rest arrives
+        server.recv_frames(&mut harness.sockets, &mut out).unwrap();
+        harness
+            .tcp_socket(client)
+            .send_slice(&frame.as_bytes()[FRAME_LEN / 2..])
+            .unwrap();
+        harness.run(10, |_, _| {});
+        assert_eq!(server.available_frames(&harness.sockets), 1);
+    }
+
+    #[cfg(feature = "heapless")]
+    #[test]
+    fn recv_into() {
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 1024);
+        let client = harness.connect(&mut server);
+
+        let frame = |id| -> Frame {
+            CanFrame::new(StandardId::new(id).unwrap(), &[id as u8]).unwrap()
+        };
+        let socket = harness.tcp_socket(client);
+        socket.send_slice(peer_header().as_bytes()).unwrap();
+        for id in 0..5 {
+            socket.send_slice(frame(id).as_bytes()).unwrap();
+        }
+        harness.run(10, |_, _| {});
+
+        let mut out = heapless::Vec::<Frame, 3>::new();
+        assert_eq!(server.recv_into(&mut harness.sockets, &mut out), Ok(3));
+        assert_eq!(out, [frame(0), frame(1), frame(2)]);
+        assert_eq!(server.recv_into(&mut harness.sockets, &mut out), Ok(0));
+        assert_eq!(server.recv_queue(&harness.sockets), 2 * FRAME_LEN);
+
+        out.clear();
+        assert_eq!(server.recv_into(&mut harness.sockets, &mut out), Ok(2));
+        assert_eq!(out, [frame(3), frame(4)]);
+    }
+
+    #[test]
+    fn rx_filter() {
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 1024);
+        server.set_rx_filter(IdFilter::from_slice(&[(0x120, 0x7F0)]).unwrap());
+        let client = harness.connect(&mut server);
+
+        let frames: [Frame; 5] =
+            [0x200, 0x121, 0x300, 0x301, 0x12F].map(|id| {
+                CanFrame::new(StandardId::new(id).unwrap(), &[1]).unwrap()
+            });
+
+        let socket = harness.tcp_socket(client);
+        socket.send_slice(peer_header().as_bytes()).unwrap();
+        for frame in &frames {
+            socket.send_slice(frame.as_bytes()).unwrap();
+        }
+        harness.run(10, |_, _| {});
+
+        let mut out: [Frame; 8] = Default::default();
+        let count = server.recv_frames(&mut harness.sockets, &mut out).unwrap();
+        assert_eq!(count, 2);
+        assert_eq!(out[0], frames[1]);
+        assert_eq!(out[1], frames[4]);
+        assert_eq!(server.stats().frames_received, 5);
+        assert_eq!(server.stats().frames_filtered, 3);
+    }
+
+    #[test]
+    fn dedup_window() {
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 1024);
+        server.set_dedup_window(Some(Duration::from_millis(50)));
+        let client = harness.connect(&mut server);
+
+        let frame = |data| -> Frame {
+            CanFrame::new(StandardId::new(0x123).unwrap(), &[data]).unwrap()
+        };
+
+        let socket = harness.tcp_socket(client);
+        socket.send_slice(peer_header().as_bytes()).unwrap();
+        for data in [1, 1, 2, 1] {
+            socket.send_slice(frame(data).as_bytes()).unwrap();
+        }
+        harness.run(10, |sockets, now| server.poll(sockets, now));
+
+        let mut out: [Frame; 8] = Default::default();
+        let count = server.recv_frames(&mut harness.sockets, &mut out).unwrap();
+        assert_eq!(out[..count], [frame(1), frame(2), frame(1)]);
+        assert_eq!(server.stats().frames_deduped, 1);
+
+        // the same frame again once the window has passed
+        harness.run(60, |sockets, now| server.poll(sockets, now));
+        let socket = harness.tcp_socket(client);
+        socket.send_slice(frame(1).as_bytes()).unwrap();
+        harness.run(10, |sockets, now| server.poll(sockets, now));
+        let received = server.recv_frame(&mut harness.sockets).unwrap();
+        assert_eq!(received, Some(frame(1)));
+        assert_eq!(server.stats().frames_deduped, 1);
+    }
+
+    #[test]
+    fn capture_recent_frames() {
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 1024);
+        let client = harness.connect(&mut server);
+
+        let frame = |id| -> Frame {
+            CanFrame::new(StandardId::new(id).unwrap(), &[id as u8]).unwrap()
+        };
+
+        let socket = harness.tcp_socket(client);
+        socket.send_slice(peer_header().as_bytes()).unwrap();
+        harness.run(10, |sockets, now| server.poll(sockets, now));
+        assert!(server.is_ready(&harness.sockets));
+
+        // off by default
+        server.send_frame(&mut harness.sockets, &frame(1)).unwrap();
+        assert_eq!(server.capture().count(), 0);
+
+        server.set_capture_enabled(true);
+        for id in 0..CAPTURE_LEN as u16 {
+            server.send_frame(&mut harness.sockets, &frame(id)).unwrap();
+        }
+        let socket = harness.tcp_socket(client);
+        for id in 100..104 {
+            socket.send_slice(frame(id).as_bytes()).unwrap();
+        }
+        harness.run(10, |sockets, now| server.poll(sockets, now));
+        let mut out: [Frame; 8] = Default::default();
+        let count = server.recv_frames(&mut harness.sockets, &mut out).unwrap();
+        assert_eq!(count, 4);
+        server
+            .send_frames(&mut harness.sockets, &[frame(200), frame(201)])
+            .unwrap();
+
+        let expected: Vec<_> = (6..CAPTURE_LEN as u16)
+            .map(|id| (Direction::Tx, frame(id)))
+            .chain((100..104).map(|id| (Direction::Rx, frame(id))))
+            .chain([200, 201].map(|id| (Direction::Tx, frame(id))))
+            .collect();
+        assert_eq!(server.capture().collect::<Vec<_>>(), expected);
+
+        server.set_capture_enabled(false);
+        assert_eq!(server.capture().count(), 0);
+    }
+
+    #[cfg(feature = "log")]
+    #[test]
+    fn log_trace_frames() {
+        use std::cell::RefCell;
+
+        std::thread_local! {
+            static RECORDS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+        }
+
+        /// Keeps trace records per thread so parallel tests don't mix.
+        struct CaptureLogger;
+
+        impl log::Log for CaptureLogger {
+            fn enabled(&self, metadata: &log::Metadata) -> bool {
+                metadata.level() == log::Level::Trace
+            }
+
+            fn log(&self, record: &log::Record) {
+                if self.enabled(record.metadata()) {
+                    let line = record.args().to_string();
+                    RECORDS.with(|records| records.borrow_mut().push(line));
+                }
+            }
+
+            fn flush(&self) {}
+        }
+
+        static LOGGER: CaptureLogger = CaptureLogger;
+        let _ = log::set_logger(&LOGGER);
+        log::set_max_level(log::LevelFilter::Trace);
+
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 1024);
+        let client = harness.connect(&mut server);
+
+        let frame = |id| -> Frame {
+            CanFrame::new(StandardId::new(id).unwrap(), &[1, 2]).unwrap()
+        };
+
+        let socket = harness.tcp_socket(client);
+        socket.send_slice(peer_header().as_bytes()).unwrap();
+        socket.send_slice(frame(0x456).as_bytes()).unwrap();
+        harness.run(10, |sockets, now| server.poll(sockets, now));
+
+        server
+            .send_frame(&mut harness.sockets, &frame(0x123))
+            .unwrap();
+        assert_eq!(
+            server.recv_frame(&mut harness.sockets),
+            Ok(Some(frame(0x456)))
+        );
+
+        let records = RECORDS.with(|records| records.take());
+        assert_eq!(records, ["Tx ID=0x123 [2] 01 02", "Rx ID=0x456 [2] 01 02"]);
+    }
+
+    #[test]
+    fn clear_rx() {
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 1024);
+        let client = harness.connect(&mut server);
+
+        let frame = |id| -> Frame {
+            CanFrame::new(StandardId::new(id).unwrap(), &[1, 2]).unwrap()
+        };
+
+        let socket = harness.tcp_socket(client);
+        socket.send_slice(peer_header().as_bytes()).unwrap();
+        socket.send_slice(frame(0).as_bytes()).unwrap();
+        socket.send_slice(&frame(1).as_bytes()[..5]).unwrap();
+        harness.run(10, |sockets, now| server.poll(sockets, now));
+
+        // the partial frame is staged by the receive
+        assert_eq!(server.recv_frame(&mut harness.sockets), Ok(Some(frame(0))));
+        assert_eq!(server.recv_frame(&mut harness.sockets), Ok(None));
+
+        let socket = harness.tcp_socket(client);
+        socket.send_slice(&frame(1).as_bytes()[5..]).unwrap();
+        for id in 2..5 {
+            socket.send_slice(frame(id).as_bytes()).unwrap();
+        }
+        harness.run(10, |sockets, now| server.poll(sockets, now));
+
+        let discarded = server.clear_rx(&mut harness.sockets).unwrap();
+        assert_eq!(discarded, 4 * FRAME_LEN);
+        assert_eq!(server.recv_frame(&mut harness.sockets), Ok(None));
+        assert_eq!(server.peer_client_id(), Some(0x1234));
+
+        // the next frame starts on a frame boundary
+        let socket = harness.tcp_socket(client);
+        socket.send_slice(frame(5).as_bytes()).unwrap();
+        harness.run(10, |sockets, now| server.poll(sockets, now));
+        assert_eq!(server.recv_frame(&mut harness.sockets), Ok(Some(frame(5))));
+    }
+
+    #[test]
+    fn clear_rx_partial() {
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 1024);
+        let client = harness.connect(&mut server);
+
+        let frame = |id| -> Frame {
+            CanFrame::new(StandardId::new(id).unwrap(), &[1, 2]).unwrap()
+        };
+
+        let socket = harness.tcp_socket(client);
+        socket.send_slice(peer_header().as_bytes()).unwrap();
+        socket.send_slice(&frame(1).as_bytes()[..5]).unwrap();
+        harness.run(10, |sockets, now| server.poll(sockets, now));
+
+        // the partial frame is staged and the socket left empty
+        assert_eq!(server.recv_frame(&mut harness.sockets), Ok(None));
+        assert_eq!(server.clear_rx(&mut harness.sockets), Ok(5));
+        assert_eq!(server.clear_rx(&mut harness.sockets), Ok(0));
+
+        let socket = harness.tcp_socket(client);
+        socket.send_slice(frame(2).as_bytes()).unwrap();
+        harness.run(10, |sockets, now| server.poll(sockets, now));
+        assert_eq!(server.recv_frame(&mut harness.sockets), Ok(Some(frame(2))));
+    }
+
+    #[test]
+    fn recv_invalid_dlc() {
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 1024);
+        let client = harness.connect(&mut server);
+
+        let mut bad: Frame =
+            CanFrame::new(StandardId::new(0x100).unwrap(), &[0; 8]).unwrap();
+        bad.set_dlc(9);
+        let good: Frame =
+            CanFrame::new(StandardId::new(0x200).unwrap(), &[1]).unwrap();
+
+        let socket = harness.tcp_socket(client);
+        socket.send_slice(peer_header().as_bytes()).unwrap();
+        socket.send_slice(bad.as_bytes()).unwrap();
+        socket.send_slice(good.as_bytes()).unwrap();
+        harness.run(10, |_, _| {});
+
+        let received = server.recv_frame(&mut harness.sockets).unwrap();
+        assert_eq!(received, Some(good));
+        assert_eq!(server.stats().malformed_frames, 1);
+    }
+
+    #[cfg(feature = "test-util")]
+    #[test]
+    fn inject_rx() {
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 1024);
+        let frame: Frame =
+            CanFrame::new(StandardId::new(0x123).unwrap(), &[1, 2]).unwrap();
+
+        // no connection needed
+        server.inject_rx(&frame).unwrap();
+        assert_eq!(server.recv_frame(&mut harness.sockets), Ok(Some(frame)));
+        assert_eq!(server.recv_frame(&mut harness.sockets), Ok(None));
+        assert_eq!(server.stats().frames_received, 1);
+
+        for _ in 0..PENDING_LEN {
+            server.inject_rx(&Frame::default()).unwrap();
+        }
+        assert!(server.inject_rx(&Frame::default()).is_err());
+    }
+
+    #[cfg(feature = "test-util")]
+    #[test]
+    fn inject_rx_peek() {
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 1024);
+        let client = harness.connect(&mut server);
+
+        let heartbeat =
+            Packet::new_heartbeat(&[1; 6], &BusNumber::default(), &500);
+        let frame = |id| -> Frame {
+            CanFrame::new(StandardId::new(id).unwrap(), &[1, 2]).unwrap()
+        };
+
+        let socket = harness.tcp_socket(client);
+        socket.send_slice(peer_header().as_bytes()).unwrap();
+        socket.send_slice(heartbeat.frame.as_bytes()).unwrap();
+        socket.send_slice(frame(2).as_bytes()).unwrap();
+        harness.run(10, |_, _| {});
+        server.inject_rx(&frame(1)).unwrap();
+
+        // the heartbeat is skipped and the injected frame left queued
+        let peeked = server.peek_frame(&mut harness.sockets).unwrap();
+        assert_eq!(peeked.unwrap().id(), frame(2).id());
+        assert_eq!(server.recv_frame(&mut harness.sockets), Ok(Some(frame(1))));
+        assert_eq!(server.recv_frame(&mut harness.sockets), Ok(Some(frame(2))));
+        assert_eq!(server.recv_frame(&mut harness.sockets), Ok(None));
+    }
+
+    #[test]
+    fn recv_heartbeat_item() {
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 1024);
+        let client = harness.connect(&mut server);
+
+        let heartbeat =
+            Packet::new_heartbeat(&[1; 6], &BusNumber::default(), &500);
+        let frame: Frame =
+            CanFrame::new(StandardId::new(0x123).unwrap(), &[1]).unwrap();
+
+        let socket = harness.tcp_socket(client);
+        socket.send_slice(peer_header().as_bytes()).unwrap();
+        for _ in 0..2 {
+            socket.send_slice(heartbeat.frame.as_bytes()).unwrap();
+            socket.send_slice(frame.as_bytes()).unwrap();
+        }
+        harness.run(10, |_, _| {});
+
+        let item = server.recv_item(&mut harness.sockets).unwrap();
+        assert_eq!(
+            item,
+            Some(ReceivedItem::Heartbeat(Heartbeat {
+                mac_addr: [1; 6],
+                bus_number: BusNumber::default(),
+                data_rate: 500,
+            }))
+        );
+        let item = server.recv_item(&mut harness.sockets).unwrap();
+        assert!(
+            matches!(item, Some(ReceivedItem::Data(data)) if data == frame)
+        );
+
+        // recv_frame skips the heartbeat
+        let received = server.recv_frame(&mut harness.sockets).unwrap();
+        assert_eq!(received, Some(frame));
+    }
+
+    #[test]
+    fn peer_alive() {
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 1024);
+        assert!(!server.peer_alive(harness.now));
+
+        let client = harness.connect(&mut server);
+        assert!(server.peer_alive(harness.now));
+
+        let socket = harness.tcp_socket(client);
+        socket.send_slice(peer_header().as_bytes()).unwrap();
+        harness.run(3100, |sockets, now| server.poll(sockets, now));
+        assert!(server.is_connected(&harness.sockets));
+        assert!(!server.peer_alive(harness.now));
+
+        let heartbeat =
+            Packet::new_heartbeat(&[1; 6], &BusNumber::default(), &500);
+        let socket = harness.tcp_socket(client);
+        socket.send_slice(heartbeat.frame.as_bytes()).unwrap();
+        harness.run(10, |sockets, now| server.poll(sockets, now));
+        while server.recv_item(&mut harness.sockets).unwrap().is_some() {}
+
+        assert!(server.peer_alive(harness.now));
+        assert!(!server.peer_alive(harness.now + Duration::from_millis(3100)));
+    }
+
+    #[test]
+    fn peer_header_fields() {
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 1024);
+        let client = harness.connect(&mut server);
+        assert_eq!(server.peer_bus_number(), None);
+        assert_eq!(server.peer_client_id(), None);
+
+        harness
+            .tcp_socket(client)
+            .send_slice(peer_header().as_bytes())
+            .unwrap();
+        harness.run(10, |_, _| {});
+        assert!(server.recv_frame(&mut harness.sockets).unwrap().is_none());
+        assert_eq!(server.peer_bus_number(), Some(BusNumber::default()));
+        assert_eq!(server.peer_client_id(), Some(0x1234));
+
+        harness.tcp_socket(client).close();
+        harness.run(10, |sockets, now| server.poll(sockets, now));
+        assert_eq!(server.peer_bus_number(), None);
+        assert_eq!(server.peer_client_id(), None);
+    }
+
+    #[test]
+    fn recv_malformed_header_aborts() {
+        let mut harness = Harness::new();
+        let mut server = harness.tcp_server(1024, 1024);
+        let client = harness.connect(&mut server);
+
+        // a whole packet with a zeroed header rather than a bare header
+        harness
+            .tcp_socket(client)
+            .send_slice(Packet::new_zeroed().as_bytes())
+            .unwrap();
+        harness.run(10, |_, _| {});
+
+        assert_eq!(
+            server.recv_frame(&mut harness.sockets),
+            Err(Error::Malformed)
+        );
+        assert_eq!(server.stats().malformed_frames, 1);
+        assert_eq!(server.state(&harness.sockets), ConnectionState::Closed);
+
+        let mut events = Vec::new();
+        harness.run(10, |sockets, now| events.push(server.poll(sockets, now)));
+        assert!(events.contains(&PollEvent::ClientDisconnected));
+        assert!(!harness.tcp_socket(client).is_active());
+    }
+
+    #[test]
+    fn strict_bus_check() {
+        let frame: Frame =
+            CanFrame::new(StandardId::new(0x123).unwrap(), &[1]).unwrap();
+
+        for (bus_number, accepted) in [(3, false), (13, true)] {
+            let mut harness = Harness::new();
+            let mut server = harness.tcp_server(1024, 1024);
+            server.set_strict_bus_check(true);
+            let client = harness.connect(&mut server);
+
+            let mut header = peer_header();
+            header.set_bus_number(bus_number);
+            let socket = harness.tcp_socket(client);
+            socket.send_slice(header.as_bytes()).unwrap();
+            socket.send_slice(frame.as_bytes()).unwrap();
+            harness.run(10, |_, _| {});
+
+            let received = server.recv_frame(&mut harness.sockets).unwrap();
+            assert_eq!(received.is_some(), accepted);
+            let state = server.state(&harness.sockets);
+            assert_eq!(state == ConnectionState::Closed, !accepted);
+        }
+    }
+
+    fn multi_server<const N: usize>(harness: &mut Harness) -> MultiServer<N> {
+        MultiServer::new(
+            &mut harness.sockets,
+            core::array::from_fn(|_| {
+                (Harness::tcp_buffer(1024), Harness::tcp_buffer(1024))
+            }),
+            MAC_ADDR,
+            harness.now,
+            BusNumber::default(),
+            500,
+        )
+    }
+
+    #[test]
+    fn multi_server_broadcast() {
+        let mut harness = Harness::new();
+        let mut server = multi_server::<2>(&mut harness);
+        let frame: Frame =
+            CanFrame::new(StandardId::new(0x123).unwrap(), &[1, 2, 3]).unwrap();
+        assert_eq!(
+            server.send_frame(&mut harness.sockets, &frame),
+            Err(Error::NotConnected)
+        );
+
+        let mut events = Vec::new();
+        harness.run(1, |sockets, now| server.poll(sockets, now));
+        let clients = [
+            harness.tcp_client_to(PORT, 49152),
+            harness.tcp_client_to(PORT, 49153),
+        ];
+        harness.run(10, |sockets, now| {
+            events.extend(server.poll(sockets, now));
+        });
+        assert_eq!(server.connected(&harness.sockets), 2);
+        let connects = events
+            .iter()
+            .filter(|(_, event)| *event == PollEvent::ClientConnected);
+        assert_eq!(connects.count(), 2);
+
+        assert_eq!(server.send_frame(&mut harness.sockets, &frame), Ok(2));
+        harness.run(10, |_, _| {});
+
+        for client in clients {
+            let mut buf = [0; 64];
+            let socket = harness.tcp_socket(client);
+            let len = socket.recv_slice(&mut buf).unwrap();
+            assert_eq!(&buf[size_of::<Packet>()..len], frame.as_bytes());
+        }
+    }
+
+    #[test]
+    fn multi_server_send_to_one() {
+        let mut harness = Harness::new();
+        let mut server = multi_server::<2>(&mut harness);
+        harness.run(1, |sockets, now| server.poll(sockets, now));
+        let clients = [
+            harness.tcp_client_to(PORT, 49152),
+            harness.tcp_client_to(PORT, 49153),
+        ];
+        let mut connected = Vec::new();
+        harness.run(10, |sockets, now| {
+            for (client, event) in server.poll(sockets, now) {
+                if event == PollEvent::ClientConnected {
+                    connected.push(client);
+                }
+            }
+        });
+        assert_eq!(connected.len(), 2);
+
+        // echo a frame back to its sender
+        let frame: Frame =
+            CanFrame::new(StandardId::new(0x123).unwrap(), &[1]).unwrap();
+        let socket = harness.tcp_socket(clients[1]);
+        socket.send_slice(peer_header().as_bytes()).unwrap();
+        socket.send_slice(frame.as_bytes()).unwrap();
+        harness.run(10, |_, _| {});
+
+        let (sender, received) = server
+            .recv_frame_from(&mut harness.sockets)
+            .unwrap()
+            .unwrap();
+        assert!(connected.contains(&sender));
+        server
+            .send_frame_to(&mut harness.sockets, sender, &received)
+            .unwrap();
+        harness.run(10, |_, _| {});
+
+        let mut lens = Vec::new();
+        for client in clients {
+            let mut buf = [0; 64];
+            let socket = harness.tcp_socket(client);
+            lens.push(socket.recv_slice(&mut buf).unwrap());
+        }
+        let packet_len = size_of::<Packet>();
+        assert_eq!(lens, [packet_len, packet_len + FRAME_LEN]);
+
+        // ids go stale once the client leaves
+        harness.tcp_socket(clients[1]).close();
+        harness.run(10, |sockets, now| server.poll(sockets, now));
+        assert_eq!(
+            server.send_frame_to(&mut harness.sockets, sender, &frame),
+            Err(Error::UnknownClient)
+        );
+    }
+
+    #[test]
+    fn multi_server_merges_received() {
+        let mut harness = Harness::new();
+        let mut server = multi_server::<2>(&mut harness);
+        harness.run(1, |sockets, now| server.poll(sockets, now));
+        let clients = [
+            harness.tcp_client_to(PORT, 49152),
+            harness.tcp_client_to(PORT, 49153),
+        ];
+        harness.run(10, |sockets, now| server.poll(sockets, now));
+
+        for (n, client) in clients.into_iter().enumerate() {
+            let frame: Frame =
+                CanFrame::new(StandardId::new(n as u16).unwrap(), &[]).unwrap();
+            let socket = harness.tcp_socket(client);
+            socket.send_slice(peer_header().as_bytes()).unwrap();
+            socket.send_slice(frame.as_bytes()).unwrap();
+            socket.send_slice(frame.as_bytes()).unwrap();
+        }
+        harness.run(10, |_, _| {});
+
+        let mut ids = Vec::new();
+        while let Some(frame) = server.recv_frame(&mut harness.sockets).unwrap()
+        {
+            ids.push(frame.id());
+        }
+        let id = |n| Id::Standard(StandardId::new(n).unwrap());
+        assert_eq!(ids, [id(0), id(1), id(0), id(1)]);
+    }
+
+    #[test]
+    fn multi_server_recv_before_header_sent() {
+        let mut harness = Harness::new();
+        let mut server = multi_server::<1>(&mut harness);
+        harness.run(1, |sockets, now| server.poll(sockets, now));
+        let client = harness.tcp_client();
+        harness.run(10, |_, _| {});
+
+        let frame: Frame =
+            CanFrame::new(StandardId::new(0x123).unwrap(), &[]).unwrap();
+        let socket = harness.tcp_socket(client);
+        socket.send_slice(peer_header().as_bytes()).unwrap();
+        socket.send_slice(frame.as_bytes()).unwrap();
+        harness.run(10, |_, _| {});
+
+        let (id, received) = server
+            .recv_frame_from(&mut harness.sockets)
+            .unwrap()
+            .unwrap();
+        assert_eq!(received, frame);
+
+        // the connect reports the client the frame came from
+        let [(connected, event)] =
+            server.poll(&mut harness.sockets, harness.now);
+        assert_eq!(event, PollEvent::ClientConnected);
+        assert_eq!(connected, id);
+        assert_eq!(
+            server.send_frame_to(&mut harness.sockets, id, &frame),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn mux_server_buses() {
+        let mut harness = Harness::new();
+        let mut server = MuxServer::new(
+            &mut harness.sockets,
+            Harness::tcp_buffer(1024),
+            Harness::tcp_buffer(1024),
+            MAC_ADDR,
+            harness.now,
+            BusNumber::default(),
+            500,
+        );
+        harness.run(1, |sockets, now| server.poll(sockets, now));
+        let client = harness.tcp_client();
+        harness.run(10, |sockets, now| server.poll(sockets, now));
+        assert!(server.is_ready(&harness.sockets));
+
+        let bus = |n| BusNumber::try_from(n).unwrap();
+        let frame = |id| -> Frame {
+            CanFrame::new(StandardId::new(id).unwrap(), &[id as u8]).unwrap()
+        };
+        let packet = |n, id| {
+            PacketBuilder::new()
+                .bus_number(bus(n))
+                .client_id(0x1234)
+                .frame(frame(id))
+                .build()
+        };
+
+        // frames on bus 1 and bus 2 interleaved on the one connection
+        let socket = harness.tcp_socket(client);
+        socket.send_slice(peer_header().as_bytes()).unwrap();
+        for id in 0..3 {
+            for n in [1, 2] {
+                let packet = packet(n, u16::from(n) * 0x100 + id);
+                socket.send_slice(packet.as_bytes()).unwrap();
+            }
+        }
+        harness.run(10, |sockets, now| server.poll(sockets, now));
+
+        let mut received = Vec::new();
+        while let Some(item) =
+            server.recv_frame_with_bus(&mut harness.sockets).unwrap()
+        {
+            received.push(item);
+        }
+        let expected: Vec<_> = (0..3)
+            .flat_map(|id| {
+                [(bus(1), frame(0x100 + id)), (bus(2), frame(0x200 + id))]
+            })
+            .collect();
+        assert_eq!(received, expected);
+
+        // discard the header
+        let mut buf = [0; 128];
+        harness.tcp_socket(client).recv_slice(&mut buf).unwrap();
+
+        server
+            .send_frame(&mut harness.sockets, bus(2), &frame(7))
+            .unwrap();
+        harness.run(10, |sockets, now| server.poll(sockets, now));
+        let len = harness.tcp_socket(client).recv_slice(&mut buf).unwrap();
+        let sent = Packet::read_from(&buf[..len]).unwrap();
+        assert_eq!(sent.header.bus_number(), 2);
+        assert_eq!(sent.frame, frame(7));
+
+        // heartbeats are tagged with the server's bus
+        harness.run(1000, |sockets, now| server.poll(sockets, now));
+        let len = harness.tcp_socket(client).recv_slice(&mut buf).unwrap();
+        let heartbeat = Packet::parse_heartbeat(&buf[..len]).unwrap();
+        assert_eq!(heartbeat.data_rate, 500);
+        assert_eq!(server.stats().heartbeats_sent, 1);
+    }
+
+    #[cfg(feature = "ipv6")]
+    #[test]
+    fn client_connect_ipv6() {
+        use crate::harness::LOCAL_V6;
+
+        let mut harness = Harness::new();
+        let peer = harness.tcp_listener(PORT);
+        let mut client = Client::new(
+            harness.tcp_server(1024, 1024),
+            (LOCAL_V6, PORT).into(),
+            49152,
+        );
+
+        harness.run_iface(10, |iface, sockets, now| {
+            client.poll(iface.context(), sockets, now)
+        });
+        assert_eq!(
+            client.server().state(&harness.sockets),
+            ConnectionState::Connected
+        );
+
+        let socket = harness.tcp_socket(peer);
+        let remote = socket.remote_endpoint().unwrap();
+        assert_eq!(remote.addr, LOCAL_V6);
+        assert_eq!(remote.port, 49152);
+    }
+
+    #[test]
+    fn client_connect() {
+        let mut harness = Harness::new();
+        let peer = harness.tcp_listener(PORT);
+        let mut client = Client::new(
+            harness.tcp_server(1024, 1024),
+            (LOCALHOST, PORT).into(),
+            49152,
+        );
+        assert_eq!(
+            client.server().state(&harness.sockets),
+            ConnectionState::Closed
+        );
+
+        harness.run_iface(1, |iface, sockets, now| {
+            client.poll(iface.context(), sockets, now)
+        });
+        assert_eq!(
+            client.server().state(&harness.sockets),
+            ConnectionState::Connecting
+        );
+
+        harness.run_iface(10, |iface, sockets, now| {
+            client.poll(iface.context(), sockets, now)
+        });
+        assert_eq!(
+            client.server().state(&harness.sockets),
+            ConnectionState::Connected
+        );
+
+        let mut buf = [0; 64];
+        let len = harness.tcp_socket(peer).recv_slice(&mut buf).unwrap();
+        let header = Header::parse(&buf[..len]).unwrap();
+        assert_eq!(len, size_of::<Packet>());
+        assert_eq!(header.bus_number(), BusNumber::default().into());
+
+        client.server_mut().close(&mut harness.sockets);
+        harness.run(10, |_, _| {});
+        assert_eq!(harness.tcp_socket(peer).state(), State::CloseWait);
     }
 }
@@ -0,0 +1,128 @@
+//! Byte stream abstraction under the TCP drivers.
+
+use smoltcp::socket::tcp::{RecvError, SendError, Socket, State};
+use smoltcp::time::Duration;
+
+/// Connected byte stream that [`Server`](crate::tcp::Server) exchanges the
+/// header and frames over.
+///
+/// The handshake, framing, heartbeats and send queue are written against
+/// this trait rather than smoltcp's [`Socket`] directly, so they can be
+/// exercised without a network stack. Listening and connecting stay with
+/// the socket.
+pub trait Transport {
+    /// TCP state of the connection.
+    fn state(&self) -> State;
+
+    /// Whether data can be written.
+    fn can_send(&self) -> bool;
+
+    /// Whether there is data to read.
+    fn can_recv(&self) -> bool;
+
+    /// Write as much of `data` as fits, returning the number of bytes
+    /// written.
+    fn send_slice(&mut self, data: &[u8]) -> Result<usize, SendError>;
+
+    /// Write in place, `f` returning how many bytes of the free space it
+    /// filled along with a result.
+    fn send<F, R>(&mut self, f: F) -> Result<R, SendError>
+    where
+        F: FnOnce(&mut [u8]) -> (usize, R);
+
+    /// Read into `data`, returning the number of bytes read.
+    fn recv_slice(&mut self, data: &mut [u8]) -> Result<usize, RecvError>;
+
+    /// Like [`Transport::recv_slice`] without consuming the bytes.
+    fn peek_slice(&mut self, data: &mut [u8]) -> Result<usize, RecvError>;
+
+    /// Bytes received and waiting to be read.
+    fn recv_queue(&self) -> usize;
+
+    /// Bytes written and not yet acknowledged.
+    fn send_queue(&self) -> usize;
+
+    /// Size of the send buffer.
+    fn send_capacity(&self) -> usize;
+
+    /// Start closing the connection once written data has gone out.
+    fn close(&mut self);
+
+    /// Drop the connection with a reset.
+    fn abort(&mut self);
+
+    /// Time without an acknowledgement after which the connection is
+    /// aborted, `None` if it never times out.
+    fn timeout(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Whether the connection is open, neither closed nor listening.
+    fn is_active(&self) -> bool {
+        !matches!(
+            self.state(),
+            State::Closed | State::TimeWait | State::Listen
+        )
+    }
+}
+
+impl Transport for Socket<'_> {
+    fn state(&self) -> State {
+        Socket::state(self)
+    }
+
+    fn can_send(&self) -> bool {
+        Socket::can_send(self)
+    }
+
+    fn can_recv(&self) -> bool {
+        Socket::can_recv(self)
+    }
+
+    fn send_slice(&mut self, data: &[u8]) -> Result<usize, SendError> {
+        Socket::send_slice(self, data)
+    }
+
+    fn send<F, R>(&mut self, f: F) -> Result<R, SendError>
+    where
+        F: FnOnce(&mut [u8]) -> (usize, R),
+    {
+        Socket::send(self, f)
+    }
+
+    fn recv_slice(&mut self, data: &mut [u8]) -> Result<usize, RecvError> {
+        Socket::recv_slice(self, data)
+    }
+
+    fn peek_slice(&mut self, data: &mut [u8]) -> Result<usize, RecvError> {
+        Socket::peek_slice(self, data)
+    }
+
+    fn recv_queue(&self) -> usize {
+        Socket::recv_queue(self)
+    }
+
+    fn send_queue(&self) -> usize {
+        Socket::send_queue(self)
+    }
+
+    fn send_capacity(&self) -> usize {
+        Socket::send_capacity(self)
+    }
+
+    fn close(&mut self) {
+        Socket::close(self)
+    }
+
+    fn abort(&mut self) {
+        Socket::abort(self)
+    }
+
+    fn timeout(&self) -> Option<Duration> {
+        Socket::timeout(self)
+    }
+
+    fn is_active(&self) -> bool {
+        Socket::is_active(self)
+    }
+}
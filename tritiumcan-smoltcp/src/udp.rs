@@ -2,18 +2,18 @@
 
 use core::mem::size_of;
 
-use crate::BROADCAST;
+use crate::{Error, BROADCAST};
 use embedded_can::Frame as CanFrame;
 use smoltcp::{
     iface::{SocketHandle, SocketSet},
     phy::PacketMeta,
-    socket::udp::{PacketBuffer, RecvError, SendError, Socket, UdpMetadata},
-    time::Instant,
+    socket::udp::{PacketBuffer, RecvError, Socket, UdpMetadata},
+    time::{Duration, Instant},
     wire::{EthernetAddress, IpEndpoint},
 };
 use tritiumcan::{
-    datagram::{Frame, Header, Packet},
-    BusNumber, HEARTBEAT_INTERVAL, PORT, PROTOCOL_VERSION,
+    datagram::{Frame, Packet, PacketBuilder},
+    BusNumber, HEARTBEAT_INTERVAL, PORT,
 };
 use zerocopy::{AsBytes, FromZeroes};
 
@@ -27,6 +27,7 @@ pub struct Server {
     mac_addr: [u8; 6],
     bus_number: BusNumber,
     data_rate: u16,
+    heartbeat_interval: Duration,
 
     // state
     last_heartbeat: Instant,
@@ -60,6 +61,7 @@ impl Server {
             mac_addr: mac_addr.0,
             bus_number,
             data_rate,
+            heartbeat_interval: HEARTBEAT_INTERVAL.into(),
             last_heartbeat: now,
         }
     }
@@ -74,6 +76,13 @@ impl Server {
         self.bus_number = bus_number;
     }
 
+    /// Set the interval between heartbeats.
+    ///
+    /// Defaults to [`HEARTBEAT_INTERVAL`].
+    pub fn set_heartbeat_interval(&mut self, interval: Duration) {
+        self.heartbeat_interval = interval;
+    }
+
     /// Perform bufferred transactions and send heartbeat if needed.
     ///
     /// This function should be called at least every 10ms to keep up with traffic.
@@ -90,7 +99,7 @@ impl Server {
             }
         }
 
-        if now - self.last_heartbeat > HEARTBEAT_INTERVAL.into() {
+        if now - self.last_heartbeat > self.heartbeat_interval {
             match self.write_heartbeat(socket) {
                 Ok(_) => self.last_heartbeat = now,
                 Err(_err) => {
@@ -107,56 +116,64 @@ impl Server {
     pub fn send_heartbeat(
         &mut self,
         sockets: &mut SocketSet,
-    ) -> Result<(), SendError> {
+    ) -> Result<(), Error> {
         let socket = sockets.get_mut::<Socket>(self.handle);
 
         self.write_heartbeat(socket)
     }
 
-    fn write_heartbeat(&self, socket: &mut Socket) -> Result<(), SendError> {
+    fn write_heartbeat(&self, socket: &mut Socket) -> Result<(), Error> {
         let packet = Packet::new_heartbeat(
             &self.mac_addr,
             &self.bus_number,
             &self.data_rate,
         );
 
-        socket.send_slice(packet.as_bytes(), self.meta)
+        Ok(socket.send_slice(packet.as_bytes(), self.meta)?)
     }
 
     /// Broadcast a CAN frame.
+    ///
+    /// A frame with more than 8 data bytes returns [`Error::FrameTooLong`].
     pub fn send_frame(
         &mut self,
         sockets: &mut SocketSet,
         frame: &impl CanFrame,
-    ) -> Result<(), SendError> {
+    ) -> Result<(), Error> {
         let socket = sockets.get_mut::<Socket>(self.handle);
 
-        let mut packet = Packet {
-            header: Header::new(),
-            frame: Frame::from_frame(frame).unwrap(),
-        };
-        packet.header.set_version(PROTOCOL_VERSION);
-        packet.header.set_bus_number(self.bus_number.into());
-        packet
-            .header
-            .set_client_identifier(u64::from_be_bytes([0u8; 8]));
+        let frame =
+            Frame::from_frame(frame).map_err(|_| Error::FrameTooLong)?;
+        let packet = PacketBuilder::new()
+            .bus_number(self.bus_number)
+            .frame(frame)
+            .build();
 
-        socket.send_slice(packet.as_bytes(), self.meta)
+        Ok(socket.send_slice(packet.as_bytes(), self.meta)?)
     }
 
+    /// Receive a CAN frame.
+    ///
+    /// Returns `Ok(None)` when no datagram is waiting or the datagram isn't
+    /// a frame packet, an oversized datagram is dropped.
     pub fn recv_frame(
         &mut self,
         sockets: &mut SocketSet,
-    ) -> Result<Option<Frame>, RecvError> {
+    ) -> Result<Option<Frame>, Error> {
         let socket = sockets.get_mut::<Socket>(self.handle);
 
         let mut packet = Packet::new_zeroed();
-        let (len, _meta) = socket.recv_slice(packet.as_bytes_mut())?;
+        let len = match socket.recv_slice(packet.as_bytes_mut()) {
+            Ok((len, _meta)) => len,
+            Err(RecvError::Exhausted | RecvError::Truncated) => {
+                return Ok(None)
+            }
+        };
 
         if len != size_of::<Packet>() {
             Ok(None)
         } else {
-            Ok(Some(packet.frame.into()))
+            Ok(Some(packet.frame))
         }
     }
 
@@ -190,3 +207,74 @@ impl Server {
         socket.register_send_waker(waker);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::harness::{FdFrame, Harness, MAC_ADDR};
+    use embedded_can::ExtendedId;
+
+    fn server(harness: &mut Harness) -> Server {
+        Server::new(
+            &mut harness.sockets,
+            Harness::udp_buffer(4),
+            Harness::udp_buffer(4),
+            MAC_ADDR,
+            harness.now,
+            BusNumber::default(),
+            500,
+        )
+    }
+
+    #[test]
+    fn send_frame_wire_format() {
+        let mut harness = Harness::new();
+        let mut server = server(&mut harness);
+        server.poll(&mut harness.sockets, harness.now);
+
+        let id = ExtendedId::new(0x18FF50E5).unwrap();
+        let frame: Frame = CanFrame::new(id, &[1, 2, 3, 4]).unwrap();
+        server.send_frame(&mut harness.sockets, &frame).unwrap();
+
+        #[rustfmt::skip]
+        let expected = [
+            // bus identifier, "Tritium" with bus number 13
+            0x00, 0x54, 0x72, 0x69, 0x74, 0x69, 0x75, 0x6D,
+            // client identifier
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            // identifier
+            0x18, 0xFF, 0x50, 0xE5,
+            // flags, length
+            0x01, 0x04,
+            // data
+            0x01, 0x02, 0x03, 0x04, 0x00, 0x00, 0x00, 0x00,
+        ];
+
+        assert_eq!(harness.take_udp_payload().unwrap(), expected);
+    }
+
+    #[test]
+    fn send_frame_too_long() {
+        let mut harness = Harness::new();
+        let mut server = server(&mut harness);
+        server.poll(&mut harness.sockets, harness.now);
+
+        let id = ExtendedId::new(0x18FF50E5).unwrap();
+        let frame = FdFrame::new(id, &[0; 12]).unwrap();
+        assert_eq!(
+            server.send_frame(&mut harness.sockets, &frame),
+            Err(Error::FrameTooLong)
+        );
+        assert!(harness.take_udp_payload().is_none());
+    }
+
+    #[test]
+    fn recv_frame_empty() {
+        let mut harness = Harness::new();
+        let mut server = server(&mut harness);
+        server.poll(&mut harness.sockets, harness.now);
+
+        assert_eq!(server.recv_frame(&mut harness.sockets), Ok(None));
+    }
+}
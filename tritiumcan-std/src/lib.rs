@@ -0,0 +1,357 @@
+//! std drivers for the Tritium CAN protocol.
+//!
+//! This crate provides blocking server and client implementations over
+//! [`std::net::TcpStream`] for hosts talking to a Tritium CAN-Ethernet
+//! adapter.
+//!
+//! # Optional features
+//!
+//! - `tokio` async server and client built on tokio, see [`tokio`].
+
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+use zerocopy::AsBytes;
+
+use tritiumcan::{
+    codec::{Codec, CodecError, HEADER_LEN},
+    datagram::{
+        Frame, FrameError, Header, PacketBuilder, ReceivedItem, FRAME_LEN,
+    },
+    BusNumber, PORT,
+};
+
+#[cfg(feature = "tokio")]
+pub mod tokio;
+
+// re-export
+pub use tritiumcan as proto;
+
+/// Read and write timeout, matching the smoltcp drivers.
+pub const TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Error on a TCP connection.
+#[derive(Debug)]
+pub enum Error {
+    /// Socket error, including timeouts.
+    Io(io::Error),
+    /// The peer sent a malformed header or frame.
+    Codec(CodecError),
+    /// The frame to send doesn't fit the protocol, such as a CAN FD frame
+    /// with more than 8 bytes.
+    Frame(FrameError),
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<CodecError> for Error {
+    fn from(err: CodecError) -> Self {
+        Error::Codec(err)
+    }
+}
+
+impl From<FrameError> for Error {
+    fn from(err: FrameError) -> Self {
+        Error::Frame(err)
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "io error: {err}"),
+            Error::Codec(err) => write!(f, "malformed data: {err:?}"),
+            Error::Frame(err) => write!(f, "invalid frame: {err:?}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            Error::Codec(_) | Error::Frame(_) => None,
+        }
+    }
+}
+
+/// Header sent when a connection opens.
+///
+/// The smoltcp drivers follow it with a heartbeat, these drivers don't know
+/// a MAC address or data rate so send the header alone.
+fn header(bus_number: BusNumber) -> Header {
+    PacketBuilder::new().bus_number(bus_number).build().header
+}
+
+/// Server end of a connection, accepted from a [`TcpListener`].
+#[derive(Debug)]
+pub struct StdServer {
+    stream: TcpStream,
+    bus_number: BusNumber,
+    peer_bus_number: Option<BusNumber>,
+    peer_client_id: Option<u64>,
+    /// Partially received frame.
+    rx_buf: [u8; FRAME_LEN],
+    rx_len: usize,
+}
+
+impl StdServer {
+    /// Accept a client on `listener` and exchange headers.
+    pub fn accept(
+        listener: &TcpListener,
+        bus_number: BusNumber,
+    ) -> Result<Self, Error> {
+        let (stream, _) = listener.accept()?;
+        Self::new(stream, bus_number)
+    }
+
+    /// Exchange headers on a connected `stream`.
+    pub fn new(
+        stream: TcpStream,
+        bus_number: BusNumber,
+    ) -> Result<Self, Error> {
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        stream.set_nodelay(true)?;
+
+        let mut server = Self {
+            stream,
+            bus_number,
+            peer_bus_number: None,
+            peer_client_id: None,
+            rx_buf: [0; FRAME_LEN],
+            rx_len: 0,
+        };
+        server.send_header()?;
+        server.recv_header()?;
+
+        Ok(server)
+    }
+
+    fn send_header(&mut self) -> Result<(), Error> {
+        let header = header(self.bus_number);
+        self.stream.write_all(header.as_bytes())?;
+        Ok(())
+    }
+
+    fn recv_header(&mut self) -> Result<(), Error> {
+        let mut buf = [0; HEADER_LEN];
+        self.stream.read_exact(&mut buf)?;
+
+        if let (Some(header), _) = Codec::decode_header(&buf)? {
+            self.peer_bus_number =
+                BusNumber::try_from(header.bus_number()).ok();
+            self.peer_client_id = Some(header.client_identifier());
+        }
+
+        Ok(())
+    }
+
+    /// Bus number declared in the peer's header.
+    pub fn peer_bus_number(&self) -> Option<BusNumber> {
+        self.peer_bus_number
+    }
+
+    /// Client identifier declared in the peer's header.
+    pub fn peer_client_id(&self) -> Option<u64> {
+        self.peer_client_id
+    }
+
+    /// Send a CAN frame, blocking until it has been written.
+    pub fn send_frame(
+        &mut self,
+        frame: &impl embedded_can::Frame,
+    ) -> Result<(), Error> {
+        let frame = Frame::from_frame(frame)?;
+
+        let mut buf = [0; FRAME_LEN];
+        let len = Codec::encode_frame(&frame, &mut buf)?;
+        self.stream.write_all(&buf[..len])?;
+        Ok(())
+    }
+
+    /// Receive a CAN frame, heartbeat or settings frame, blocking until a
+    /// whole frame has arrived.
+    ///
+    /// If the read times out the bytes of a partial frame are kept for the
+    /// next call.
+    pub fn recv_item(&mut self) -> Result<ReceivedItem, Error> {
+        while self.rx_len < FRAME_LEN {
+            let len = self.stream.read(&mut self.rx_buf[self.rx_len..])?;
+            if len == 0 {
+                return Err(
+                    io::Error::from(io::ErrorKind::UnexpectedEof).into()
+                );
+            }
+            self.rx_len += len;
+        }
+        self.rx_len = 0;
+
+        let (frame, _) = Codec::decode(&self.rx_buf)?;
+        let frame = frame.ok_or(CodecError::BufferTooSmall)?;
+        let bus_number = self.peer_bus_number.unwrap_or_default();
+
+        Ok(ReceivedItem::classify(frame, bus_number))
+    }
+
+    /// Receive a CAN frame, blocking until one has arrived.
+    ///
+    /// Heartbeats and settings frames are skipped, see
+    /// [`StdServer::recv_item`].
+    pub fn recv_frame(&mut self) -> Result<Frame, Error> {
+        loop {
+            if let ReceivedItem::Data(frame) = self.recv_item()? {
+                return Ok(frame);
+            }
+        }
+    }
+
+    /// The underlying stream.
+    pub fn stream(&self) -> &TcpStream {
+        &self.stream
+    }
+}
+
+/// Client end of a connection to a bridge.
+#[derive(Debug)]
+pub struct StdClient {
+    inner: StdServer,
+}
+
+impl StdClient {
+    /// Connect to a bridge at `addr` and exchange headers.
+    pub fn connect(
+        addr: impl ToSocketAddrs,
+        bus_number: BusNumber,
+    ) -> Result<Self, Error> {
+        let stream = TcpStream::connect(addr)?;
+        Ok(Self {
+            inner: StdServer::new(stream, bus_number)?,
+        })
+    }
+
+    /// Connect to the bridge at `ip` on [`PORT`].
+    pub fn connect_ip(
+        ip: std::net::IpAddr,
+        bus_number: BusNumber,
+    ) -> Result<Self, Error> {
+        Self::connect((ip, PORT), bus_number)
+    }
+
+    /// Bus number declared in the peer's header.
+    pub fn peer_bus_number(&self) -> Option<BusNumber> {
+        self.inner.peer_bus_number()
+    }
+
+    /// Client identifier declared in the peer's header.
+    pub fn peer_client_id(&self) -> Option<u64> {
+        self.inner.peer_client_id()
+    }
+
+    /// Send a CAN frame, blocking until it has been written.
+    pub fn send_frame(
+        &mut self,
+        frame: &impl embedded_can::Frame,
+    ) -> Result<(), Error> {
+        self.inner.send_frame(frame)
+    }
+
+    /// Receive a CAN frame, heartbeat or settings frame.
+    pub fn recv_item(&mut self) -> Result<ReceivedItem, Error> {
+        self.inner.recv_item()
+    }
+
+    /// Receive a CAN frame, blocking until one has arrived.
+    pub fn recv_frame(&mut self) -> Result<Frame, Error> {
+        self.inner.recv_frame()
+    }
+
+    /// The underlying stream.
+    pub fn stream(&self) -> &TcpStream {
+        self.inner.stream()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_can::{Frame as CanFrame, Id, StandardId};
+    use std::thread;
+
+    /// CAN FD frame with more data than the protocol carries.
+    pub(crate) struct FdFrame {
+        id: Id,
+        data: [u8; 12],
+    }
+
+    impl CanFrame for FdFrame {
+        fn new(id: impl Into<Id>, data: &[u8]) -> Option<Self> {
+            Some(FdFrame {
+                id: id.into(),
+                data: data.try_into().ok()?,
+            })
+        }
+
+        fn new_remote(_: impl Into<Id>, _: usize) -> Option<Self> {
+            None
+        }
+
+        fn is_extended(&self) -> bool {
+            matches!(self.id, Id::Extended(_))
+        }
+
+        fn is_remote_frame(&self) -> bool {
+            false
+        }
+
+        fn id(&self) -> Id {
+            self.id
+        }
+
+        fn dlc(&self) -> usize {
+            self.data.len()
+        }
+
+        fn data(&self) -> &[u8] {
+            &self.data
+        }
+    }
+
+    #[test]
+    fn loopback_exchange() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let bus_number = BusNumber::try_from(3).unwrap();
+
+        let peer = thread::spawn(move || {
+            let mut server = StdServer::accept(&listener, bus_number).unwrap();
+            let frame = server.recv_frame().unwrap();
+            server.send_frame(&frame).unwrap();
+            server.peer_bus_number()
+        });
+
+        let mut client =
+            StdClient::connect(addr, BusNumber::default()).unwrap();
+        assert_eq!(client.peer_bus_number(), Some(bus_number));
+        assert_eq!(client.peer_client_id(), Some(0));
+        assert_eq!(client.stream().read_timeout().unwrap(), Some(TIMEOUT));
+
+        let frame: Frame =
+            CanFrame::new(StandardId::new(0x123).unwrap(), &[1, 2, 3]).unwrap();
+        // too long for the protocol, an error rather than a panic
+        let fd = FdFrame::new(StandardId::new(0x456).unwrap(), &[0; 12]);
+        assert!(matches!(
+            client.send_frame(&fd.unwrap()),
+            Err(Error::Frame(FrameError::DataTooLong))
+        ));
+
+        client.send_frame(&frame).unwrap();
+        assert_eq!(client.recv_frame().unwrap(), frame);
+
+        assert_eq!(peer.join().unwrap(), Some(BusNumber::default()));
+    }
+}
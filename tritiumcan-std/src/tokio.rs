@@ -0,0 +1,241 @@
+//! Async transport built on tokio.
+
+use ::tokio::io::{AsyncReadExt, AsyncWriteExt};
+use ::tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::io;
+use std::net::SocketAddr;
+use zerocopy::AsBytes;
+
+use crate::{header, Error};
+use tritiumcan::{
+    codec::{Codec, CodecError, HEADER_LEN},
+    datagram::{Frame, ReceivedItem, FRAME_LEN},
+    BusNumber,
+};
+
+/// Listener accepting connections from many bridges.
+#[derive(Debug)]
+pub struct TokioServer {
+    listener: TcpListener,
+    bus_number: BusNumber,
+}
+
+impl TokioServer {
+    /// Listen on `addr`.
+    pub async fn bind(
+        addr: impl ToSocketAddrs,
+        bus_number: BusNumber,
+    ) -> io::Result<Self> {
+        Ok(Self {
+            listener: TcpListener::bind(addr).await?,
+            bus_number,
+        })
+    }
+
+    /// Address the server is listening on.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Accept a connection and exchange headers.
+    pub async fn accept(&self) -> Result<TokioConnection, Error> {
+        let (stream, _) = self.listener.accept().await?;
+        TokioConnection::new(stream, self.bus_number).await
+    }
+}
+
+/// One end of a connection, accepted by a [`TokioServer`] or opened by a
+/// [`TokioClient`].
+#[derive(Debug)]
+pub struct TokioConnection {
+    stream: TcpStream,
+    peer_bus_number: Option<BusNumber>,
+    peer_client_id: Option<u64>,
+    /// Partially received frame.
+    rx_buf: [u8; FRAME_LEN],
+    rx_len: usize,
+}
+
+impl TokioConnection {
+    /// Exchange headers on a connected `stream`.
+    pub async fn new(
+        mut stream: TcpStream,
+        bus_number: BusNumber,
+    ) -> Result<Self, Error> {
+        stream.set_nodelay(true)?;
+        stream.write_all(header(bus_number).as_bytes()).await?;
+
+        let mut buf = [0; HEADER_LEN];
+        stream.read_exact(&mut buf).await?;
+
+        let mut connection = Self {
+            stream,
+            peer_bus_number: None,
+            peer_client_id: None,
+            rx_buf: [0; FRAME_LEN],
+            rx_len: 0,
+        };
+        if let (Some(header), _) = Codec::decode_header(&buf)? {
+            connection.peer_bus_number =
+                BusNumber::try_from(header.bus_number()).ok();
+            connection.peer_client_id = Some(header.client_identifier());
+        }
+
+        Ok(connection)
+    }
+
+    /// Bus number declared in the peer's header.
+    pub fn peer_bus_number(&self) -> Option<BusNumber> {
+        self.peer_bus_number
+    }
+
+    /// Client identifier declared in the peer's header.
+    pub fn peer_client_id(&self) -> Option<u64> {
+        self.peer_client_id
+    }
+
+    /// Send a CAN frame.
+    pub async fn send_frame(
+        &mut self,
+        frame: &impl embedded_can::Frame,
+    ) -> Result<(), Error> {
+        let frame = Frame::from_frame(frame)?;
+
+        let mut buf = [0; FRAME_LEN];
+        let len = Codec::encode_frame(&frame, &mut buf)?;
+        self.stream.write_all(&buf[..len]).await?;
+        Ok(())
+    }
+
+    /// Receive a CAN frame, heartbeat or settings frame.
+    ///
+    /// Cancel safe, bytes of a partial frame are kept for the next call.
+    pub async fn recv_item(&mut self) -> Result<ReceivedItem, Error> {
+        while self.rx_len < FRAME_LEN {
+            let len = self.stream.read(&mut self.rx_buf[self.rx_len..]).await?;
+            if len == 0 {
+                return Err(
+                    io::Error::from(io::ErrorKind::UnexpectedEof).into()
+                );
+            }
+            self.rx_len += len;
+        }
+        self.rx_len = 0;
+
+        let (frame, _) = Codec::decode(&self.rx_buf)?;
+        let frame = frame.ok_or(CodecError::BufferTooSmall)?;
+        let bus_number = self.peer_bus_number.unwrap_or_default();
+
+        Ok(ReceivedItem::classify(frame, bus_number))
+    }
+
+    /// Receive a CAN frame, skipping heartbeats and settings frames.
+    pub async fn recv_frame(&mut self) -> Result<Frame, Error> {
+        loop {
+            if let ReceivedItem::Data(frame) = self.recv_item().await? {
+                return Ok(frame);
+            }
+        }
+    }
+}
+
+/// Client end of a connection to a bridge or [`TokioServer`].
+#[derive(Debug)]
+pub struct TokioClient {
+    inner: TokioConnection,
+}
+
+impl TokioClient {
+    /// Connect to `addr` and exchange headers.
+    pub async fn connect(
+        addr: impl ToSocketAddrs,
+        bus_number: BusNumber,
+    ) -> Result<Self, Error> {
+        let stream = TcpStream::connect(addr).await?;
+        Ok(Self {
+            inner: TokioConnection::new(stream, bus_number).await?,
+        })
+    }
+
+    /// Bus number declared in the peer's header.
+    pub fn peer_bus_number(&self) -> Option<BusNumber> {
+        self.inner.peer_bus_number()
+    }
+
+    /// Client identifier declared in the peer's header.
+    pub fn peer_client_id(&self) -> Option<u64> {
+        self.inner.peer_client_id()
+    }
+
+    /// Send a CAN frame.
+    pub async fn send_frame(
+        &mut self,
+        frame: &impl embedded_can::Frame,
+    ) -> Result<(), Error> {
+        self.inner.send_frame(frame).await
+    }
+
+    /// Receive a CAN frame, heartbeat or settings frame.
+    pub async fn recv_item(&mut self) -> Result<ReceivedItem, Error> {
+        self.inner.recv_item().await
+    }
+
+    /// Receive a CAN frame, skipping heartbeats and settings frames.
+    pub async fn recv_frame(&mut self) -> Result<Frame, Error> {
+        self.inner.recv_frame().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::FdFrame;
+    use embedded_can::{Frame as CanFrame, StandardId};
+    use tritiumcan::datagram::FrameError;
+
+    #[::tokio::test]
+    async fn server_client_exchange() {
+        let server = TokioServer::bind("127.0.0.1:0", BusNumber::default())
+            .await
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+
+        fn frame(n: u16) -> Frame {
+            CanFrame::new(StandardId::new(n).unwrap(), &[n as u8; 3]).unwrap()
+        }
+
+        let client = ::tokio::spawn(async move {
+            let bus_number = BusNumber::try_from(3).unwrap();
+            let mut client =
+                TokioClient::connect(addr, bus_number).await.unwrap();
+            for n in 0..5 {
+                client.send_frame(&frame(n)).await.unwrap();
+            }
+            (client.peer_bus_number(), client.peer_client_id())
+        });
+
+        let mut connection = server.accept().await.unwrap();
+        assert_eq!(connection.peer_bus_number(), BusNumber::try_from(3).ok());
+
+        // too long for the protocol, an error rather than a panic
+        let fd = FdFrame::new(StandardId::new(0x456).unwrap(), &[0; 12]);
+        assert!(matches!(
+            connection.send_frame(&fd.unwrap()).await,
+            Err(Error::Frame(FrameError::DataTooLong))
+        ));
+
+        // only the frames sent, the client closes after them
+        for n in 0..5 {
+            assert_eq!(connection.recv_frame().await.unwrap(), frame(n));
+        }
+        assert!(matches!(
+            connection.recv_frame().await,
+            Err(Error::Io(err)) if err.kind() == io::ErrorKind::UnexpectedEof
+        ));
+
+        assert_eq!(
+            client.await.unwrap(),
+            (Some(BusNumber::default()), Some(0))
+        );
+    }
+}
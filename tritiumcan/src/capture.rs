@@ -0,0 +1,118 @@
+//! Recent frame capture for debugging.
+
+use crate::datagram::{Frame, FrameBitfield, FRAME_LEN};
+
+/// Which way a captured frame went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub enum Direction {
+    /// Sent to the peer.
+    Tx,
+    /// Received from the peer.
+    Rx,
+}
+
+/// Ring of the last `N` frames, overwriting the oldest once full.
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub struct Capture<const N: usize> {
+    entries: [(Direction, [u8; FRAME_LEN]); N],
+    head: usize,
+    len: usize,
+}
+
+impl<const N: usize> Capture<N> {
+    /// Create an empty capture.
+    pub const fn new() -> Self {
+        Self {
+            entries: [(Direction::Rx, [0; FRAME_LEN]); N],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    /// Record `frame`, dropping the oldest frame if the ring is full.
+    pub fn record(&mut self, direction: Direction, frame: &Frame) {
+        if N == 0 {
+            return;
+        }
+
+        if self.len < N {
+            self.entries[(self.head + self.len) % N] = (direction, frame.0);
+            self.len += 1;
+        } else {
+            self.entries[self.head] = (direction, frame.0);
+            self.head = (self.head + 1) % N;
+        }
+    }
+
+    /// Captured frames, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = (Direction, Frame)> + '_ {
+        (0..self.len).map(move |i| {
+            let (direction, bytes) = self.entries[(self.head + i) % N];
+            (direction, FrameBitfield(bytes))
+        })
+    }
+
+    /// Remove every frame.
+    pub fn clear(&mut self) {
+        self.head = 0;
+        self.len = 0;
+    }
+
+    /// Number of captured frames.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether nothing has been captured.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl<const N: usize> Default for Capture<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_can::StandardId;
+
+    fn frame(id: u16) -> Frame {
+        Frame::new(StandardId::new(id).unwrap(), &[id as u8]).unwrap()
+    }
+
+    #[test]
+    fn keeps_most_recent() {
+        let mut capture = Capture::<3>::new();
+        assert!(capture.is_empty());
+        assert_eq!(capture.iter().count(), 0);
+
+        capture.record(Direction::Tx, &frame(1));
+        capture.record(Direction::Rx, &frame(2));
+        assert_eq!(
+            capture.iter().collect::<Vec<_>>(),
+            [(Direction::Tx, frame(1)), (Direction::Rx, frame(2))]
+        );
+
+        for id in 3..8 {
+            capture.record(Direction::Tx, &frame(id));
+        }
+        assert_eq!(capture.len(), 3);
+        assert_eq!(
+            capture.iter().collect::<Vec<_>>(),
+            [5, 6, 7].map(|id| (Direction::Tx, frame(id)))
+        );
+
+        capture.clear();
+        assert!(capture.is_empty());
+
+        let mut capture = Capture::<0>::new();
+        capture.record(Direction::Tx, &frame(1));
+        assert!(capture.is_empty());
+    }
+}
@@ -0,0 +1,498 @@
+//! Stream encoding and decoding independent of any socket.
+//!
+//! A TCP connection starts with a [`Header`] from each side followed by a
+//! stream of [`Frame`]s. [`Codec`] turns these into bytes and back so the
+//! protocol can be used over any transport. [`StreamDecoder`] keeps track
+//! of whether the header has been read for a whole incoming stream.
+//!
+//! The protocol sends the CAN ID big-endian, [`Endianness`] also reads and
+//! writes it little-endian for clone bridges that get this wrong.
+//!
+//! With the `frame-crc` feature frames can also be encoded with a trailing
+//! CRC16, an extension for noisy links that both ends have to agree on.
+
+pub use crate::datagram::HEADER_LEN;
+
+use crate::datagram::{
+    Frame, FrameBitfield, Header, HeaderError, ReceivedItem, FRAME_LEN,
+};
+use crate::BusNumber;
+use zerocopy::{AsBytes, FromBytes};
+
+/// Length of the CRC following a frame encoded with
+/// [`Codec::encode_frame_crc`].
+#[cfg(feature = "frame-crc")]
+pub const CRC_LEN: usize = 2;
+
+/// CRC-16/CCITT-FALSE of `bytes`.
+#[cfg(feature = "frame-crc")]
+pub fn crc16(bytes: &[u8]) -> u16 {
+    let mut crc = 0xFFFF_u16;
+    for &byte in bytes {
+        crc ^= u16::from(byte) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// Error encoding or decoding a stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub enum CodecError {
+    /// Output buffer is too small for the encoded item.
+    BufferTooSmall,
+    /// Received header is invalid.
+    Header(HeaderError),
+    /// Received frame has a DLC above 8.
+    ///
+    /// Frames have a fixed length so the caller can skip [`FRAME_LEN`] bytes
+    /// and carry on decoding.
+    InvalidDlc,
+    /// Input ends partway through a frame.
+    Truncated,
+    /// Received frame doesn't match its trailing CRC.
+    #[cfg(feature = "frame-crc")]
+    BadCrc,
+}
+
+impl From<HeaderError> for CodecError {
+    fn from(err: HeaderError) -> Self {
+        CodecError::Header(err)
+    }
+}
+
+/// Byte order of the CAN ID of a frame on the wire.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub enum Endianness {
+    /// As the protocol specifies.
+    #[default]
+    Big,
+    /// As sent by some clone bridges.
+    Little,
+}
+
+impl Endianness {
+    /// Convert `frame` between the protocol's byte order and this one.
+    ///
+    /// The conversion is its own inverse, so it serves both to encode and
+    /// to decode.
+    pub fn convert(self, frame: &Frame) -> Frame {
+        let mut bytes = frame.0;
+        if self == Endianness::Little {
+            bytes[..4].reverse();
+        }
+        FrameBitfield(bytes)
+    }
+}
+
+/// Tritium stream codec.
+pub struct Codec;
+
+impl Codec {
+    /// Encode `frame` into the start of `buf`, returning the number of bytes
+    /// written.
+    pub fn encode_frame(
+        frame: &Frame,
+        buf: &mut [u8],
+    ) -> Result<usize, CodecError> {
+        let out = buf.get_mut(..FRAME_LEN).ok_or(CodecError::BufferTooSmall)?;
+        out.copy_from_slice(frame.as_bytes());
+        Ok(FRAME_LEN)
+    }
+
+    /// Decode a frame from the start of `buf`.
+    ///
+    /// Returns the frame and the number of bytes consumed, or `(None, 0)` if
+    /// `buf` doesn't hold a whole frame yet.
+    pub fn decode(buf: &[u8]) -> Result<(Option<Frame>, usize), CodecError> {
+        let Some(frame) = buf.get(..FRAME_LEN).and_then(Frame::read_from)
+        else {
+            return Ok((None, 0));
+        };
+
+        if frame.raw_dlc() > 8 {
+            return Err(CodecError::InvalidDlc);
+        }
+
+        Ok((Some(frame), FRAME_LEN))
+    }
+
+    /// Like [`Codec::encode_frame`], writing the CAN ID in `endianness`
+    /// byte order.
+    pub fn encode_frame_with(
+        frame: &Frame,
+        buf: &mut [u8],
+        endianness: Endianness,
+    ) -> Result<usize, CodecError> {
+        Self::encode_frame(&endianness.convert(frame), buf)
+    }
+
+    /// Like [`Codec::decode`], reading the CAN ID in `endianness` byte
+    /// order.
+    pub fn decode_with(
+        buf: &[u8],
+        endianness: Endianness,
+    ) -> Result<(Option<Frame>, usize), CodecError> {
+        let (frame, len) = Self::decode(buf)?;
+        Ok((frame.map(|frame| endianness.convert(&frame)), len))
+    }
+
+    /// Decode every whole frame in `buf`, passing each to `sink` classified
+    /// as sent by a peer on `bus_number`.
+    ///
+    /// Returns the trailing bytes of a partial frame, for example to
+    /// prepend to the next read. Stops at the first malformed frame,
+    /// frames before it have already been passed to `sink`.
+    pub fn decode_all(
+        buf: &[u8],
+        bus_number: BusNumber,
+        mut sink: impl FnMut(ReceivedItem),
+    ) -> Result<&[u8], CodecError> {
+        let mut rest = buf;
+
+        while let (Some(frame), len) = Self::decode(rest)? {
+            sink(ReceivedItem::classify(frame, bus_number));
+            rest = &rest[len..];
+        }
+
+        Ok(rest)
+    }
+
+    /// Encode `frame` followed by its big-endian CRC16 into the start of
+    /// `buf`, returning the number of bytes written.
+    #[cfg(feature = "frame-crc")]
+    pub fn encode_frame_crc(
+        frame: &Frame,
+        buf: &mut [u8],
+    ) -> Result<usize, CodecError> {
+        let out = buf
+            .get_mut(..FRAME_LEN + CRC_LEN)
+            .ok_or(CodecError::BufferTooSmall)?;
+        out[..FRAME_LEN].copy_from_slice(frame.as_bytes());
+        out[FRAME_LEN..]
+            .copy_from_slice(&crc16(frame.as_bytes()).to_be_bytes());
+        Ok(FRAME_LEN + CRC_LEN)
+    }
+
+    /// Decode a frame followed by its CRC16 from the start of `buf`.
+    ///
+    /// Like [`Codec::decode`], returns `(None, 0)` until `buf` holds the
+    /// frame and CRC.
+    #[cfg(feature = "frame-crc")]
+    pub fn decode_crc(
+        buf: &[u8],
+    ) -> Result<(Option<Frame>, usize), CodecError> {
+        let Some(bytes) = buf.get(..FRAME_LEN + CRC_LEN) else {
+            return Ok((None, 0));
+        };
+
+        let (frame, crc) = bytes.split_at(FRAME_LEN);
+        if crc16(frame).to_be_bytes() != crc {
+            return Err(CodecError::BadCrc);
+        }
+
+        match Self::decode(frame)? {
+            (Some(frame), _) => Ok((Some(frame), FRAME_LEN + CRC_LEN)),
+            (None, _) => Ok((None, 0)),
+        }
+    }
+
+    /// Encode `header` into the start of `buf`, returning the number of
+    /// bytes written.
+    pub fn encode_header(
+        header: &Header,
+        buf: &mut [u8],
+    ) -> Result<usize, CodecError> {
+        let out = buf
+            .get_mut(..HEADER_LEN)
+            .ok_or(CodecError::BufferTooSmall)?;
+        out.copy_from_slice(header.as_bytes());
+        Ok(HEADER_LEN)
+    }
+
+    /// Decode and validate a header from the start of `buf`.
+    ///
+    /// Returns the header and the number of bytes consumed, or `(None, 0)` if
+    /// `buf` doesn't hold a whole header yet.
+    pub fn decode_header(
+        buf: &[u8],
+    ) -> Result<(Option<Header>, usize), CodecError> {
+        if buf.len() < HEADER_LEN {
+            return Ok((None, 0));
+        }
+
+        let header = Header::parse(buf)?;
+        Ok((Some(header), HEADER_LEN))
+    }
+}
+
+/// Item decoded by a [`StreamDecoder`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum StreamItem {
+    Header(Header),
+    Frame(Frame),
+}
+
+/// Decoder for an incoming stream, reading the header once and then bare
+/// frames.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct StreamDecoder {
+    header_seen: bool,
+}
+
+impl StreamDecoder {
+    pub const fn new() -> Self {
+        Self { header_seen: false }
+    }
+
+    /// Whether the header has been decoded.
+    pub fn header_seen(&self) -> bool {
+        self.header_seen
+    }
+
+    /// Decode the header, or once it has been seen a frame, from the start
+    /// of `buf`.
+    ///
+    /// Returns the item and the number of bytes consumed, or `(None, 0)` if
+    /// `buf` doesn't hold a whole item yet.
+    pub fn decode(
+        &mut self,
+        buf: &[u8],
+    ) -> Result<(Option<StreamItem>, usize), CodecError> {
+        if self.header_seen {
+            let (frame, len) = Codec::decode(buf)?;
+            return Ok((frame.map(StreamItem::Frame), len));
+        }
+
+        let (header, len) = Codec::decode_header(buf)?;
+        self.header_seen = header.is_some();
+        Ok((header.map(StreamItem::Header), len))
+    }
+
+    /// Expect a header again, for a new connection.
+    pub fn reset(&mut self) {
+        self.header_seen = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datagram::{Heartbeat, Packet};
+    use crate::PROTOCOL_VERSION;
+    use embedded_can::{ExtendedId, StandardId};
+
+    #[test]
+    fn frame_round_trip() {
+        let frame =
+            Frame::new(StandardId::new(0x123).unwrap(), &[1, 2, 3]).unwrap();
+
+        let mut buf = [0; 32];
+        assert_eq!(Codec::encode_frame(&frame, &mut buf), Ok(FRAME_LEN));
+        assert_eq!(
+            Codec::encode_frame(&frame, &mut buf[..FRAME_LEN - 1]),
+            Err(CodecError::BufferTooSmall)
+        );
+
+        assert_eq!(Codec::decode(&buf[..FRAME_LEN - 1]), Ok((None, 0)));
+        assert_eq!(Codec::decode(&buf), Ok((Some(frame), FRAME_LEN)));
+    }
+
+    #[test]
+    fn decode_all_replay() {
+        let bus_number = BusNumber::default();
+        let frame =
+            |id| Frame::new(StandardId::new(id).unwrap(), &[id as u8]).unwrap();
+        let heartbeat = Packet::new_heartbeat(&[1; 6], &bus_number, &500);
+
+        // a captured stream: header, frames, heartbeat and a partial frame
+        let mut capture = Vec::new();
+        capture.extend_from_slice(heartbeat.header.as_bytes());
+        capture.extend_from_slice(frame(1).as_bytes());
+        capture.extend_from_slice(heartbeat.frame.as_bytes());
+        capture.extend_from_slice(frame(2).as_bytes());
+        capture.extend_from_slice(&frame(3).as_bytes()[..5]);
+
+        let (header, len) = Codec::decode_header(&capture).unwrap();
+        assert!(header.is_some());
+
+        let mut items = Vec::new();
+        let rest = Codec::decode_all(&capture[len..], bus_number, |item| {
+            items.push(item)
+        })
+        .unwrap();
+        assert_eq!(
+            items,
+            [
+                ReceivedItem::Data(frame(1)),
+                ReceivedItem::Heartbeat(Heartbeat {
+                    mac_addr: [1; 6],
+                    bus_number,
+                    data_rate: 500,
+                }),
+                ReceivedItem::Data(frame(2)),
+            ]
+        );
+        assert_eq!(rest, &frame(3).as_bytes()[..5]);
+
+        let mut bad = frame(4);
+        bad.set_dlc(9);
+        let mut capture = frame(5).as_bytes().to_vec();
+        capture.extend_from_slice(bad.as_bytes());
+        let mut count = 0;
+        let result = Codec::decode_all(&capture, bus_number, |_| count += 1);
+        assert_eq!(result, Err(CodecError::InvalidDlc));
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn endianness() {
+        let frame =
+            Frame::new(ExtendedId::new(0x1234567).unwrap(), &[1, 2]).unwrap();
+
+        let mut big = [0; FRAME_LEN];
+        Codec::encode_frame_with(&frame, &mut big, Endianness::Big).unwrap();
+        assert_eq!(big, *frame.as_bytes());
+        assert_eq!(big[..4], [0x01, 0x23, 0x45, 0x67]);
+
+        let mut little = [0; FRAME_LEN];
+        Codec::encode_frame_with(&frame, &mut little, Endianness::Little)
+            .unwrap();
+        assert_eq!(little[..4], [0x67, 0x45, 0x23, 0x01]);
+        assert_eq!(little[4..], big[4..]);
+
+        for (buf, endianness) in
+            [(big, Endianness::Big), (little, Endianness::Little)]
+        {
+            assert_eq!(
+                Codec::decode_with(&buf, endianness),
+                Ok((Some(Frame::read_from(&frame.0[..]).unwrap()), FRAME_LEN))
+            );
+        }
+        assert_eq!(Endianness::default(), Endianness::Big);
+    }
+
+    #[test]
+    fn invalid_dlc() {
+        let mut frame = Frame::new(StandardId::ZERO, &[0; 8]).unwrap();
+        frame.set_dlc(9);
+
+        let mut buf = [0; FRAME_LEN];
+        Codec::encode_frame(&frame, &mut buf).unwrap();
+        assert_eq!(Codec::decode(&buf), Err(CodecError::InvalidDlc));
+    }
+
+    #[cfg(feature = "frame-crc")]
+    #[test]
+    fn crc_check_value() {
+        assert_eq!(crc16(b"123456789"), 0x29B1);
+    }
+
+    #[cfg(feature = "frame-crc")]
+    #[test]
+    fn frame_crc_round_trip() {
+        let frame =
+            || Frame::new(StandardId::new(0x123).unwrap(), &[1, 2, 3]).unwrap();
+
+        let mut buf = [0; 32];
+        let len = Codec::encode_frame_crc(&frame(), &mut buf).unwrap();
+        assert_eq!(len, FRAME_LEN + CRC_LEN);
+        assert_eq!(buf[..FRAME_LEN], *frame().as_bytes());
+
+        assert_eq!(Codec::decode_crc(&buf[..len - 1]), Ok((None, 0)));
+        assert_eq!(Codec::decode_crc(&buf), Ok((Some(frame()), len)));
+    }
+
+    #[cfg(feature = "frame-crc")]
+    #[test]
+    fn frame_crc_corrupted() {
+        let frame = Frame::new(StandardId::new(0x123).unwrap(), &[1]).unwrap();
+
+        let mut buf = [0; FRAME_LEN + CRC_LEN];
+        Codec::encode_frame_crc(&frame, &mut buf).unwrap();
+        buf[6] ^= 0x01;
+        assert_eq!(Codec::decode_crc(&buf), Err(CodecError::BadCrc));
+    }
+
+    #[test]
+    fn decode_short_input() {
+        for len in [0, 1, FRAME_LEN - 1] {
+            assert_eq!(Codec::decode(&[0xFF; 16][..len]), Ok((None, 0)));
+        }
+        for len in [0, 1, HEADER_LEN - 1] {
+            assert_eq!(Codec::decode_header(&[0xFF; 16][..len]), Ok((None, 0)));
+        }
+    }
+
+    #[test]
+    fn decode_dlc_255() {
+        let mut buf = [0; FRAME_LEN];
+        buf[5] = 0xFF;
+        assert_eq!(Codec::decode(&buf), Err(CodecError::InvalidDlc));
+
+        // a frame read directly still clamps the DLC
+        let frame = Frame::read_from(&buf[..]).unwrap();
+        assert_eq!(frame.dlc(), 8);
+        assert_eq!(frame.data().len(), 8);
+        assert!(frame.to_frame::<Frame>().is_none());
+    }
+
+    #[test]
+    fn stream_decoder_bare_frames() {
+        let frame =
+            |id| Frame::new(StandardId::new(id).unwrap(), &[id as u8]).unwrap();
+        let header = Header::with(PROTOCOL_VERSION, 1, 0x1234);
+
+        let mut stream = header.as_bytes().to_vec();
+        for id in 0..10 {
+            stream.extend_from_slice(frame(id).as_bytes());
+        }
+
+        let mut decoder = StreamDecoder::new();
+        assert_eq!(decoder.decode(&stream[..4]), Ok((None, 0)));
+        assert!(!decoder.header_seen());
+
+        let (item, len) = decoder.decode(&stream).unwrap();
+        assert_eq!(item, Some(StreamItem::Header(header)));
+        assert!(decoder.header_seen());
+
+        let mut rest = &stream[len..];
+        for id in 0..10 {
+            let (item, len) = decoder.decode(rest).unwrap();
+            assert_eq!(item, Some(StreamItem::Frame(frame(id))));
+            rest = &rest[len..];
+        }
+        assert!(rest.is_empty());
+        assert_eq!(decoder.decode(rest), Ok((None, 0)));
+
+        // a new connection starts with a header again
+        decoder.reset();
+        let (item, _) = decoder.decode(&stream).unwrap();
+        assert!(matches!(item, Some(StreamItem::Header(_))));
+    }
+
+    #[test]
+    fn header_round_trip() {
+        let mut header = Header::new();
+        header.set_version(PROTOCOL_VERSION);
+        header.set_bus_number(BusNumber::default().into());
+        header.set_client_identifier(0x1234);
+
+        let mut buf = [0; HEADER_LEN];
+        assert_eq!(Codec::encode_header(&header, &mut buf), Ok(HEADER_LEN));
+        assert_eq!(Codec::decode_header(&buf[..4]), Ok((None, 0)));
+        assert_eq!(Codec::decode_header(&buf), Ok((Some(header), HEADER_LEN)));
+
+        assert_eq!(
+            Codec::decode_header(&[0; HEADER_LEN]),
+            Err(CodecError::Header(HeaderError::UnsupportedVersion(0)))
+        );
+    }
+}
@@ -1,34 +1,172 @@
-use crate::{BusNumber, Flags, PROTOCOL_VERSION};
+#[cfg(feature = "heapless")]
+use crate::codec::{Codec, CodecError};
+use crate::{BusNumber, Flags, FrameFlags, PROTOCOL_VERSION};
 use embedded_can::{ExtendedId, Id, StandardId};
-use zerocopy::{AsBytes, FromBytes, FromZeroes};
+use zerocopy::{AsBytes, FromBytes, FromZeroes, Ref};
 
 /// Datagram header length.
-const HEADER_LEN: usize = 16;
+pub const HEADER_LEN: usize = 16;
 
 bitfield::bitfield! {
     /// Datagram header, used when receiving UDP data and sending TCP data.
-    #[derive(AsBytes, FromBytes, FromZeroes)]
+    ///
+    /// Fields are big-endian on the wire whatever the host byte order.
+    #[derive(AsBytes, FromBytes, FromZeroes, PartialEq, Eq, Hash)]
     #[repr(transparent)]
     #[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
     pub struct HeaderBitfield(MSB0 [u8]);
     impl Debug;
     pub u64, version, set_version: 59, 8;
     pub u8, bus_number, set_bus_number: 63, 60;
+    /// Whether the bridge should forward frames to its other buses.
+    ///
+    /// Held in the otherwise reserved byte before the client identifier,
+    /// peers that don't know it see the byte as zero.
+    pub forward, set_forward: 71;
     pub u64, client_identifier, set_client_identifier: 127, 72;
 }
 
 pub type Header = HeaderBitfield<[u8; HEADER_LEN]>;
 
 impl Header {
-    pub fn new() -> Self {
+    pub const fn new() -> Self {
         HeaderBitfield([0; HEADER_LEN])
     }
+
+    /// Create a header from its fields, usable in `const` contexts.
+    ///
+    /// Fields are truncated to their width on the wire like the setters.
+    ///
+    /// ```
+    /// use tritiumcan::{datagram::Header, PROTOCOL_VERSION};
+    ///
+    /// static HEADER: Header = Header::with(PROTOCOL_VERSION, 13, 0x1234);
+    /// assert_eq!(HEADER.bus_number(), 13);
+    /// ```
+    pub const fn with(
+        version: u64,
+        bus_number: u8,
+        client_identifier: u64,
+    ) -> Self {
+        // bytes 0..8 hold the version then the bus number, bytes 8..16 the
+        // client identifier
+        let high =
+            (version & 0x000F_FFFF_FFFF_FFFF) << 4 | (bus_number & 0xF) as u64;
+        let low = client_identifier & 0x00FF_FFFF_FFFF_FFFF;
+
+        let (high, low) = (high.to_be_bytes(), low.to_be_bytes());
+        let mut bytes = [0; HEADER_LEN];
+        let mut n = 0;
+        while n < 8 {
+            bytes[n] = high[n];
+            bytes[n + 8] = low[n];
+            n += 1;
+        }
+
+        HeaderBitfield(bytes)
+    }
+
+    /// Parse and validate a header from the start of `bytes`.
+    ///
+    /// Any 4-bit bus number is a valid [`BusNumber`], so only the length and
+    /// version are checked.
+    pub fn parse(bytes: &[u8]) -> Result<Header, HeaderError> {
+        let header = bytes
+            .get(..HEADER_LEN)
+            .and_then(Header::read_from)
+            .ok_or(HeaderError::TooShort)?;
+
+        if header.version() != PROTOCOL_VERSION {
+            return Err(HeaderError::UnsupportedVersion(header.version()));
+        }
+
+        Ok(header)
+    }
+}
+
+/// Error parsing a [`Header`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub enum HeaderError {
+    /// Fewer than a header's worth of bytes.
+    TooShort,
+    /// Protocol version identifier doesn't match [`PROTOCOL_VERSION`].
+    UnsupportedVersion(u64),
+}
+
+impl Default for Header {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl embedded_can::Frame for Frame {
     fn new(id: impl Into<Id>, data: &[u8]) -> Option<Self> {
+        Frame::new(id, data).ok()
+    }
+
+    fn new_remote(id: impl Into<Id>, dlc: usize) -> Option<Self> {
+        Frame::new_remote(id, dlc).ok()
+    }
+
+    fn is_extended(&self) -> bool {
+        self.is_extended()
+    }
+
+    fn is_remote_frame(&self) -> bool {
+        self.is_remote()
+    }
+
+    fn id(&self) -> Id {
+        self.id()
+    }
+
+    fn dlc(&self) -> usize {
+        self.dlc()
+    }
+
+    fn data(&self) -> &[u8] {
+        self.data()
+    }
+}
+
+/// Frame datagram length.
+pub const FRAME_LEN: usize = 14;
+
+bitfield::bitfield! {
+    /// Frame datagram only including the CAN frame section.
+    ///
+    /// Used for incomming frames on a TCP connection stream. Like the
+    /// [`Header`], the ID and data are big-endian on the wire.
+    ///
+    /// With the `defmt-03` feature frames are logged decoded, in the same
+    /// format as [`Display`](core::fmt::Display):
+    ///
+    /// ```rust,ignore
+    /// if let Some(frame) = server.recv_frame(&mut sockets)? {
+    ///     defmt::info!("{}", frame); // ID=0x123 [2] 01 02
+    /// }
+    /// ```
+    #[derive(AsBytes, FromBytes, FromZeroes, PartialEq, Eq, Hash)]
+    #[repr(transparent)]
+    pub struct FrameBitfield(MSB0 [u8]);
+    impl Debug;
+    impl FromZeroes;
+    impl FromBytes;
+    impl AsBytes;
+    pub u32, raw_id, set_raw_id: 31, 0;
+    pub u8, flags, set_flags: 39, 32;
+    pub u8, raw_dlc, set_dlc: 47, 40;
+    pub u64, raw_data, set_raw_data: 111, 48;
+}
+
+pub type Frame = FrameBitfield<[u8; FRAME_LEN]>;
+
+impl Frame {
+    /// Create a data frame, rejecting more than 8 bytes of data.
+    pub fn new(id: impl Into<Id>, data: &[u8]) -> Result<Self, FrameError> {
         if data.len() > 8 {
-            return None;
+            return Err(FrameError::DataTooLong);
         }
 
         let (flags, id) = match id.into() {
@@ -39,18 +177,22 @@ impl embedded_can::Frame for Frame {
         let mut can_data = [0u8; 8];
         can_data[..data.len()].copy_from_slice(data);
 
-        let mut datagram = Frame::new();
-        datagram.set_id(id);
+        let mut datagram = Frame::new_zeroed();
+        datagram.set_raw_id(id);
         datagram.set_flags(flags.bits());
         datagram.set_dlc(data.len() as u8);
-        datagram.set_data(u64::from_be_bytes(can_data));
+        datagram.set_raw_data(u64::from_be_bytes(can_data));
 
-        Some(datagram)
+        Ok(datagram)
     }
 
-    fn new_remote(id: impl Into<Id>, dlc: usize) -> Option<Self> {
+    /// Create a remote frame, rejecting a DLC above 8.
+    pub fn new_remote(
+        id: impl Into<Id>,
+        dlc: usize,
+    ) -> Result<Self, FrameError> {
         if dlc > 8 {
-            return None;
+            return Err(FrameError::InvalidDlc);
         }
 
         let (mut flags, id) = match id.into() {
@@ -60,197 +202,1992 @@ impl embedded_can::Frame for Frame {
 
         flags |= Flags::Remote;
 
-        let mut datagram = Frame::new();
-        datagram.set_id(id);
+        let mut datagram = Frame::new_zeroed();
+        datagram.set_raw_id(id);
         datagram.set_flags(flags.bits());
         datagram.set_dlc(dlc as u8);
-        datagram.set_data(0);
+        datagram.set_raw_data(0);
 
-        Some(datagram)
+        Ok(datagram)
     }
 
-    fn is_extended(&self) -> bool {
-        Flags::from_bits(self.flags())
-            .unwrap()
-            .intersects(Flags::Extended)
+    /// Create a frame from a raw ID and flags byte, written verbatim.
+    ///
+    /// The DLC is the length of `data`, rejecting more than 8 bytes. For
+    /// remote frames only the length of `data` is kept.
+    pub fn from_parts(
+        id: u32,
+        flags: u8,
+        data: &[u8],
+    ) -> Result<Self, FrameError> {
+        if data.len() > 8 {
+            return Err(FrameError::DataTooLong);
+        }
+
+        let mut can_data = [0u8; 8];
+        if !Flags::from_bits_truncate(flags).intersects(Flags::Remote) {
+            can_data[..data.len()].copy_from_slice(data);
+        }
+
+        let mut datagram = Frame::new_zeroed();
+        datagram.set_raw_id(id);
+        datagram.set_flags(flags);
+        datagram.set_dlc(data.len() as u8);
+        datagram.set_raw_data(u64::from_be_bytes(can_data));
+
+        Ok(datagram)
     }
 
-    fn is_remote_frame(&self) -> bool {
-        Flags::from_bits(self.flags())
-            .unwrap()
-            .intersects(Flags::Remote)
+    /// Create a data frame from an integer ID, extended if it doesn't fit
+    /// in 11 bits.
+    ///
+    /// Rejects an ID above 29 bits and more than 8 bytes of data.
+    pub fn from_raw_id(id: u32, data: &[u8]) -> Result<Self, FrameError> {
+        let id: Id = match u16::try_from(id).ok().and_then(StandardId::new) {
+            Some(id) => id.into(),
+            None => ExtendedId::new(id).ok_or(FrameError::InvalidId)?.into(),
+        };
+
+        Self::new(id, data)
     }
 
-    fn id(&self) -> Id {
+    /// Typed flags byte, unknown bits are kept.
+    pub fn frame_flags(&self) -> FrameFlags {
+        FrameFlags::from_bits_retain(self.flags())
+    }
+
+    /// Replace the flags byte.
+    pub fn set_frame_flags(&mut self, flags: FrameFlags) {
+        self.set_flags(flags.bits());
+    }
+
+    /// Whether the frame uses a 29-bit extended identifier.
+    pub fn is_extended(&self) -> bool {
+        self.frame_flags().is_extended()
+    }
+
+    /// Whether the frame is a remote transmission request.
+    pub fn is_remote(&self) -> bool {
+        self.frame_flags().is_remote()
+    }
+
+    /// Whether this is a bridge heartbeat rather than a CAN frame.
+    pub fn is_heartbeat(&self) -> bool {
+        self.frame_flags().is_heartbeat()
+    }
+
+    /// Whether this is a settings frame rather than a CAN frame.
+    pub fn is_settings(&self) -> bool {
+        self.frame_flags().is_settings()
+    }
+
+    /// Data rate carried by a settings frame.
+    pub fn settings_data_rate(&self) -> Option<u16> {
+        if !self.is_settings() {
+            return None;
+        }
+
+        let data = self.raw_data().to_be_bytes();
+        Some(u16::from_be_bytes([data[0], data[1]]))
+    }
+
+    /// CAN identifier, masked to 11 or 29 bits depending on the extended
+    /// flag.
+    pub fn id(&self) -> Id {
         if self.is_extended() {
-            Id::Extended(ExtendedId::new(self.id()).unwrap())
+            let raw = self.raw_id() & ExtendedId::MAX.as_raw();
+            Id::Extended(ExtendedId::new(raw).unwrap())
         } else {
-            Id::Standard(StandardId::new(self.id() as u16).unwrap())
+            let raw = self.raw_id() as u16 & StandardId::MAX.as_raw();
+            Id::Standard(StandardId::new(raw).unwrap())
         }
     }
-    fn dlc(&self) -> usize {
-        self.dlc() as usize
+
+    /// Data length code, clamped to 8.
+    pub fn dlc(&self) -> usize {
+        (self.raw_dlc() as usize).min(8)
     }
 
-    fn data(&self) -> &[u8] {
-        // todo: check if this has the right byte order
-        &self.0[22..]
+    /// Payload sliced to the DLC, empty for remote frames.
+    pub fn data(&self) -> &[u8] {
+        if self.is_remote() {
+            &[]
+        } else {
+            &self.0[6..6 + self.dlc()]
+        }
     }
-}
 
-pub const FRAME_LEN: usize = 14;
+    /// Mutable payload sliced to the DLC, empty for remote frames.
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        if self.is_remote() {
+            &mut []
+        } else {
+            let dlc = self.dlc();
+            &mut self.0[6..6 + dlc]
+        }
+    }
 
-bitfield::bitfield! {
-    /// Frame datagram only including the CAN frame section.
-    ///
-    /// Used for incomming frames on a TCP connection stream.
-    #[derive(AsBytes, FromBytes, FromZeroes)]
-    #[repr(transparent)]
-    #[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
-    pub struct FrameBitfield(MSB0 [u8]);
-    impl Debug;
-    impl FromZeroes;
-    impl FromBytes;
-    impl AsBytes;
-    pub u32, id, set_id: 31, 0;
-    pub u8, flags, set_flags: 39, 32;
-    pub u8, dlc, set_dlc: 47, 40;
-    pub u64, data, set_data: 111, 48;
-}
+    /// Replace the payload and set the DLC to its length, rejecting more
+    /// than 8 bytes or a remote frame.
+    pub fn set_data(&mut self, data: &[u8]) -> Result<(), FrameError> {
+        if data.len() > 8 {
+            return Err(FrameError::DataTooLong);
+        }
+        if self.is_remote() {
+            return Err(FrameError::RemoteFrame);
+        }
 
-pub type Frame = FrameBitfield<[u8; FRAME_LEN]>;
+        let mut can_data = [0u8; 8];
+        can_data[..data.len()].copy_from_slice(data);
+        self.set_dlc(data.len() as u8);
+        self.set_raw_data(u64::from_be_bytes(can_data));
 
-impl Frame {
-    pub fn new() -> Self {
-        FrameBitfield([0; FRAME_LEN])
+        Ok(())
     }
 
-    pub fn from_frame(frame: &impl embedded_can::Frame) -> Result<Self, ()> {
+    /// Build a frame from any [`embedded_can::Frame`] implementation.
+    pub fn from_frame(
+        frame: &impl embedded_can::Frame,
+    ) -> Result<Self, FrameError> {
         if frame.dlc() > 8 {
             // we only support standard frames of up to 8 bytes in length.
-            return Err(()); // todo: descriptive error.
+            return Err(FrameError::DataTooLong);
         }
 
-        let mut data: u64 = 0;
+        if frame.is_remote_frame() {
+            Frame::new_remote(frame.id(), frame.dlc())
+        } else {
+            let len = frame.data().len().min(frame.dlc());
+            Frame::new(frame.id(), &frame.data()[..len])
+        }
+    }
 
-        for (n, &byte) in frame.data().iter().rev().enumerate() {
-            if n < frame.dlc() as usize {
-                data |= (byte as u64) << (n * 8);
-            } else {
-                break;
-            }
+    /// Convert into any [`embedded_can::Frame`] implementation.
+    ///
+    /// Returns `None` if the stored identifier or DLC is invalid, or if `F`
+    /// rejects the frame.
+    pub fn to_frame<F: embedded_can::Frame>(&self) -> Option<F> {
+        let flags = self.frame_flags();
+        let dlc = self.raw_dlc() as usize;
+
+        if dlc > 8 {
+            return None;
         }
 
-        let mut dg = Frame::new();
-        dg.set_flags(Flags::from_frame(frame).bits());
-        dg.set_id(match frame.id() {
-            Id::Standard(id) => id.as_raw() as u32,
-            Id::Extended(id) => id.as_raw(),
-        });
-        dg.set_dlc(frame.dlc() as u8);
-        dg.set_data(data);
+        let id = if flags.intersects(Flags::Extended) {
+            Id::Extended(ExtendedId::new(self.raw_id())?)
+        } else {
+            Id::Standard(StandardId::new(u16::try_from(self.raw_id()).ok()?)?)
+        };
 
-        Ok(dg)
+        if flags.intersects(Flags::Remote) {
+            F::new_remote(id, dlc)
+        } else {
+            F::new(id, &self.0[6..6 + dlc])
+        }
     }
 }
 
-/// Complete datagram packet.
-///
-/// Used when receiving UDP frames and sending frames for both UDP and TCP.
-#[derive(Debug, FromBytes, AsBytes, FromZeroes)]
-#[repr(C)]
-#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
-pub struct Packet {
-    pub header: Header,
-    pub frame: Frame,
+impl Default for Frame {
+    fn default() -> Self {
+        Self::new_zeroed()
+    }
 }
 
-impl Packet {
-    pub fn new_heartbeat(
-        mac_addr: &[u8; 6],
-        bus_number: &BusNumber,
-        data_rate: &u16,
-    ) -> Self {
-        let flags = Flags::Heartbeat;
-
-        let mut data = [0u8; 8];
-        // bitrate
-        data[0..2].copy_from_slice(&data_rate.to_be_bytes());
-        data[2..8].copy_from_slice(mac_addr);
+impl core::fmt::Display for Frame {
+    /// Formats as `ID=0x123 [2] 01 02`, extended identifiers padded to eight
+    /// digits and remote frames shown as `ID=0x123 RTR [2]`.
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self.id() {
+            Id::Standard(id) => write!(f, "ID=0x{:03X}", id.as_raw())?,
+            Id::Extended(id) => write!(f, "ID=0x{:08X}", id.as_raw())?,
+        }
 
-        let mut packet = Packet {
-            header: Header::new(),
-            frame: Frame::new(),
-        };
+        if self.is_remote() {
+            f.write_str(" RTR")?;
+        }
 
-        // metadata
-        packet.header.set_version(PROTOCOL_VERSION);
-        packet.header.set_bus_number(bus_number.0);
-        packet
-            .header
-            .set_client_identifier(u64::from_be_bytes([0u8; 8]));
+        write!(f, " [{}]", self.dlc())?;
 
-        // frame
-        packet.frame.set_flags(flags.bits());
-        packet.frame.set_id(0);
-        packet.frame.set_dlc(data.len() as u8);
-        packet.frame.set_data(u64::from_be_bytes(data));
+        for byte in self.data() {
+            write!(f, " {:02X}", byte)?;
+        }
 
-        packet
+        Ok(())
     }
+}
 
-    pub fn as_bytes(&self) -> &[u8] {
-        // is safe because we use size_of::<Packet>
-        unsafe {
-            ::core::slice::from_raw_parts(
-                self as *const _ as *const u8,
-                ::core::mem::size_of::<Packet>(),
-            )
+#[cfg(feature = "defmt-03")]
+impl defmt::Format for Frame {
+    fn format(&self, f: defmt::Formatter) {
+        match self.id() {
+            Id::Standard(id) => defmt::write!(f, "ID={=u16:#x}", id.as_raw()),
+            Id::Extended(id) => defmt::write!(f, "ID={=u32:#x}", id.as_raw()),
+        }
+
+        if self.is_remote() {
+            defmt::write!(f, " RTR");
+        }
+
+        defmt::write!(f, " [{=usize}]", self.dlc());
+
+        for byte in self.data() {
+            defmt::write!(f, " {=u8:02x}", byte);
         }
     }
 }
 
-/// Filter setting datagram length.
-pub const FILTER_LEN: usize = 24;
+/// Error building a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub enum FrameError {
+    /// More data than the frame type can carry.
+    DataTooLong,
+    /// DLC out of range for the frame type.
+    InvalidDlc,
+    /// The frame type doesn't support remote frames.
+    RemoteFrame,
+    /// Identifier doesn't fit in 29 bits.
+    InvalidId,
+    /// The frame type only has 11-bit identifiers.
+    #[cfg(feature = "can-xl")]
+    ExtendedId,
+}
+
+/// CAN FD frame datagram length.
+pub const FD_FRAME_LEN: usize = 70;
+
+/// Maximum CAN FD payload length.
+const FD_DATA_LEN: usize = 64;
+
+/// Payload lengths for CAN FD DLC codes above 8.
+const FD_LENGTHS: [usize; 7] = [12, 16, 20, 24, 32, 48, 64];
+
+/// Payload length for a CAN FD DLC code.
+pub const fn fd_dlc_to_len(dlc: u8) -> usize {
+    match dlc {
+        0..=8 => dlc as usize,
+        9..=15 => FD_LENGTHS[dlc as usize - 9],
+        _ => FD_DATA_LEN,
+    }
+}
+
+/// CAN FD DLC code for a payload length, if the length is valid.
+pub fn fd_len_to_dlc(len: usize) -> Option<u8> {
+    match len {
+        0..=8 => Some(len as u8),
+        _ => FD_LENGTHS
+            .iter()
+            .position(|&l| l == len)
+            .map(|n| n as u8 + 9),
+    }
+}
 
 bitfield::bitfield! {
-    /// Datagram use for filt
+    /// CAN FD frame datagram with up to 64 bytes of data.
+    ///
+    /// Shares the layout of [`Frame`] with a larger data section, the DLC
+    /// holding the FD length code.
+    #[derive(AsBytes, FromBytes, FromZeroes, PartialEq, Eq, Hash)]
+    #[repr(transparent)]
     #[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
-    pub struct FilterBitfield(MSB0 [u8]);
+    pub struct FdFrameBitfield(MSB0 [u8]);
     impl Debug;
-    pub u32, fwd_identifier, set_fwd_identifier: 31, 0;
-    pub u32, fwd_range, set_fwd_range: 63, 32;
-    pub u8, bus_number, set_bus_bumber: 71, 64;
-    pub u64, version_number, set_version_number: 123, 72;
-    pub u64, client_identifier, set_client_identifier: 187, 132;
+    pub u32, raw_id, set_raw_id: 31, 0;
+    pub u8, flags, set_flags: 39, 32;
+    pub u8, dlc, set_dlc: 47, 40;
 }
 
-pub type Filter = FilterBitfield<[u8; FILTER_LEN]>;
+pub type FdFrame = FdFrameBitfield<[u8; FD_FRAME_LEN]>;
 
-impl Filter {
+impl FdFrame {
     pub fn new() -> Self {
-        FilterBitfield([0; FILTER_LEN])
+        FdFrameBitfield([0; FD_FRAME_LEN])
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Typed flags byte, unknown bits are kept.
+    pub fn frame_flags(&self) -> FrameFlags {
+        FrameFlags::from_bits_retain(self.flags())
+    }
 
-    use core::mem::size_of;
+    /// Whether the frame uses a 29-bit extended identifier.
+    pub fn is_extended(&self) -> bool {
+        self.frame_flags().is_extended()
+    }
 
-    #[test]
-    fn header_type_length() {
-        assert_eq!(size_of::<Header>(), 16)
+    /// Whether the data phase uses the faster bit rate.
+    pub fn bit_rate_switch(&self) -> bool {
+        self.frame_flags().is_bit_rate_switch()
     }
 
-    #[test]
-    fn frame_type_length() {
-        assert_eq!(size_of::<Frame>(), 14)
+    pub fn set_bit_rate_switch(&mut self, brs: bool) {
+        let mut flags = self.frame_flags();
+        flags.set(Flags::BitRateSwitch, brs);
+        self.set_flags(flags.bits());
     }
 
-    #[test]
-    fn packet_type_length() {
-        assert_eq!(size_of::<Packet>(), 30)
+    /// CAN identifier, masked to 11 or 29 bits depending on the extended
+    /// flag.
+    pub fn id(&self) -> Id {
+        if self.is_extended() {
+            let raw = self.raw_id() & ExtendedId::MAX.as_raw();
+            Id::Extended(ExtendedId::new(raw).unwrap())
+        } else {
+            let raw = self.raw_id() as u16 & StandardId::MAX.as_raw();
+            Id::Standard(StandardId::new(raw).unwrap())
+        }
+    }
+
+    /// Payload, sized by the DLC code.
+    pub fn data(&self) -> &[u8] {
+        &self.0[6..6 + fd_dlc_to_len(self.dlc())]
+    }
+
+    /// Build an FD frame from any [`embedded_can::Frame`] whose data length
+    /// is a valid CAN FD length.
+    ///
+    /// The FDF flag is always set, BRS is left clear.
+    pub fn from_frame(
+        frame: &impl embedded_can::Frame,
+    ) -> Result<Self, FrameError> {
+        let data = frame.data();
+
+        if data.len() > FD_DATA_LEN {
+            return Err(FrameError::DataTooLong);
+        }
+
+        let dlc = fd_len_to_dlc(data.len()).ok_or(FrameError::InvalidDlc)?;
+
+        if frame.is_remote_frame() {
+            // remote frames don't exist in CAN FD
+            return Err(FrameError::RemoteFrame);
+        }
+
+        let mut fd = FdFrame::new();
+        fd.set_flags((Flags::from_frame(frame) | Flags::Fd).bits());
+        fd.set_raw_id(match frame.id() {
+            Id::Standard(id) => id.as_raw() as u32,
+            Id::Extended(id) => id.as_raw(),
+        });
+        fd.set_dlc(dlc);
+        fd.0[6..6 + data.len()].copy_from_slice(data);
+
+        Ok(fd)
+    }
+
+    /// Convert into any [`embedded_can::Frame`] implementation.
+    ///
+    /// Returns `None` if the stored identifier or DLC is invalid, or if `F`
+    /// rejects the frame.
+    pub fn to_frame<F: embedded_can::Frame>(&self) -> Option<F> {
+        if self.dlc() > 15 {
+            return None;
+        }
+
+        let id = if self.is_extended() {
+            Id::Extended(ExtendedId::new(self.raw_id())?)
+        } else {
+            Id::Standard(StandardId::new(u16::try_from(self.raw_id()).ok()?)?)
+        };
+
+        F::new(id, self.data())
+    }
+}
+
+impl Default for FdFrame {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Maximum CAN XL payload length.
+#[cfg(feature = "can-xl")]
+pub const XL_DATA_LEN: usize = 2048;
+
+/// Offset of the payload in an [`XlFrame`].
+#[cfg(feature = "can-xl")]
+const XL_DATA_START: usize = 13;
+
+/// CAN XL frame datagram length with the largest payload.
+#[cfg(feature = "can-xl")]
+pub const XL_FRAME_LEN: usize = XL_DATA_START + XL_DATA_LEN;
+
+#[cfg(feature = "can-xl")]
+bitfield::bitfield! {
+    /// CAN XL frame datagram with 1 to 2048 bytes of data.
+    ///
+    /// Starts like [`Frame`] with the 11-bit priority identifier and flags,
+    /// followed by the SDU type, virtual CAN network ID, acceptance field
+    /// and the payload length. Only that length of payload goes on the
+    /// wire, see [`XlFrame::as_bytes`].
+    #[derive(PartialEq, Eq, Hash)]
+    #[repr(transparent)]
+    #[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+    pub struct XlFrameBitfield(MSB0 [u8]);
+    impl Debug;
+    pub u32, raw_id, set_raw_id: 31, 0;
+    pub u8, flags, set_flags: 39, 32;
+    /// SDU type (SDT) of the payload.
+    pub u8, sdt, set_sdt: 47, 40;
+    /// Virtual CAN network ID (VCID).
+    pub u8, vcid, set_vcid: 55, 48;
+    /// Acceptance field (AF).
+    pub u32, af, set_af: 87, 56;
+    pub u16, raw_len, set_raw_len: 103, 88;
+}
+
+#[cfg(feature = "can-xl")]
+pub type XlFrame = XlFrameBitfield<[u8; XL_FRAME_LEN]>;
+
+#[cfg(feature = "can-xl")]
+impl XlFrame {
+    /// Create a zeroed frame with the XLF flag set.
+    ///
+    /// The payload is empty, which isn't valid on the wire until
+    /// [`XlFrame::set_data`] is called.
+    pub fn new() -> Self {
+        let mut frame = XlFrameBitfield([0; XL_FRAME_LEN]);
+        frame.set_flags(Flags::Xl.bits());
+        frame
+    }
+
+    /// Typed flags byte, unknown bits are kept.
+    pub fn frame_flags(&self) -> FrameFlags {
+        FrameFlags::from_bits_retain(self.flags())
+    }
+
+    /// Priority identifier, masked to 11 bits.
+    pub fn id(&self) -> StandardId {
+        let raw = self.raw_id() as u16 & StandardId::MAX.as_raw();
+        StandardId::new(raw).unwrap()
+    }
+
+    pub fn set_id(&mut self, id: StandardId) {
+        self.set_raw_id(id.as_raw().into());
+    }
+
+    /// Payload length in bytes.
+    pub fn len(&self) -> usize {
+        usize::from(self.raw_len()).min(XL_DATA_LEN)
+    }
+
+    /// Whether the payload is empty, only the case before
+    /// [`XlFrame::set_data`].
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.0[XL_DATA_START..XL_DATA_START + self.len()]
+    }
+
+    /// Replace the payload, which must be 1 to [`XL_DATA_LEN`] bytes.
+    pub fn set_data(&mut self, data: &[u8]) -> Result<(), FrameError> {
+        if data.len() > XL_DATA_LEN {
+            return Err(FrameError::DataTooLong);
+        }
+        if data.is_empty() {
+            return Err(FrameError::InvalidDlc);
+        }
+
+        self.0[XL_DATA_START..XL_DATA_START + data.len()].copy_from_slice(data);
+        self.set_raw_len(data.len() as u16);
+        Ok(())
+    }
+
+    /// Build an XL frame from any [`embedded_can::Frame`] with a standard
+    /// identifier and a payload of 1 to [`XL_DATA_LEN`] bytes.
+    ///
+    /// The SDU type, VCID and acceptance field are left zero.
+    pub fn from_frame(
+        frame: &impl embedded_can::Frame,
+    ) -> Result<Self, FrameError> {
+        if frame.is_remote_frame() {
+            // remote frames don't exist in CAN XL
+            return Err(FrameError::RemoteFrame);
+        }
+        let Id::Standard(id) = frame.id() else {
+            return Err(FrameError::ExtendedId);
+        };
+
+        let mut xl = XlFrame::new();
+        xl.set_id(id);
+        xl.set_data(frame.data())?;
+        Ok(xl)
+    }
+
+    /// Convert into any [`embedded_can::Frame`] implementation, or `None`
+    /// if `F` rejects the frame.
+    pub fn to_frame<F: embedded_can::Frame>(&self) -> Option<F> {
+        F::new(self.id(), self.data())
+    }
+
+    /// Length of the frame on the wire.
+    pub fn wire_len(&self) -> usize {
+        XL_DATA_START + self.len()
+    }
+
+    /// The frame as sent on the wire, only as long as its payload.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0[..self.wire_len()]
+    }
+
+    /// Read a frame from the start of `bytes`, returning `None` if `bytes`
+    /// is too short or doesn't start with an XL frame.
+    ///
+    /// The frame takes [`XlFrame::wire_len`] bytes.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let start = bytes.get(..XL_DATA_START)?;
+        let mut frame = XlFrameBitfield([0; XL_FRAME_LEN]);
+        frame.0[..XL_DATA_START].copy_from_slice(start);
+
+        let len = usize::from(frame.raw_len());
+        if !frame.frame_flags().contains(Flags::Xl)
+            || !(1..=XL_DATA_LEN).contains(&len)
+        {
+            return None;
+        }
+
+        let data = bytes.get(XL_DATA_START..XL_DATA_START + len)?;
+        frame.0[XL_DATA_START..XL_DATA_START + len].copy_from_slice(data);
+        Some(frame)
+    }
+}
+
+#[cfg(feature = "can-xl")]
+impl Default for XlFrame {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Frame borrowed from a received byte buffer, read in place.
+///
+/// Lets a receive path look at the ID, for example to filter, without
+/// copying the frame out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameRef<'a>(&'a Frame);
+
+impl<'a> FrameRef<'a> {
+    /// View the frame at the start of `bytes`, or `None` if `bytes` is
+    /// shorter than [`FRAME_LEN`].
+    pub fn from_bytes(bytes: &'a [u8]) -> Option<Self> {
+        Frame::ref_from_prefix(bytes).map(Self)
+    }
+
+    /// CAN identifier, see [`Frame::id`].
+    pub fn id(&self) -> Id {
+        self.0.id()
+    }
+
+    /// Data length code, see [`Frame::dlc`].
+    pub fn dlc(&self) -> usize {
+        self.0.dlc()
+    }
+
+    /// Data bytes, see [`Frame::data`].
+    pub fn data(&self) -> &'a [u8] {
+        self.0.data()
+    }
+
+    /// Raw flags byte.
+    pub fn flags(&self) -> u8 {
+        self.0.flags()
+    }
+
+    /// Typed flags byte, see [`Frame::frame_flags`].
+    pub fn frame_flags(&self) -> FrameFlags {
+        self.0.frame_flags()
+    }
+
+    /// The borrowed frame.
+    pub fn frame(&self) -> &'a Frame {
+        self.0
+    }
+}
+
+impl<'a> From<Ref<&'a [u8], Frame>> for FrameRef<'a> {
+    fn from(frame: Ref<&'a [u8], Frame>) -> Self {
+        Self(frame.into_ref())
+    }
+}
+
+impl From<FrameRef<'_>> for Frame {
+    fn from(frame: FrameRef<'_>) -> Self {
+        Frame::read_from(frame.0.as_bytes()).unwrap()
+    }
+}
+
+/// Iterator over the whole frames in a received byte buffer.
+///
+/// Trailing bytes that don't make up a whole frame are left in
+/// [`FrameIter::remainder`] so they can be completed by the next read.
+#[derive(Debug, Clone)]
+pub struct FrameIter<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> FrameIter<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    /// Bytes not yet consumed by the iterator.
+    pub fn remainder(&self) -> &'a [u8] {
+        self.bytes
+    }
+}
+
+impl Iterator for FrameIter<'_> {
+    type Item = Frame;
+
+    fn next(&mut self) -> Option<Frame> {
+        if self.bytes.len() < FRAME_LEN {
+            return None;
+        }
+
+        let (frame, rest) = self.bytes.split_at(FRAME_LEN);
+        self.bytes = rest;
+
+        Frame::read_from(frame)
+    }
+}
+
+/// Complete datagram packet.
+///
+/// Used when receiving UDP frames and sending frames for both UDP and TCP.
+#[derive(Debug, FromBytes, AsBytes, FromZeroes, PartialEq, Eq, Hash)]
+#[repr(C)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub struct Packet {
+    pub header: Header,
+    pub frame: Frame,
+}
+
+/// Complete datagram packet length.
+pub const PACKET_LEN: usize = HEADER_LEN + FRAME_LEN;
+
+// buffer math across the crates relies on these matching the layouts
+const _: () = assert!(core::mem::size_of::<Header>() == HEADER_LEN);
+const _: () = assert!(core::mem::size_of::<Frame>() == FRAME_LEN);
+const _: () = assert!(core::mem::size_of::<Packet>() == PACKET_LEN);
+
+impl Packet {
+    /// Length of a heartbeat as broadcast over UDP, the whole packet.
+    ///
+    /// On a TCP stream only the heartbeat's [`Frame`] is sent, which is
+    /// [`FRAME_LEN`] bytes.
+    pub const fn heartbeat_len() -> usize {
+        PACKET_LEN
+    }
+
+    pub fn new_heartbeat(
+        mac_addr: &[u8; 6],
+        bus_number: &BusNumber,
+        data_rate: &u16,
+    ) -> Self {
+        let mut data = [0u8; 8];
+        // bitrate
+        data[0..2].copy_from_slice(&data_rate.to_be_bytes());
+        data[2..8].copy_from_slice(mac_addr);
+
+        Self::new_control(bus_number, Flags::Heartbeat, data)
+    }
+
+    /// Create a settings packet pushing bus configuration to a bridge.
+    pub fn new_settings(bus_number: &BusNumber, data_rate: &u16) -> Self {
+        let mut data = [0u8; 8];
+        // bitrate
+        data[0..2].copy_from_slice(&data_rate.to_be_bytes());
+
+        Self::new_control(bus_number, Flags::Settings, data)
+    }
+
+    /// Packet carrying bridge control data rather than a CAN frame.
+    fn new_control(
+        bus_number: &BusNumber,
+        flags: Flags,
+        data: [u8; 8],
+    ) -> Self {
+        let mut packet = Packet {
+            header: Header::new(),
+            frame: Frame::new_zeroed(),
+        };
+
+        // metadata
+        packet.header.set_version(PROTOCOL_VERSION);
+        packet.header.set_bus_number(bus_number.0);
+        packet
+            .header
+            .set_client_identifier(u64::from_be_bytes([0u8; 8]));
+
+        // frame
+        packet.frame.set_flags(flags.bits());
+        packet.frame.set_raw_id(0);
+        packet.frame.set_dlc(data.len() as u8);
+        packet.frame.set_raw_data(u64::from_be_bytes(data));
+
+        packet
+    }
+
+    /// Parse a heartbeat packet as produced by [`Packet::new_heartbeat`].
+    ///
+    /// Returns `None` if `bytes` is not exactly one packet long or the
+    /// heartbeat flag isn't set.
+    pub fn parse_heartbeat(bytes: &[u8]) -> Option<Heartbeat> {
+        let packet = Packet::read_from(bytes)?;
+        let bus_number =
+            BusNumber::try_from(packet.header.bus_number()).ok()?;
+
+        Heartbeat::from_frame(&packet.frame, bus_number)
+    }
+
+    /// Parse a settings packet as produced by [`Packet::new_settings`].
+    ///
+    /// Returns `None` if `bytes` is not exactly one packet long or the
+    /// settings flag isn't set.
+    pub fn parse_settings(bytes: &[u8]) -> Option<Settings> {
+        let packet = Packet::read_from(bytes)?;
+
+        Some(Settings {
+            bus_number: BusNumber::try_from(packet.header.bus_number()).ok()?,
+            data_rate: packet.frame.settings_data_rate()?,
+        })
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        // is safe because we use size_of::<Packet>
+        unsafe {
+            ::core::slice::from_raw_parts(
+                self as *const _ as *const u8,
+                ::core::mem::size_of::<Packet>(),
+            )
+        }
+    }
+
+    /// Decode a header followed by any number of frames filling `bytes`.
+    ///
+    /// Returns [`CodecError::BufferTooSmall`] if there are more than `N`
+    /// frames and [`CodecError::Truncated`] if `bytes` ends partway through
+    /// a frame.
+    #[cfg(feature = "heapless")]
+    pub fn decode_full<const N: usize>(
+        bytes: &[u8],
+    ) -> Result<DecodedPacket<N>, CodecError> {
+        let header = Header::parse(bytes)?;
+        let mut frames = heapless::Vec::new();
+
+        let mut rest = &bytes[HEADER_LEN..];
+        while !rest.is_empty() {
+            let (Some(frame), len) = Codec::decode(rest)? else {
+                return Err(CodecError::Truncated);
+            };
+            frames.push(frame).map_err(|_| CodecError::BufferTooSmall)?;
+            rest = &rest[len..];
+        }
+
+        Ok(DecodedPacket { header, frames })
+    }
+}
+
+/// Header and frames decoded by [`Packet::decode_full`].
+#[cfg(feature = "heapless")]
+#[derive(Debug, PartialEq, Eq)]
+pub struct DecodedPacket<const N: usize> {
+    pub header: Header,
+    pub frames: heapless::Vec<Frame, N>,
+}
+
+/// Builder for a [`Packet`], defaulting to [`PROTOCOL_VERSION`], the
+/// default [`BusNumber`], client identifier 0 and a zeroed frame.
+///
+/// ```
+/// use tritiumcan::{datagram::PacketBuilder, BusNumber};
+///
+/// let packet = PacketBuilder::new()
+///     .bus_number(BusNumber::try_from(3).unwrap())
+///     .client_id(0x1234)
+///     .build();
+/// assert_eq!(packet.header.bus_number(), 3);
+/// ```
+#[derive(Debug)]
+pub struct PacketBuilder {
+    version: u64,
+    bus_number: BusNumber,
+    client_id: u64,
+    forward: bool,
+    frame: Frame,
+}
+
+impl PacketBuilder {
+    pub fn new() -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            bus_number: BusNumber::default(),
+            client_id: 0,
+            forward: false,
+            frame: Frame::new_zeroed(),
+        }
+    }
+
+    /// Protocol version identifier.
+    pub fn version(mut self, version: u64) -> Self {
+        self.version = version;
+        self
+    }
+
+    pub fn bus_number(mut self, bus_number: BusNumber) -> Self {
+        self.bus_number = bus_number;
+        self
+    }
+
+    pub fn client_id(mut self, client_id: u64) -> Self {
+        self.client_id = client_id;
+        self
+    }
+
+    /// Forwarding flag, see [`HeaderBitfield::forward`].
+    pub fn forward(mut self, forward: bool) -> Self {
+        self.forward = forward;
+        self
+    }
+
+    pub fn frame(mut self, frame: Frame) -> Self {
+        self.frame = frame;
+        self
+    }
+
+    pub fn build(self) -> Packet {
+        let mut header =
+            Header::with(self.version, self.bus_number.into(), self.client_id);
+        header.set_forward(self.forward);
+
+        Packet {
+            header,
+            frame: self.frame,
+        }
+    }
+}
+
+impl Default for PacketBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Decoded heartbeat advertised by a bridge.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub struct Heartbeat {
+    pub mac_addr: [u8; 6],
+    pub bus_number: BusNumber,
+    pub data_rate: u16,
+}
+
+impl Heartbeat {
+    /// Decode a heartbeat frame sent by a bridge on `bus_number`.
+    ///
+    /// Returns `None` if the heartbeat flag isn't set.
+    pub fn from_frame(frame: &Frame, bus_number: BusNumber) -> Option<Self> {
+        if !frame.is_heartbeat() {
+            return None;
+        }
+
+        let data = frame.raw_data().to_be_bytes();
+        let mut mac_addr = [0; 6];
+        mac_addr.copy_from_slice(&data[2..8]);
+
+        Some(Heartbeat {
+            mac_addr,
+            bus_number,
+            data_rate: u16::from_be_bytes([data[0], data[1]]),
+        })
+    }
+}
+
+/// Decoded bus configuration pushed to a bridge.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub struct Settings {
+    pub bus_number: BusNumber,
+    pub data_rate: u16,
+}
+
+/// Frame received on a stream, classified by its flags.
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub enum ReceivedItem {
+    /// CAN frame.
+    Data(Frame),
+    /// Bridge heartbeat.
+    Heartbeat(Heartbeat),
+    /// Bus configuration.
+    Settings(Settings),
+}
+
+impl ReceivedItem {
+    /// Classify `frame` received from a peer on `bus_number`.
+    pub fn classify(frame: Frame, bus_number: BusNumber) -> Self {
+        if let Some(heartbeat) = Heartbeat::from_frame(&frame, bus_number) {
+            return ReceivedItem::Heartbeat(heartbeat);
+        }
+
+        match frame.settings_data_rate() {
+            Some(data_rate) => ReceivedItem::Settings(Settings {
+                bus_number,
+                data_rate,
+            }),
+            None => ReceivedItem::Data(frame),
+        }
+    }
+}
+
+/// Filter setting datagram length.
+pub const FILTER_LEN: usize = 24;
+
+bitfield::bitfield! {
+    /// Datagram use for filt
+    #[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+    pub struct FilterBitfield(MSB0 [u8]);
+    impl Debug;
+    pub u32, fwd_identifier, set_fwd_identifier: 31, 0;
+    pub u32, fwd_range, set_fwd_range: 63, 32;
+    pub u8, bus_number, set_bus_bumber: 71, 64;
+    pub u64, version_number, set_version_number: 123, 72;
+    pub u64, client_identifier, set_client_identifier: 187, 132;
+}
+
+pub type Filter = FilterBitfield<[u8; FILTER_LEN]>;
+
+impl Filter {
+    pub fn new() -> Self {
+        FilterBitfield([0; FILTER_LEN])
+    }
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "socketcan")]
+mod socketcan_impls {
+    //! Conversion to and from SocketCAN frames.
+
+    use super::*;
+    use socketcan::{CanFrame, EmbeddedFrame};
+
+    impl From<CanFrame> for Frame {
+        fn from(frame: CanFrame) -> Self {
+            // classic SocketCAN frames never carry more than 8 bytes
+            Frame::from_frame(&frame).unwrap_or_default()
+        }
+    }
+
+    impl TryFrom<Frame> for CanFrame {
+        type Error = FrameError;
+
+        fn try_from(frame: Frame) -> Result<Self, FrameError> {
+            if frame.is_remote() {
+                CanFrame::new_remote(frame.id(), frame.dlc())
+                    .ok_or(FrameError::InvalidDlc)
+            } else {
+                CanFrame::new(frame.id(), frame.data())
+                    .ok_or(FrameError::DataTooLong)
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn socketcan_round_trip() {
+            let frames = [
+                CanFrame::new(StandardId::new(0x123).unwrap(), &[1, 2, 3]),
+                CanFrame::new(ExtendedId::new(0x18FF50E5).unwrap(), &[4; 8]),
+                CanFrame::new_remote(StandardId::new(0x7FF).unwrap(), 2),
+                CanFrame::new_remote(ExtendedId::MAX, 0),
+            ];
+
+            for expected in frames.map(Option::unwrap) {
+                let frame = Frame::from(expected);
+                assert_eq!(frame.id(), expected.id());
+                assert_eq!(frame.is_extended(), expected.is_extended());
+                assert_eq!(frame.is_remote(), expected.is_remote_frame());
+                assert_eq!(frame.dlc(), expected.dlc());
+
+                let actual = CanFrame::try_from(frame).unwrap();
+                assert_eq!(actual.id(), expected.id());
+                assert_eq!(
+                    actual.is_remote_frame(),
+                    expected.is_remote_frame()
+                );
+                assert_eq!(actual.dlc(), expected.dlc());
+                assert_eq!(actual.data(), expected.data());
+            }
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+mod serde_impls {
+    //! Serialize datagrams as named fields rather than raw bytes.
+
+    use super::*;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Serialize, Deserialize)]
+    struct HeaderRepr {
+        version: u64,
+        bus_number: u8,
+        /// Missing from payloads written before the flag was supported.
+        #[serde(default)]
+        forward: bool,
+        client_identifier: u64,
+    }
+
+    impl Serialize for Header {
+        fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+            HeaderRepr {
+                version: self.version(),
+                bus_number: self.bus_number(),
+                forward: self.forward(),
+                client_identifier: self.client_identifier(),
+            }
+            .serialize(s)
+        }
+    }
+
+    impl<'de> Deserialize<'de> for Header {
+        fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+            let repr = HeaderRepr::deserialize(d)?;
+
+            let mut header = Header::new();
+            header.set_version(repr.version);
+            header.set_bus_number(repr.bus_number);
+            header.set_forward(repr.forward);
+            header.set_client_identifier(repr.client_identifier);
+
+            Ok(header)
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct FrameRepr {
+        id: u32,
+        flags: u8,
+        dlc: u8,
+        data: [u8; 8],
+    }
+
+    impl Serialize for Frame {
+        fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+            FrameRepr {
+                id: self.raw_id(),
+                flags: self.flags(),
+                dlc: self.raw_dlc(),
+                data: self.raw_data().to_be_bytes(),
+            }
+            .serialize(s)
+        }
+    }
+
+    impl<'de> Deserialize<'de> for Frame {
+        fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+            let repr = FrameRepr::deserialize(d)?;
+
+            let mut frame = Frame::new_zeroed();
+            frame.set_raw_id(repr.id);
+            frame.set_flags(repr.flags);
+            frame.set_dlc(repr.dlc);
+            frame.set_raw_data(u64::from_be_bytes(repr.data));
+
+            Ok(frame)
+        }
+    }
+
+    #[derive(Serialize)]
+    #[serde(rename = "Packet")]
+    struct PacketRef<'a> {
+        header: &'a Header,
+        frame: &'a Frame,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(rename = "Packet")]
+    struct PacketRepr {
+        header: Header,
+        frame: Frame,
+    }
+
+    impl Serialize for Packet {
+        fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+            PacketRef {
+                header: &self.header,
+                frame: &self.frame,
+            }
+            .serialize(s)
+        }
+    }
+
+    impl<'de> Deserialize<'de> for Packet {
+        fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+            let repr = PacketRepr::deserialize(d)?;
+
+            Ok(Packet {
+                header: repr.header,
+                frame: repr.frame,
+            })
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn frame_json_round_trip() {
+            let id = ExtendedId::new(0x18FF50E5).unwrap();
+            let frame = Frame::new(id, &[1, 2, 3]).unwrap();
+
+            let json = serde_json::to_string(&frame).unwrap();
+            assert_eq!(
+                json,
+                r#"{"id":419385573,"flags":1,"dlc":3,"data":[1,2,3,0,0,0,0,0]}"#
+            );
+            assert_eq!(serde_json::from_str::<Frame>(&json).unwrap(), frame);
+        }
+
+        #[test]
+        fn packet_json_round_trip() {
+            let packet =
+                Packet::new_heartbeat(&[1; 6], &BusNumber::default(), &500);
+
+            let json = serde_json::to_string(&packet).unwrap();
+            assert_eq!(serde_json::from_str::<Packet>(&json).unwrap(), packet);
+
+            let packet = PacketBuilder::new().forward(true).build();
+            let json = serde_json::to_string(&packet).unwrap();
+            let parsed = serde_json::from_str::<Packet>(&json).unwrap();
+            assert!(parsed.header.forward());
+            assert_eq!(parsed, packet);
+        }
+
+        #[test]
+        fn header_json_without_forward() {
+            // payloads from before the flag don't forward
+            let json = r#"{"version":0,"bus_number":0,"client_identifier":0}"#;
+            let parsed = serde_json::from_str::<Header>(json).unwrap();
+            assert!(!parsed.forward());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use core::mem::size_of;
+
+    #[test]
+    fn header_type_length() {
+        assert_eq!(size_of::<Header>(), 16);
+        assert_eq!(size_of::<Header>(), HEADER_LEN);
+    }
+
+    #[test]
+    fn header_parse() {
+        let mut header = Header::new();
+        header.set_version(PROTOCOL_VERSION);
+        header.set_bus_number(13);
+        header.set_client_identifier(0x123456);
+
+        let parsed = Header::parse(header.as_bytes()).unwrap();
+        assert_eq!(parsed.bus_number(), 13);
+        assert_eq!(parsed.client_identifier(), 0x123456);
+    }
+
+    #[test]
+    fn header_forward() {
+        let mut header = Header::with(PROTOCOL_VERSION, 13, 0x123456);
+        assert!(!header.forward());
+
+        header.set_forward(true);
+        assert_eq!(header.as_bytes()[8], 0x01);
+        let parsed = Header::parse(header.as_bytes()).unwrap();
+        assert!(parsed.forward());
+        assert_eq!(parsed.bus_number(), 13);
+        assert_eq!(parsed.client_identifier(), 0x123456);
+
+        let packet = PacketBuilder::new().forward(true).build();
+        assert!(packet.header.forward());
+    }
+
+    #[test]
+    fn header_with() {
+        const HEADER: Header = Header::with(PROTOCOL_VERSION, 13, 0x123456);
+
+        let mut header = Header::new();
+        header.set_version(PROTOCOL_VERSION);
+        header.set_bus_number(13);
+        header.set_client_identifier(0x123456);
+        assert_eq!(HEADER.as_bytes(), header.as_bytes());
+
+        // out of range fields are truncated like the setters
+        let mut header = Header::new();
+        header.set_version(u64::MAX);
+        header.set_bus_number(u8::MAX);
+        header.set_client_identifier(u64::MAX);
+        assert_eq!(Header::with(u64::MAX, u8::MAX, u64::MAX), header);
+    }
+
+    #[test]
+    fn header_wire_bytes() {
+        let header = Header::with(PROTOCOL_VERSION, 13, 0x0001_0203_0405_0607);
+        assert_eq!(
+            header.as_bytes(),
+            [
+                0x00, 0x54, 0x72, 0x69, 0x74, 0x69, 0x75,
+                0x6D, // version, bus
+                0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06,
+                0x07, // client id
+            ]
+        );
+    }
+
+    #[test]
+    fn frame_wire_bytes() {
+        let id = ExtendedId::new(0x1234567).unwrap();
+        let frame = Frame::new(id, &[1, 2, 3]).unwrap();
+        assert_eq!(
+            frame.as_bytes(),
+            [
+                0x01, 0x23, 0x45, 0x67, // id
+                0x01, // flags
+                0x03, // dlc
+                0x01, 0x02, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, // data
+            ]
+        );
+
+        let bytes = [0, 0, 0x01, 0x23, 0, 2, 0xAB, 0xCD, 0, 0, 0, 0, 0, 0];
+        let frame = Frame::read_from(&bytes[..]).unwrap();
+        assert_eq!(frame.id(), Id::Standard(StandardId::new(0x123).unwrap()));
+        assert_eq!(frame.data(), [0xAB, 0xCD]);
+    }
+
+    #[test]
+    fn control_wire_bytes() {
+        let mac_addr = [0x10, 0x20, 0x30, 0x40, 0x50, 0x60];
+        let packet =
+            Packet::new_heartbeat(&mac_addr, &BusNumber::default(), &0x01F4);
+        assert_eq!(
+            packet.frame.as_bytes()[6..],
+            [0x01, 0xF4, 0x10, 0x20, 0x30, 0x40, 0x50, 0x60]
+        );
+
+        let packet = Packet::new_settings(&BusNumber::default(), &0x0102);
+        assert_eq!(packet.frame.as_bytes()[6..8], [0x01, 0x02]);
+        assert_eq!(packet.frame.settings_data_rate(), Some(0x0102));
+    }
+
+    #[test]
+    fn frame_ref() {
+        let id = ExtendedId::new(0x1234567).unwrap();
+        let frame = Frame::new(id, &[1, 2, 3]).unwrap();
+        let mut buf = [0xFF; 16];
+        buf[..FRAME_LEN].copy_from_slice(frame.as_bytes());
+
+        let view = FrameRef::from_bytes(&buf).unwrap();
+        assert_eq!(view.id(), frame.id());
+        assert_eq!(view.dlc(), frame.dlc());
+        assert_eq!(view.data(), frame.data());
+        assert_eq!(view.flags(), frame.flags());
+        assert_eq!(view.frame_flags(), FrameFlags::Extended);
+        assert_eq!(Frame::from(view), frame);
+
+        let view: FrameRef =
+            Ref::<_, Frame>::new_from_prefix(&buf[..]).unwrap().0.into();
+        assert_eq!(view.frame(), &frame);
+
+        assert!(FrameRef::from_bytes(&buf[..FRAME_LEN - 1]).is_none());
+    }
+
+    #[test]
+    fn packet_builder() {
+        let mut packet = Packet {
+            header: Header::new(),
+            frame: Frame::new_zeroed(),
+        };
+        packet.header.set_version(PROTOCOL_VERSION);
+        packet.header.set_bus_number(BusNumber::default().into());
+        packet.header.set_client_identifier(0);
+        assert_eq!(PacketBuilder::new().build().as_bytes(), packet.as_bytes());
+
+        let frame =
+            || Frame::new(StandardId::new(0x123).unwrap(), &[1]).unwrap();
+        let packet = PacketBuilder::new()
+            .version(1)
+            .bus_number(BusNumber::try_from(3).unwrap())
+            .client_id(0x1234)
+            .frame(frame())
+            .build();
+        assert_eq!(packet.header, Header::with(1, 3, 0x1234));
+        assert_eq!(packet.frame, frame());
+    }
+
+    #[test]
+    fn frame_from_parts() {
+        let id = ExtendedId::new(0x1234567).unwrap();
+        let frame = Frame::from_frame(&Frame::new(id, &[1, 2]).unwrap());
+        assert_eq!(
+            Frame::from_parts(0x1234567, Flags::Extended.bits(), &[1, 2]),
+            frame
+        );
+
+        let remote = Frame::from_parts(0x123, Flags::Remote.bits(), &[0; 4]);
+        assert_eq!(
+            remote,
+            Frame::new_remote(StandardId::new(0x123).unwrap(), 4)
+        );
+
+        assert_eq!(
+            Frame::from_parts(0, 0, &[0; 9]),
+            Err(FrameError::DataTooLong)
+        );
+    }
+
+    #[test]
+    fn frame_from_raw_id() {
+        let frame = Frame::from_raw_id(0x7FF, &[1]).unwrap();
+        assert!(!frame.is_extended());
+        assert_eq!(frame.id(), Id::Standard(StandardId::MAX));
+
+        let frame = Frame::from_raw_id(0x800, &[1]).unwrap();
+        assert!(frame.is_extended());
+        assert_eq!(frame.id(), Id::Extended(ExtendedId::new(0x800).unwrap()));
+
+        assert_eq!(
+            Frame::from_raw_id(0x2000_0000, &[]),
+            Err(FrameError::InvalidId)
+        );
+        assert_eq!(
+            Frame::from_raw_id(0x123, &[0; 9]),
+            Err(FrameError::DataTooLong)
+        );
+    }
+
+    #[test]
+    fn frame_typed_flags() {
+        let id = ExtendedId::new(0x1234567).unwrap();
+        let mut frame = Frame::new(id, &[1]).unwrap();
+        assert_eq!(frame.frame_flags(), FrameFlags::Extended);
+
+        frame.set_frame_flags(FrameFlags::Extended | FrameFlags::Remote);
+        assert_eq!(frame.flags(), 0x03);
+        assert!(frame.is_remote() && frame.is_extended());
+
+        // bits without a name survive a round trip
+        frame.set_flags(0x30);
+        assert_eq!(frame.frame_flags().bits(), 0x30);
+        assert!(!frame.is_heartbeat() && !frame.is_settings());
+    }
+
+    #[test]
+    fn frame_mutate_data() {
+        let id = StandardId::new(0x123).unwrap();
+        let mut frame = Frame::new(id, &[1, 2, 3]).unwrap();
+
+        frame.data_mut()[2] = 0x42;
+        assert_eq!(frame.data(), [1, 2, 0x42]);
+        assert_eq!(frame.dlc(), 3);
+
+        frame.set_data(&[9; 5]).unwrap();
+        assert_eq!(frame.data(), [9; 5]);
+        assert_eq!(frame.dlc(), 5);
+        frame.set_data(&[7]).unwrap();
+        assert_eq!(frame, Frame::new(id, &[7]).unwrap());
+        assert_eq!(frame.set_data(&[0; 9]), Err(FrameError::DataTooLong));
+        assert_eq!(frame.dlc(), 1);
+
+        let mut remote = Frame::new_remote(id, 4).unwrap();
+        assert!(remote.data_mut().is_empty());
+        assert_eq!(remote.set_data(&[1]), Err(FrameError::RemoteFrame));
+        assert_eq!(remote.dlc(), 4);
+    }
+
+    #[test]
+    fn parse_arbitrary_bytes() {
+        for bytes in [&[][..], &[0xFF], &[0xFF; 29], &[0xFF; 64]] {
+            assert!(Header::parse(bytes).is_err());
+            assert!(Packet::parse_heartbeat(bytes).is_none());
+            assert!(Packet::parse_settings(bytes).is_none());
+        }
+
+        let fd = FdFrame::read_from(&[0xFF; FD_FRAME_LEN][..]).unwrap();
+        assert_eq!(fd.data().len(), FD_DATA_LEN);
+        assert!(fd.to_frame::<Frame>().is_none());
+    }
+
+    #[test]
+    fn header_parse_too_short() {
+        assert_eq!(Header::parse(&[0; 15]).unwrap_err(), HeaderError::TooShort);
+    }
+
+    #[test]
+    fn header_parse_unsupported_version() {
+        let mut header = Header::new();
+        header.set_version(0x1234);
+
+        assert_eq!(
+            Header::parse(header.as_bytes()).unwrap_err(),
+            HeaderError::UnsupportedVersion(0x1234)
+        );
+    }
+
+    #[test]
+    fn header_parse_any_bus_number() {
+        for bus_number in 0..=BusNumber::MAX {
+            let header = Header::with(PROTOCOL_VERSION, bus_number, 0);
+            let parsed = Header::parse(header.as_bytes()).unwrap();
+            assert_eq!(parsed.bus_number(), bus_number);
+        }
+    }
+
+    fn hash(value: &impl core::hash::Hash) -> u64 {
+        use core::hash::Hasher;
+
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        value.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn frame_eq_hash() {
+        let id = StandardId::new(0x123).unwrap();
+        let a = Frame::new(id, &[1, 2, 3]).unwrap();
+        let b: TestFrame = embedded_can::Frame::new(id, &[1, 2, 3]).unwrap();
+        let b = Frame::from_frame(&b).unwrap();
+
+        assert_eq!(a, b);
+        assert_eq!(hash(&a), hash(&b));
+        assert_ne!(a, Frame::new(id, &[1, 2, 4]).unwrap());
+    }
+
+    #[test]
+    fn packet_eq_hash() {
+        let a = Packet::new_heartbeat(&[1; 6], &BusNumber::default(), &500);
+        let b = Packet::new_heartbeat(&[1; 6], &BusNumber::default(), &500);
+
+        assert_eq!(a, b);
+        assert_eq!(a.header, b.header);
+        assert_eq!(hash(&a), hash(&b));
+    }
+
+    #[test]
+    fn frame_type_length() {
+        assert_eq!(size_of::<Frame>(), 14);
+        assert_eq!(size_of::<Frame>(), FRAME_LEN);
+    }
+
+    #[test]
+    fn packet_type_length() {
+        assert_eq!(size_of::<Packet>(), 30);
+        assert_eq!(size_of::<Packet>(), PACKET_LEN);
+    }
+
+    #[cfg(feature = "heapless")]
+    #[test]
+    fn packet_decode_full() {
+        let frame =
+            |id| Frame::new(StandardId::new(id).unwrap(), &[id as u8]).unwrap();
+        let header = Header::with(PROTOCOL_VERSION, 3, 0x1234);
+
+        let mut bytes = header.as_bytes().to_vec();
+        bytes.extend_from_slice(frame(1).as_bytes());
+        bytes.extend_from_slice(frame(2).as_bytes());
+
+        let packet = Packet::decode_full::<4>(&bytes).unwrap();
+        assert_eq!(packet.header, header);
+        assert_eq!(packet.frames, [frame(1), frame(2)]);
+
+        // a header alone has no frames
+        let packet = Packet::decode_full::<4>(header.as_bytes()).unwrap();
+        assert!(packet.frames.is_empty());
+
+        assert_eq!(
+            Packet::decode_full::<4>(&bytes[..bytes.len() - 1]),
+            Err(CodecError::Truncated)
+        );
+        assert_eq!(
+            Packet::decode_full::<4>(&bytes[..4]),
+            Err(CodecError::Header(HeaderError::TooShort))
+        );
+    }
+
+    #[cfg(feature = "heapless")]
+    #[test]
+    fn packet_decode_full_overflow() {
+        let frame = Frame::new(StandardId::ZERO, &[]).unwrap();
+        let mut bytes =
+            Header::with(PROTOCOL_VERSION, 3, 0).as_bytes().to_vec();
+        for _ in 0..3 {
+            bytes.extend_from_slice(frame.as_bytes());
+        }
+
+        assert_eq!(Packet::decode_full::<3>(&bytes).unwrap().frames.len(), 3);
+        assert_eq!(
+            Packet::decode_full::<2>(&bytes),
+            Err(CodecError::BufferTooSmall)
+        );
+    }
+
+    #[test]
+    fn heartbeat_len() {
+        let heartbeat =
+            Packet::new_heartbeat(&[1; 6], &BusNumber::default(), &500);
+        assert_eq!(Packet::heartbeat_len(), heartbeat.as_bytes().len());
+
+        const BUF: [u8; Packet::heartbeat_len()] = [0; 30];
+        assert!(Packet::parse_heartbeat(&BUF).is_none());
+    }
+
+    /// Minimal [`embedded_can::Frame`] implementation for testing conversions.
+    #[derive(Debug, PartialEq)]
+    struct TestFrame {
+        id: Id,
+        remote: bool,
+        dlc: usize,
+        data: [u8; 64],
+    }
+
+    impl embedded_can::Frame for TestFrame {
+        fn new(id: impl Into<Id>, data: &[u8]) -> Option<Self> {
+            if data.len() > 64 {
+                return None;
+            }
+
+            let mut buf = [0; 64];
+            buf[..data.len()].copy_from_slice(data);
+
+            Some(TestFrame {
+                id: id.into(),
+                remote: false,
+                dlc: data.len(),
+                data: buf,
+            })
+        }
+
+        fn new_remote(id: impl Into<Id>, dlc: usize) -> Option<Self> {
+            if dlc > 8 {
+                return None;
+            }
+
+            Some(TestFrame {
+                id: id.into(),
+                remote: true,
+                dlc,
+                data: [0; 64],
+            })
+        }
+
+        fn is_extended(&self) -> bool {
+            matches!(self.id, Id::Extended(_))
+        }
+
+        fn is_remote_frame(&self) -> bool {
+            self.remote
+        }
+
+        fn id(&self) -> Id {
+            self.id
+        }
+
+        fn dlc(&self) -> usize {
+            self.dlc
+        }
+
+        fn data(&self) -> &[u8] {
+            if self.remote {
+                &[]
+            } else {
+                &self.data[..self.dlc]
+            }
+        }
+    }
+
+    fn round_trip(frame: &TestFrame) -> TestFrame {
+        Frame::from_frame(frame).unwrap().to_frame().unwrap()
+    }
+
+    #[test]
+    fn frame_round_trip() {
+        let id = StandardId::new(0x123).unwrap();
+
+        for len in 0..=8 {
+            let data = [1, 2, 3, 4, 5, 6, 7, 8];
+            let frame: TestFrame =
+                embedded_can::Frame::new(id, &data[..len]).unwrap();
+            assert_eq!(round_trip(&frame), frame);
+        }
+    }
+
+    #[test]
+    fn frame_data_byte_order() {
+        let frame: TestFrame =
+            embedded_can::Frame::new(StandardId::ZERO, &[0xAA, 0xBB]).unwrap();
+        let frame = Frame::from_frame(&frame).unwrap();
+
+        assert_eq!(frame.0[6..], [0xAA, 0xBB, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(embedded_can::Frame::data(&frame), &[0xAA, 0xBB]);
+    }
+
+    #[test]
+    fn frame_extended_id() {
+        let id = ExtendedId::new(0x1FFFFFFF).unwrap();
+        let frame: TestFrame = embedded_can::Frame::new(id, &[]).unwrap();
+        let frame = Frame::from_frame(&frame).unwrap();
+
+        assert!(frame.is_extended());
+        assert_eq!(frame.id(), Id::Extended(id));
+        assert_eq!(frame.0[..5], [0x1F, 0xFF, 0xFF, 0xFF, 0x01]);
+    }
+
+    #[test]
+    fn frame_standard_id() {
+        let id = StandardId::new(0x7FF).unwrap();
+        let frame: TestFrame = embedded_can::Frame::new(id, &[]).unwrap();
+        let frame = Frame::from_frame(&frame).unwrap();
+
+        assert!(!frame.is_extended());
+        assert_eq!(frame.id(), Id::Standard(id));
+        assert_eq!(frame.0[..5], [0x00, 0x00, 0x07, 0xFF, 0x00]);
+    }
+
+    #[test]
+    fn frame_id_masking() {
+        let mut frame = Frame::new_zeroed();
+        frame.set_raw_id(0xFFFFFFFF);
+        assert_eq!(frame.id(), Id::Standard(StandardId::MAX));
+
+        frame.set_flags(Flags::Extended.bits());
+        assert_eq!(frame.id(), Id::Extended(ExtendedId::MAX));
+    }
+
+    #[test]
+    fn frame_remote_round_trip() {
+        let id = StandardId::new(0x123).unwrap();
+        let frame: TestFrame = embedded_can::Frame::new_remote(id, 8).unwrap();
+        let encoded = Frame::from_frame(&frame).unwrap();
+
+        assert!(encoded.is_remote());
+        assert_eq!(encoded.dlc(), 8);
+        assert_eq!(encoded.0[6..], [0; 8]);
+        assert_eq!(round_trip(&frame), frame);
+    }
+
+    #[test]
+    fn heartbeat_round_trip() {
+        let mac_addr = [0x02, 0x00, 0x00, 0x12, 0x34, 0x56];
+        let bus_number = BusNumber::try_from(3).unwrap();
+        let packet = Packet::new_heartbeat(&mac_addr, &bus_number, &500);
+
+        assert_eq!(
+            Packet::parse_heartbeat(packet.as_bytes()),
+            Some(Heartbeat {
+                mac_addr,
+                bus_number,
+                data_rate: 500,
+            })
+        );
+    }
+
+    #[test]
+    fn heartbeat_parse_invalid() {
+        let packet =
+            Packet::new_heartbeat(&[0; 6], &BusNumber::default(), &500);
+        let bytes = packet.as_bytes();
+        assert_eq!(Packet::parse_heartbeat(&bytes[..29]), None);
+
+        let mut packet = Packet::new_zeroed();
+        packet.frame.set_dlc(8);
+        assert_eq!(Packet::parse_heartbeat(packet.as_bytes()), None);
+    }
+
+    #[test]
+    fn settings_round_trip() {
+        let bus_number = BusNumber::try_from(3).unwrap();
+        let packet = Packet::new_settings(&bus_number, &250);
+
+        assert!(packet.frame.is_settings());
+        assert!(!packet.frame.is_heartbeat());
+        assert_eq!(packet.frame.settings_data_rate(), Some(250));
+        assert_eq!(
+            Packet::parse_settings(packet.as_bytes()),
+            Some(Settings {
+                bus_number,
+                data_rate: 250,
+            })
+        );
+        assert_eq!(Packet::parse_heartbeat(packet.as_bytes()), None);
+    }
+
+    #[test]
+    fn settings_parse_invalid() {
+        let packet =
+            Packet::new_heartbeat(&[0; 6], &BusNumber::default(), &500);
+        assert_eq!(Packet::parse_settings(packet.as_bytes()), None);
+
+        let frame = Frame::new(StandardId::ZERO, &[0, 250]).unwrap();
+        assert!(!frame.is_settings());
+        assert_eq!(frame.settings_data_rate(), None);
+    }
+
+    #[test]
+    fn classify_received() {
+        let bus_number = BusNumber::default();
+
+        let packet = Packet::new_heartbeat(&[1; 6], &bus_number, &500);
+        assert_eq!(
+            ReceivedItem::classify(packet.frame, bus_number),
+            ReceivedItem::Heartbeat(Heartbeat {
+                mac_addr: [1; 6],
+                bus_number,
+                data_rate: 500,
+            })
+        );
+
+        let packet = Packet::new_settings(&bus_number, &250);
+        assert_eq!(
+            ReceivedItem::classify(packet.frame, bus_number),
+            ReceivedItem::Settings(Settings {
+                bus_number,
+                data_rate: 250,
+            })
+        );
+
+        let frame = Frame::new(StandardId::ZERO, &[1; 8]).unwrap();
+        assert!(matches!(
+            ReceivedItem::classify(frame, bus_number),
+            ReceivedItem::Data(_)
+        ));
+    }
+
+    fn frames(count: usize, extra: usize) -> ([u8; FRAME_LEN * 4], usize) {
+        let mut buf = [0; FRAME_LEN * 4];
+        for n in 0..count {
+            let mut frame = Frame::new_zeroed();
+            frame.set_raw_id(n as u32);
+            buf[n * FRAME_LEN..][..FRAME_LEN].copy_from_slice(&frame.0);
+        }
+        (buf, count * FRAME_LEN + extra)
+    }
+
+    #[test]
+    fn frame_iter_empty() {
+        let mut iter = FrameIter::new(&[]);
+        assert!(iter.next().is_none());
+        assert!(iter.remainder().is_empty());
+    }
+
+    #[test]
+    fn frame_iter_whole_frames() {
+        for count in [1, 3] {
+            let (buf, len) = frames(count, 0);
+            let mut iter = FrameIter::new(&buf[..len]);
+
+            for n in 0..count {
+                assert_eq!(iter.next().unwrap().raw_id(), n as u32);
+            }
+            assert!(iter.next().is_none());
+            assert!(iter.remainder().is_empty());
+        }
+    }
+
+    #[test]
+    fn frame_iter_partial_frame() {
+        let (buf, len) = frames(2, FRAME_LEN / 2);
+        let mut iter = FrameIter::new(&buf[..len]);
+
+        assert_eq!(iter.next().unwrap().raw_id(), 0);
+        assert_eq!(iter.next().unwrap().raw_id(), 1);
+        assert!(iter.next().is_none());
+        assert_eq!(iter.remainder(), &buf[FRAME_LEN * 2..len]);
+    }
+
+    #[test]
+    fn fd_frame_type_length() {
+        assert_eq!(size_of::<FdFrame>(), FD_FRAME_LEN)
+    }
+
+    #[test]
+    fn fd_frame_round_trip() {
+        let id = ExtendedId::new(0x18FF50E5).unwrap();
+        let data: [u8; 64] = core::array::from_fn(|n| n as u8);
+
+        for (dlc, len) in (9..).zip([12, 16, 20, 24, 32, 48, 64]) {
+            let frame: TestFrame =
+                embedded_can::Frame::new(id, &data[..len]).unwrap();
+            let mut fd = FdFrame::from_frame(&frame).unwrap();
+            fd.set_bit_rate_switch(true);
+
+            assert_eq!(fd.dlc(), dlc);
+            assert_eq!(fd.data(), &data[..len]);
+            assert_eq!(
+                fd.flags(),
+                (Flags::Extended | Flags::Fd | Flags::BitRateSwitch).bits()
+            );
+            assert_eq!(fd.to_frame::<TestFrame>().unwrap(), frame);
+        }
+    }
+
+    #[cfg(feature = "can-xl")]
+    #[test]
+    fn xl_frame_round_trip() {
+        for len in [64, 512, 2048] {
+            let data: Vec<u8> = (0..len).map(|n| n as u8).collect();
+            let mut xl = XlFrame::new();
+            xl.set_id(StandardId::new(0x123).unwrap());
+            xl.set_sdt(0x01);
+            xl.set_vcid(0x42);
+            xl.set_af(0xDEADBEEF);
+            xl.set_data(&data).unwrap();
+
+            let bytes = xl.as_bytes();
+            assert_eq!(bytes.len(), 13 + len);
+            assert_eq!(bytes[..2], [0, 0]);
+            assert_eq!(bytes[2..6], [0x01, 0x23, Flags::Xl.bits(), 0x01]);
+            assert_eq!(bytes[6..11], [0x42, 0xDE, 0xAD, 0xBE, 0xEF]);
+            assert_eq!(bytes[11..13], (len as u16).to_be_bytes());
+
+            let mut wire = bytes.to_vec();
+            wire.extend_from_slice(&[0xFF; 4]);
+            let decoded = XlFrame::from_bytes(&wire).unwrap();
+            assert_eq!(decoded, xl);
+            assert_eq!(decoded.wire_len(), 13 + len);
+            assert_eq!(decoded.data(), &data[..]);
+            assert_eq!(decoded.af(), 0xDEADBEEF);
+
+            assert!(XlFrame::from_bytes(&bytes[..bytes.len() - 1]).is_none());
+        }
+    }
+
+    #[cfg(feature = "can-xl")]
+    #[test]
+    fn xl_frame_conversion() {
+        let data: [u8; 64] = core::array::from_fn(|n| n as u8);
+        let id = StandardId::new(0x7FF).unwrap();
+        let frame: TestFrame = embedded_can::Frame::new(id, &data).unwrap();
+
+        let xl = XlFrame::from_frame(&frame).unwrap();
+        assert_eq!(xl.id(), id);
+        assert_eq!(xl.data(), &data);
+        assert_eq!(xl.to_frame::<TestFrame>().unwrap(), frame);
+
+        let extended: TestFrame =
+            embedded_can::Frame::new(ExtendedId::ZERO, &[1]).unwrap();
+        assert_eq!(
+            XlFrame::from_frame(&extended).unwrap_err(),
+            FrameError::ExtendedId
+        );
+        let empty: TestFrame = embedded_can::Frame::new(id, &[]).unwrap();
+        assert_eq!(
+            XlFrame::from_frame(&empty).unwrap_err(),
+            FrameError::InvalidDlc
+        );
+        assert_eq!(
+            XlFrame::new().set_data(&[0; XL_DATA_LEN + 1]),
+            Err(FrameError::DataTooLong)
+        );
+
+        // a classic frame isn't read as XL
+        let classic = Frame::new(id, &[1]).unwrap();
+        let mut bytes = classic.as_bytes().to_vec();
+        bytes.resize(32, 1);
+        assert!(XlFrame::from_bytes(&bytes).is_none());
+    }
+
+    #[test]
+    fn fd_frame_invalid_length() {
+        let frame: TestFrame =
+            embedded_can::Frame::new(StandardId::ZERO, &[0; 13]).unwrap();
+        assert_eq!(
+            FdFrame::from_frame(&frame).unwrap_err(),
+            FrameError::InvalidDlc
+        );
+    }
+
+    #[test]
+    fn frame_new() {
+        let id = StandardId::new(0x123).unwrap();
+        let frame = Frame::new(id, &[1, 2, 3, 4, 5, 6, 7, 8]).unwrap();
+
+        assert_eq!(frame.id(), Id::Standard(id));
+        assert_eq!(frame.dlc(), 8);
+        assert_eq!(frame.data(), &[1, 2, 3, 4, 5, 6, 7, 8]);
+        assert!(!frame.is_remote());
+    }
+
+    #[test]
+    fn frame_display() {
+        let id = ExtendedId::new(0x18FF50E5).unwrap();
+        let frame = Frame::new(id, &[1, 2, 3, 4, 5, 6, 7, 8]).unwrap();
+        assert_eq!(
+            format!("{}", frame),
+            "ID=0x18FF50E5 [8] 01 02 03 04 05 06 07 08"
+        );
+
+        let id = StandardId::new(0x12).unwrap();
+        let frame = Frame::new(id, &[0xAB]).unwrap();
+        assert_eq!(format!("{}", frame), "ID=0x012 [1] AB");
+
+        let id = StandardId::new(0x123).unwrap();
+        let frame = Frame::new_remote(id, 8).unwrap();
+        assert_eq!(format!("{}", frame), "ID=0x123 RTR [8]");
+    }
+
+    #[test]
+    fn frame_new_data_too_long() {
+        assert_eq!(
+            Frame::new(StandardId::ZERO, &[0; 9]).unwrap_err(),
+            FrameError::DataTooLong
+        );
+    }
+
+    #[test]
+    fn frame_new_remote() {
+        let id = ExtendedId::new(0x1234).unwrap();
+        let frame = Frame::new_remote(id, 4).unwrap();
+
+        assert_eq!(frame.id(), Id::Extended(id));
+        assert_eq!(frame.dlc(), 4);
+        assert!(frame.is_remote());
+        assert_eq!(
+            Frame::new_remote(id, 9).unwrap_err(),
+            FrameError::InvalidDlc
+        );
+    }
+
+    #[test]
+    fn frame_accessors() {
+        let id = ExtendedId::new(0x18FF50E5).unwrap();
+        let frame: TestFrame =
+            embedded_can::Frame::new(id, &[0xDE, 0xAD, 0xBE, 0xEF]).unwrap();
+        let frame = Frame::from_frame(&frame).unwrap();
+
+        assert_eq!(frame.id(), Id::Extended(id));
+        assert_eq!(frame.dlc(), 4);
+        assert_eq!(frame.data(), &[0xDE, 0xAD, 0xBE, 0xEF]);
+        assert_eq!(frame.flags(), Flags::Extended.bits());
+    }
+
+    #[test]
+    fn frame_to_frame_invalid_dlc() {
+        let mut frame = Frame::new_zeroed();
+        frame.set_dlc(9);
+
+        assert_eq!(frame.to_frame::<TestFrame>(), None);
     }
 }
@@ -0,0 +1,118 @@
+//! CAN ID filtering.
+
+use embedded_can::Id;
+
+/// Maximum number of `(id, mask)` pairs in an [`IdFilter`].
+pub const ID_FILTER_LEN: usize = 8;
+
+/// Allowlist of CAN IDs as `(id, mask)` pairs.
+///
+/// A frame is accepted if its raw ID matches any pair, that is
+/// `frame_id & mask == id & mask`. An empty filter accepts every frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub struct IdFilter {
+    entries: [(u32, u32); ID_FILTER_LEN],
+    len: usize,
+}
+
+/// Error adding to an [`IdFilter`] that already holds [`ID_FILTER_LEN`]
+/// pairs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub struct FilterFull;
+
+impl IdFilter {
+    /// Create an empty filter that accepts every frame.
+    pub const fn new() -> Self {
+        Self {
+            entries: [(0, 0); ID_FILTER_LEN],
+            len: 0,
+        }
+    }
+
+    /// Create a filter from a list of `(id, mask)` pairs.
+    pub fn from_slice(pairs: &[(u32, u32)]) -> Result<Self, FilterFull> {
+        let mut filter = Self::new();
+        for &(id, mask) in pairs {
+            filter.push(id, mask)?;
+        }
+        Ok(filter)
+    }
+
+    /// Add an `(id, mask)` pair.
+    pub fn push(&mut self, id: u32, mask: u32) -> Result<(), FilterFull> {
+        let entry = self.entries.get_mut(self.len).ok_or(FilterFull)?;
+        *entry = (id, mask);
+        self.len += 1;
+        Ok(())
+    }
+
+    /// The `(id, mask)` pairs in the filter.
+    pub fn pairs(&self) -> &[(u32, u32)] {
+        &self.entries[..self.len]
+    }
+
+    /// Whether the filter has no pairs and so accepts every frame.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Whether a frame with `id` passes the filter.
+    pub fn matches(&self, id: Id) -> bool {
+        let raw = match id {
+            Id::Standard(id) => id.as_raw().into(),
+            Id::Extended(id) => id.as_raw(),
+        };
+
+        self.is_empty()
+            || self
+                .pairs()
+                .iter()
+                .any(|&(id, mask)| raw & mask == id & mask)
+    }
+}
+
+impl Default for IdFilter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_can::{ExtendedId, StandardId};
+
+    fn standard(id: u16) -> Id {
+        StandardId::new(id).unwrap().into()
+    }
+
+    #[test]
+    fn empty_accepts_all() {
+        let filter = IdFilter::new();
+        assert!(filter.matches(standard(0x123)));
+        assert!(filter.matches(ExtendedId::MAX.into()));
+    }
+
+    #[test]
+    fn mask_match() {
+        let filter = IdFilter::from_slice(&[(0x120, 0x7F0)]).unwrap();
+        assert!(filter.matches(standard(0x120)));
+        assert!(filter.matches(standard(0x12F)));
+        assert!(!filter.matches(standard(0x130)));
+
+        let filter =
+            IdFilter::from_slice(&[(0x100, 0x7FF), (0x200, 0x7FF)]).unwrap();
+        assert!(filter.matches(standard(0x200)));
+        assert!(!filter.matches(standard(0x300)));
+    }
+
+    #[test]
+    fn full() {
+        let mut filter =
+            IdFilter::from_slice(&[(0, 0); ID_FILTER_LEN]).unwrap();
+        assert_eq!(filter.push(0, 0), Err(FilterFull));
+        assert_eq!(filter.pairs().len(), ID_FILTER_LEN);
+    }
+}
@@ -1,6 +1,10 @@
 #![cfg_attr(not(test), no_std)]
 
+pub mod capture;
+pub mod codec;
 pub mod datagram;
+pub mod filter;
+pub mod queue;
 
 use core::net::{IpAddr, Ipv4Addr};
 use core::time::Duration;
@@ -19,7 +23,7 @@ pub const PROTOCOL_VERSION: u64 = 0x5472697469756;
 pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
 
 /// Flags bitfield.
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
 pub struct Flags(u8);
 
@@ -27,12 +31,56 @@ bitflags::bitflags! {
     impl Flags: u8 {
         const Heartbeat = 1 << 7;
         const Settings = 1 << 6;
+        /// CAN FD bit rate switch (BRS).
+        const BitRateSwitch = 1 << 3;
+        /// CAN XL frame format (XLF).
+        const Xl = 1 << 4;
+        /// CAN FD frame format (FDF).
+        const Fd = 1 << 2;
         const Remote = 1 << 1;
         const Extended = 1 << 0;
     }
 }
 
+/// Typed flags byte of a frame, see [`datagram::Frame::frame_flags`].
+pub type FrameFlags = Flags;
+
 impl Flags {
+    /// Whether the frame is a bridge heartbeat.
+    pub fn is_heartbeat(&self) -> bool {
+        self.contains(Flags::Heartbeat)
+    }
+
+    /// Whether the frame is a settings frame.
+    pub fn is_settings(&self) -> bool {
+        self.contains(Flags::Settings)
+    }
+
+    /// Whether the CAN FD data phase uses the faster bit rate.
+    pub fn is_bit_rate_switch(&self) -> bool {
+        self.contains(Flags::BitRateSwitch)
+    }
+
+    /// Whether the frame uses the CAN XL format.
+    pub fn is_xl(&self) -> bool {
+        self.contains(Flags::Xl)
+    }
+
+    /// Whether the frame uses the CAN FD format.
+    pub fn is_fd(&self) -> bool {
+        self.contains(Flags::Fd)
+    }
+
+    /// Whether the frame is a remote transmission request.
+    pub fn is_remote(&self) -> bool {
+        self.contains(Flags::Remote)
+    }
+
+    /// Whether the frame uses a 29-bit extended identifier.
+    pub fn is_extended(&self) -> bool {
+        self.contains(Flags::Extended)
+    }
+
     /// Set flags from [`Frame`]
     pub fn from_frame(frame: &impl Frame) -> Self {
         let mut flags = Flags::empty();
@@ -50,16 +98,38 @@ impl Flags {
 }
 
 /// Bus number
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
 pub struct BusNumber(u8);
 
+impl BusNumber {
+    /// Highest valid bus number, the header field is 4 bits wide.
+    pub const MAX: u8 = 0xF;
+
+    /// Create a [`BusNumber`] returning `None` if `n` is higher than
+    /// [`BusNumber::MAX`].
+    pub fn new(n: u16) -> Option<BusNumber> {
+        u8::try_from(n)
+            .ok()
+            .and_then(|n| BusNumber::try_from(n).ok())
+    }
+
+    /// Create a [`BusNumber`] without checking the range, for known-good
+    /// constants.
+    ///
+    /// Only the low 4 bits of `n` are kept, so an out-of-range value can't
+    /// reach the header.
+    pub const fn new_unchecked(n: u8) -> BusNumber {
+        BusNumber(n & Self::MAX)
+    }
+}
+
 impl TryFrom<u8> for BusNumber {
     type Error = ();
 
     /// Try create a [`BusNumber`] from a [`u8`] returning an error if the input is higher than `0xF`.
     fn try_from(value: u8) -> Result<BusNumber, Self::Error> {
-        if value > 0xF {
+        if value > Self::MAX {
             Err(())
         } else {
             Ok(BusNumber(value))
@@ -92,4 +162,44 @@ mod tests {
         assert!(BusNumber::try_from(16).is_err());
         assert!(BusNumber::try_from(255).is_err());
     }
+
+    #[test]
+    fn frame_flags() {
+        type IsSet = fn(&FrameFlags) -> bool;
+        let named: [(FrameFlags, IsSet, u8); 7] = [
+            (Flags::Heartbeat, FrameFlags::is_heartbeat, 0x80),
+            (Flags::Settings, FrameFlags::is_settings, 0x40),
+            (Flags::Xl, FrameFlags::is_xl, 0x10),
+            (Flags::BitRateSwitch, FrameFlags::is_bit_rate_switch, 0x08),
+            (Flags::Fd, FrameFlags::is_fd, 0x04),
+            (Flags::Remote, FrameFlags::is_remote, 0x02),
+            (Flags::Extended, FrameFlags::is_extended, 0x01),
+        ];
+
+        for (flag, is_set, byte) in named {
+            let mut flags = FrameFlags::empty();
+            flags.set(flag, true);
+            assert_eq!(flags.bits(), byte);
+            assert!(is_set(&flags));
+            assert_eq!(FrameFlags::from_bits(byte), Some(flags));
+
+            let mut flags = FrameFlags::all();
+            flags.set(flag, false);
+            assert_eq!(flags.bits(), 0xDF & !byte);
+            assert!(!is_set(&flags));
+        }
+
+        assert_eq!(FrameFlags::from_bits(0x20), None);
+    }
+
+    #[test]
+    fn bus_number_new() {
+        assert_eq!(BusNumber::new(3), BusNumber::try_from(3).ok());
+        assert_eq!(BusNumber::new(15).map(u8::from), Some(15));
+        assert_eq!(BusNumber::new(16), None);
+        assert_eq!(BusNumber::new(0x103), None);
+
+        const BUS: BusNumber = BusNumber::new_unchecked(13);
+        assert_eq!(BUS, BusNumber::default());
+    }
 }
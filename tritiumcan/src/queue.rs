@@ -0,0 +1,145 @@
+//! Fixed capacity frame queue.
+
+use crate::datagram::{Frame, FrameBitfield, FRAME_LEN};
+
+/// First in first out ring buffer of up to `N` frames, backed by an array.
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub struct FrameQueue<const N: usize> {
+    frames: [Frame; N],
+    head: usize,
+    len: usize,
+}
+
+impl<const N: usize> FrameQueue<N> {
+    const EMPTY: Frame = FrameBitfield([0; FRAME_LEN]);
+
+    /// Create an empty queue.
+    pub const fn new() -> Self {
+        Self {
+            frames: [Self::EMPTY; N],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    /// Add `frame` to the back of the queue, handing it back if the queue
+    /// is full.
+    pub fn push(&mut self, frame: Frame) -> Result<(), Frame> {
+        if self.is_full() {
+            return Err(frame);
+        }
+
+        self.frames[(self.head + self.len) % N] = frame;
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Remove the frame at the front of the queue.
+    pub fn pop(&mut self) -> Option<Frame> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let frame = core::mem::take(&mut self.frames[self.head]);
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        Some(frame)
+    }
+
+    /// The frame at the front of the queue.
+    pub fn front(&self) -> Option<&Frame> {
+        (!self.is_empty()).then(|| &self.frames[self.head])
+    }
+
+    /// Remove every frame.
+    pub fn clear(&mut self) {
+        self.head = 0;
+        self.len = 0;
+    }
+
+    /// Number of frames in the queue.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the queue holds no frames.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Whether the queue holds `N` frames.
+    pub fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    /// Maximum number of frames, `N`.
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+}
+
+impl<const N: usize> Default for FrameQueue<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_can::StandardId;
+
+    fn frame(id: u16) -> Frame {
+        Frame::new(StandardId::new(id).unwrap(), &[id as u8]).unwrap()
+    }
+
+    #[test]
+    fn empty_and_full() {
+        let mut queue = FrameQueue::<2>::new();
+        assert!(queue.is_empty());
+        assert_eq!(queue.pop(), None);
+        assert_eq!(queue.front(), None);
+
+        queue.push(frame(1)).unwrap();
+        queue.push(frame(2)).unwrap();
+        assert!(queue.is_full());
+        assert_eq!(queue.push(frame(3)), Err(frame(3)));
+        assert_eq!(queue.len(), 2);
+
+        queue.clear();
+        assert!(queue.is_empty());
+        assert_eq!(queue.pop(), None);
+
+        let mut queue = FrameQueue::<0>::new();
+        assert!(queue.is_empty() && queue.is_full());
+        assert_eq!(queue.push(frame(1)), Err(frame(1)));
+        assert_eq!(queue.pop(), None);
+    }
+
+    #[test]
+    fn wraparound() {
+        let mut queue = FrameQueue::<3>::new();
+
+        // head walks around the array several times
+        for id in 0..10 {
+            queue.push(frame(id)).unwrap();
+            queue.push(frame(id + 100)).unwrap();
+            assert_eq!(queue.front(), Some(&frame(id)));
+            assert_eq!(queue.pop(), Some(frame(id)));
+            assert_eq!(queue.pop(), Some(frame(id + 100)));
+            assert!(queue.is_empty());
+        }
+
+        for id in 0..3 {
+            queue.push(frame(id)).unwrap();
+        }
+        assert_eq!(queue.pop(), Some(frame(0)));
+        queue.push(frame(3)).unwrap();
+        assert!(queue.is_full());
+        for id in 1..4 {
+            assert_eq!(queue.pop(), Some(frame(id)));
+        }
+        assert_eq!(queue.pop(), None);
+    }
+}